@@ -1,7 +1,28 @@
-use std::{env, fs};
+use std::fs;
+use std::io::{stdout, IsTerminal};
 
-pub fn print_about() {
-    let ascii_art = r#"⠀⠀⠀⠀⠀⣀⣠⣤⣤⣤⣤⣄⣀⠀⠀⠀⠀⠀
+use crate::{
+    config::{home_dir, Config},
+    editor::display_width,
+    json,
+    sysinfo::{format_bytes, format_uptime, SystemInfo},
+};
+
+/// A logo `about` can show next to the system information, for the
+/// systems whose ids are listed.
+struct Logo {
+    name: &'static str,
+    ids: &'static [&'static str],
+    /// The ANSI color of the logo and the labels.
+    color: u8,
+    art: &'static str,
+}
+
+const SHELL_LOGO: Logo = Logo {
+    name: "a-shell",
+    ids: &[],
+    color: 34,
+    art: "⠀⠀⠀⠀⠀⣀⣠⣤⣤⣤⣤⣄⣀⠀⠀⠀⠀⠀
 ⠀⠀⢀⣴⣿⣿⣿⣿⣿⣿⣿⣿⣿⣿⣦⡀⠀⠀
 ⠀⣴⣿⣿⣿⣿⣿⣿⣿⣿⣿⣿⠟⢿⣿⣷⡀⠀
 ⣸⣿⣿⣿⣿⣿⣿⣿⣿⣿⠟⠁⠀⣴⢿⣿⣧⠀
@@ -10,114 +31,282 @@ pub fn print_about() {
 ⢹⣿⣤⠄⠀⠀⠀⠀⠀⠀⠀⠀⢠⣿⣿⣿⣿⡏
 ⠀⠻⡏⠀⠀⠀⠀⠀⠀⠀⠀⠀⢿⣿⣿⣿⠟⠀
 ⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⢻⠟⠁⠀⠀
-⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀"#;
-
-    // Fetch system information
-    let username = env::var("USER").unwrap_or_else(|_| "Unknown".to_string());
-    let hostname = env::var("HOSTNAME").unwrap_or_else(|_| {
-        fs::read_to_string("/etc/hostname")
-            .unwrap_or_else(|_| "Unknown".to_string())
-            .trim()
-            .to_string()
-    });
-    let os = fs::read_to_string("/etc/os-release")
-        .ok()
-        .and_then(|content| {
-            content
-                .lines()
-                .find(|line| line.starts_with("PRETTY_NAME="))
-                .map(|line| line.replace("PRETTY_NAME=", "").replace('"', ""))
-        })
-        .unwrap_or_else(|| "Unknown".to_string());
-    let kernel = fs::read_to_string("/proc/version")
-        .map(|v| v.split_whitespace().nth(2).unwrap_or("Unknown").to_string())
-        .unwrap_or_else(|_| "Unknown".to_string());
-    let uptime = fs::read_to_string("/proc/uptime")
-        .map(|up| {
-            up.split_whitespace()
-                .next()
-                .and_then(|secs| secs.parse::<f64>().ok())
-                .map(|s| format!("{:.2} hours", s / 3600.0))
-                .unwrap_or("Unknown".to_string())
-        })
-        .unwrap_or_else(|_| "Unknown".to_string());
+⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀⠀",
+};
 
-    // RAM Information (Total)
-    let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
-    let total_ram = meminfo
-        .lines()
-        .find(|line| line.starts_with("MemTotal:"))
-        .and_then(|line| line.split_whitespace().nth(1))
-        .and_then(|kb| kb.parse::<u64>().ok())
-        .map(|kb| format!("{:.2} GB", kb as f64 / (1024.0 * 1024.0)))
-        .unwrap_or_else(|| "Unknown".to_string());
+/// Checked in order, so more specific systems come before those they are
+/// like.
+const LOGOS: &[Logo] = &[
+    Logo {
+        name: "ubuntu",
+        ids: &["ubuntu"],
+        color: 31,
+        art: r"         _
+     ---(_)
+ _/  ---  \
+(_) |   |
+  \  --- _/
+     ---(_)",
+    },
+    Logo {
+        name: "debian",
+        ids: &["debian"],
+        color: 31,
+        art: r"   _____
+  /  __ \
+ |  /    |
+ |  \___-
+ -_
+   --_",
+    },
+    Logo {
+        name: "fedora",
+        ids: &["fedora", "rhel", "centos"],
+        color: 34,
+        art: r"      _____
+     /   __)\
+     |  /  \ \
+  ___|  |__/ /
+ / (_    _)_/
+/ /  |  |
+\ \__/  |
+ \(_____/",
+    },
+    Logo {
+        name: "arch",
+        ids: &["arch"],
+        color: 36,
+        art: r"       /\
+      /  \
+     /\   \
+    /      \
+   /   ,,   \
+  /   |  |  -\
+ /_-''    ''-_\",
+    },
+    Logo {
+        name: "macos",
+        ids: &["macos"],
+        color: 32,
+        art: r"        .:'
+    __ :'__
+ .'`  `-'  ``.
+:          .-'
+:         :
+ :         `-;
+  `.__.-.__.'",
+    },
+    Logo {
+        name: "linux",
+        ids: &["linux"],
+        color: 33,
+        art: r"    .--.
+   |o_o |
+   |:_/ |
+  //   \ \
+ (|     | )
+/'\_   _/`\
+\___)=(___/",
+    },
+];
 
-    // CPU Model
-    let cpu_model = fs::read_to_string("/proc/cpuinfo")
-        .unwrap_or_default()
-        .lines()
-        .find(|line| line.starts_with("model name"))
-        .map(|line| {
-            line.split(':')
-                .nth(1)
-                .unwrap_or("Unknown")
-                .trim()
-                .to_string()
-        })
-        .unwrap_or_else(|| "Unknown".to_string());
-
-    let sh = env::var("0").unwrap_or_else(|_| {
-        fs::read_to_string("/etc/passwd")
-            .unwrap_or_default()
-            .lines()
-            .find(|line| line.contains(&username))
-            .map(|line| {
-                line.split(":")
-                    .last()
-                    .unwrap_or("Unknown")
-                    .split("/")
-                    .last()
-                    .unwrap_or("Unknown")
-                    .to_string()
-            })
-            .unwrap_or_else(|| "Unknown".to_string())
-    });
+/// The `about` builtin: a summary of the system next to a logo, set from
+/// `[about]` in the config file:
+///
+/// ```toml
+/// [about]
+/// # `auto` picks one for the system, the default; also `none`, a name
+/// # like `arch`, `debian`, `fedora`, `ubuntu`, `macos`, `linux` or
+/// # `a-shell`, or the path of a file with one.
+/// logo = "auto"
+/// ```
+///
+/// `about --json` prints the information as JSON instead, with sizes in
+/// bytes and the uptime in seconds.
+pub struct About {
+    logo: String,
+}
 
-    // Collect system info
-    let system_info = vec![
-        format!("User:    {}", username),
-        format!("Host:    {}", hostname),
-        format!("OS:      {}", os),
-        format!("Kernel:  {}", kernel),
-        format!("Uptime:  {}", uptime),
-        format!("RAM:     {}", total_ram),
-        format!("CPU:     {}", cpu_model),
-        format!("Shell:   {}", sh),
-    ];
+impl About {
+    pub fn from_config(config: &Config) -> Self {
+        About {
+            logo: config
+                .get_str("about", "logo")
+                .unwrap_or("auto")
+                .to_string(),
+        }
+    }
 
-    // Print ASCII art and information side-by-side
-    let art_lines: Vec<&str> = ascii_art.lines().collect();
-    let info_lines = system_info;
+    pub fn print(&self, json: bool) {
+        let info = SystemInfo::collect();
+        if json {
+            println!("{}", to_json(&info));
+            return;
+        }
+        let color = stdout().is_terminal();
+        let (art, code) = self.logo(&info);
+        let paint = |text: &str, sgr: &str| match color {
+            true => format!("\x1b[{}m{}\x1b[0m", sgr, text),
+            false => text.to_string(),
+        };
 
-    let max_art_width = art_lines.iter().map(|line| line.len()).max().unwrap_or(0) + 5;
+        let title = format!("{}@{}", info.user, info.host);
+        let mut lines = vec![
+            paint(&title, &format!("1;{}", code)),
+            "-".repeat(title.chars().count()),
+        ];
+        for (label, value) in fields(&info) {
+            lines.push(format!(
+                "{}: {}",
+                paint(label, &format!("1;{}", code)),
+                value
+            ));
+        }
+        if color {
+            lines.push(String::new());
+            for bright in [false, true] {
+                let blocks = (0..8)
+                    .map(|n| format!("\x1b[{}m   ", if bright { 100 } else { 40 } + n))
+                    .collect::<String>();
+                lines.push(format!("{}\x1b[0m", blocks));
+            }
+        }
 
-    for (i, art_line) in art_lines.iter().enumerate() {
-        print!("{}", art_line);
-        if i < info_lines.len() {
-            print!(
-                "{:width$}{}",
-                "",
-                info_lines[i],
-                width = max_art_width - art_line.len()
+        let art = art.lines().collect::<Vec<_>>();
+        let art_width = art
+            .iter()
+            .map(|line| display_width(line) as usize)
+            .max()
+            .unwrap_or(0);
+        let gap = if art_width > 0 { 3 } else { 0 };
+        for i in 0..art.len().max(lines.len()) {
+            let art_line = art.get(i).copied().unwrap_or_default();
+            let padding = art_width - display_width(art_line) as usize + gap;
+            let line = lines.get(i).map(String::as_str).unwrap_or_default();
+            let row = format!(
+                "{}{}{}",
+                if art_line.is_empty() {
+                    String::new()
+                } else {
+                    paint(art_line, &code.to_string())
+                },
+                " ".repeat(padding),
+                line
             );
+            println!("{}", row.trim_end());
         }
-        println!();
     }
 
-    // Print remaining info lines if any
-    if art_lines.len() < info_lines.len() {
-        for line in info_lines.iter().skip(art_lines.len()) {
-            println!("{:width$}{}", "", line, width = max_art_width);
+    /// The art of the logo to show and its color.
+    fn logo(&self, info: &SystemInfo) -> (String, u8) {
+        match self.logo.as_str() {
+            "none" => (String::new(), SHELL_LOGO.color),
+            "auto" => {
+                let logo = info
+                    .os_ids
+                    .iter()
+                    .find_map(|id| LOGOS.iter().find(|logo| logo.ids.contains(&id.as_str())))
+                    .unwrap_or(&SHELL_LOGO);
+                (logo.art.to_string(), logo.color)
+            }
+            name => match LOGOS.iter().chain([&SHELL_LOGO]).find(|l| l.name == name) {
+                Some(logo) => (logo.art.to_string(), logo.color),
+                None => {
+                    let path = match name.strip_prefix("~/") {
+                        Some(rest) => home_dir().join(rest),
+                        None => name.into(),
+                    };
+                    match fs::read_to_string(&path) {
+                        Ok(art) => (art, SHELL_LOGO.color),
+                        Err(e) => {
+                            eprintln!("Cannot read logo {}: {}", path.display(), e);
+                            (SHELL_LOGO.art.to_string(), SHELL_LOGO.color)
+                        }
+                    }
+                }
+            },
         }
     }
 }
+
+/// The labelled lines of what is known.
+fn fields(info: &SystemInfo) -> Vec<(&'static str, String)> {
+    let usage = |(used, total): (u64, u64)| {
+        let percent = (used as f64 / total.max(1) as f64 * 100.0).round();
+        format!(
+            "{} / {} ({}%)",
+            format_bytes(used),
+            format_bytes(total),
+            percent
+        )
+    };
+    let packages = info
+        .packages
+        .iter()
+        .map(|(manager, count)| format!("{} ({})", count, manager))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut fields = vec![
+        ("OS", info.os.clone()),
+        ("Kernel", info.kernel.clone()),
+        ("Uptime", info.uptime.map(format_uptime)),
+        ("Packages", Some(packages).filter(|p| !p.is_empty())),
+        ("Shell", Some(info.shell.clone())),
+        ("Terminal", info.terminal.clone()),
+        ("Resolution", info.resolution.clone()),
+        ("CPU", info.cpu.clone()),
+    ];
+    fields.extend(info.gpus.iter().map(|gpu| ("GPU", Some(gpu.clone()))));
+    fields.push(("Memory", info.memory.map(usage)));
+    fields.push(("Disk (/)", info.disk.map(usage)));
+    fields
+        .into_iter()
+        .filter_map(|(label, value)| Some((label, value?)))
+        .collect()
+}
+
+fn to_json(info: &SystemInfo) -> String {
+    let string = |value: &Option<String>| value.as_deref().map_or("null".to_string(), json::quote);
+    let usage = |usage: Option<(u64, u64)>| {
+        usage.map_or("null".to_string(), |(used, total)| {
+            format!("{{\"used\": {}, \"total\": {}}}", used, total)
+        })
+    };
+    let packages = info
+        .packages
+        .iter()
+        .map(|(manager, count)| format!("{}: {}", json::quote(manager), count))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let gpus = info
+        .gpus
+        .iter()
+        .map(|gpu| json::quote(gpu))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let fields = [
+        ("user", json::quote(&info.user)),
+        ("host", json::quote(&info.host)),
+        ("os", string(&info.os)),
+        ("kernel", string(&info.kernel)),
+        (
+            "uptime_secs",
+            info.uptime
+                .map_or("null".to_string(), |u| u.as_secs().to_string()),
+        ),
+        ("packages", format!("{{{}}}", packages)),
+        ("shell", json::quote(&info.shell)),
+        ("terminal", string(&info.terminal)),
+        ("resolution", string(&info.resolution)),
+        ("cpu", string(&info.cpu)),
+        ("gpus", format!("[{}]", gpus)),
+        ("memory", usage(info.memory)),
+        ("disk", usage(info.disk)),
+    ];
+    let body = fields
+        .iter()
+        .map(|(key, value)| format!("  {}: {}", json::quote(key), value))
+        .collect::<Vec<_>>()
+        .join(",\n");
+    format!("{{\n{}\n}}", body)
+}
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+
+use crate::config::Config;
+
+/// Command aliases, seeded from the `[aliases]` table of the config file
+/// and changed at runtime with `alias` and `unalias`.
+#[derive(Clone)]
+pub struct Aliases {
+    map: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    pub fn from_config(config: &Config) -> Self {
+        let map = config
+            .section("aliases")
+            .map(|table| {
+                table
+                    .iter()
+                    .filter_map(|(name, value)| Some((name.clone(), value.as_str()?.to_string())))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Aliases { map }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.map.get(name).map(String::as_str)
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.map.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.map.remove(name).is_some()
+    }
+
+    /// Names and values, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.map.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Replaces an aliased first word of `command_line` with its value. An
+    /// alias is not expanded again inside its own value, so `ls = "ls -F"`
+    /// works.
+    pub fn expand(&self, command_line: &str) -> String {
+        let mut line = command_line.to_string();
+        let mut expanded = vec![];
+        loop {
+            let trimmed = line.trim_start();
+            let word = trimmed.split_whitespace().next().unwrap_or("");
+            match self.get(word) {
+                Some(value) if !expanded.contains(&word.to_string()) => {
+                    expanded.push(word.to_string());
+                    line = format!("{}{}", value, &trimmed[word.len()..]);
+                }
+                _ => return line,
+            }
+        }
+    }
+}
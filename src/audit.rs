@@ -0,0 +1,109 @@
+use std::env;
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use crate::{
+    clock,
+    config::{home_dir, Config},
+    json,
+    paths::{self, File},
+    prompt::{hostname, user},
+};
+
+/// A record of every command line run, for shared servers that need one,
+/// set from `[audit]` in the config file:
+///
+/// ```toml
+/// [audit]
+/// enabled = true                      # off by default
+/// file = "/var/log/a-shell/audit.log" # `audit.log` in the state directory by default
+/// syslog = true                       # log to syslog instead of the file
+/// ```
+///
+/// Each command line is one JSON object: when it finished in UTC, the
+/// user, host, shell process, working directory, the line, its exit code
+/// and how many milliseconds it ran. Lines run in private mode are logged
+/// too, and so are those of scripts and the rc file.
+pub struct Audit {
+    target: Option<Target>,
+}
+
+enum Target {
+    File(PathBuf),
+    Syslog,
+}
+
+impl Audit {
+    pub fn from_config(config: &Config) -> Self {
+        let target = match (
+            config.get_bool("audit", "enabled").unwrap_or(false),
+            config.get_bool("audit", "syslog").unwrap_or(false),
+        ) {
+            (false, _) => None,
+            (true, true) => {
+                // SAFETY: the identifier is a static C string, which syslog
+                // keeps a pointer to.
+                unsafe { libc::openlog(c"a-shell".as_ptr(), libc::LOG_PID, libc::LOG_USER) };
+                Some(Target::Syslog)
+            }
+            (true, false) => Some(Target::File(
+                config
+                    .get_str("audit", "file")
+                    .map(|file| match file.strip_prefix("~/") {
+                        Some(rest) => home_dir().join(rest),
+                        None => PathBuf::from(file),
+                    })
+                    .unwrap_or_else(|| paths::path(File::Audit)),
+            )),
+        };
+        Audit { target }
+    }
+
+    /// Logs that `line` ran for `duration` and exited with `exit_code`.
+    pub fn record(&self, line: &str, exit_code: i32, duration: Duration) {
+        let Some(target) = &self.target else {
+            return;
+        };
+        if line.trim().is_empty() {
+            return;
+        }
+        let cwd = env::current_dir().unwrap_or_default();
+        let entry = format!(
+            "{{\"time\":{},\"user\":{},\"host\":{},\"pid\":{},\"cwd\":{},\"command\":{},\"exit\":{},\"duration_ms\":{}}}",
+            json::quote(&clock::format_utc(clock::now())),
+            json::quote(&user()),
+            json::quote(&hostname()),
+            process::id(),
+            json::quote(&cwd.to_string_lossy()),
+            json::quote(line),
+            exit_code,
+            duration.as_millis()
+        );
+        let written = match target {
+            Target::File(path) => append(path, &entry),
+            Target::Syslog => {
+                if let Ok(entry) = CString::new(entry) {
+                    // SAFETY: both strings are NUL-terminated and outlive the
+                    // call, and the format takes exactly the one argument.
+                    unsafe { libc::syslog(libc::LOG_INFO, c"%s".as_ptr(), entry.as_ptr()) };
+                }
+                Ok(())
+            }
+        };
+        if let Err(e) = written {
+            eprintln!("Cannot write audit log: {}", e);
+        }
+    }
+}
+
+fn append(path: &Path, entry: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", entry)
+}
@@ -1,117 +1,285 @@
-use crossterm::terminal;
+use std::collections::HashSet;
 
-use crate::parser::CommandParser;
-
-use std::error::Error;
-use std::fs::{self};
-use std::io::{self};
+use crate::{
+    completer::{
+        same_char, BashCompleter, CargoCompleter, CommandCompleter, Completer, FileCompleter,
+        FlagCompleter, HistoryCompleter, HostCompleter, Matcher, ProcessCompleter,
+        RecentDirectoryCompleter, ScriptCompleter, Sources, Tier, UserCompleter, VariableCompleter,
+    },
+    completion_context::{escape, CompletionContext},
+    config::Config,
+    script::Scripts,
+    theme::{Role, Theme},
+};
 
 pub struct Suggestion {
-    file_name: String,
-    is_dir: bool,
+    pub file_name: String,
+    pub kind: Kind,
+    /// SGR parameters to draw the name with in the listing.
+    pub style: Option<String>,
+    /// Shown dimmed after the name in the listing, not inserted.
+    pub description: Option<String>,
 }
 
-pub struct AutoComplete {}
-
-impl AutoComplete {
-    pub fn new() -> Self {
-        return AutoComplete {};
+impl Suggestion {
+    pub fn new(file_name: String, kind: Kind) -> Self {
+        Suggestion {
+            file_name,
+            kind,
+            style: None,
+            description: None,
+        }
     }
+}
 
-    pub fn autocomplete(
-        &self,
-        command: &str,
-        parser: &CommandParser,
-    ) -> Result<String, Box<dyn Error>> {
-        let mut new_value = String::from(command);
-        let parsed_command = parser.parse(command);
-        let searched_file = parsed_command.paths.last().map_or("", |s| s.as_str());
-        let in_path =
-            parsed_command.paths[..parsed_command.paths.len().saturating_sub(1)].join("/");
-
-        let mut entries = fs::read_dir(&in_path)?
-            .map(|res| res.map(|e| e.path()))
-            .collect::<Result<Vec<_>, io::Error>>()?;
-        entries.sort();
-
-        if parsed_command.command == "cd" {
-            entries = entries.into_iter().filter(|f| f.is_dir()).collect();
-        }
+/// What a candidate is, which decides how it goes on the line.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    File,
+    Directory,
+    Command,
+    Builtin,
+    Alias,
+    Variable,
+    /// A whole word, such as one from the history.
+    Word,
+    /// A remote host, followed by `:` where a path comes next.
+    Host,
+}
 
-        let terminal_width = terminal::size()?.0 as usize;
+pub struct AutoComplete {
+    matcher: Matcher,
+    /// Asked in order; earlier ones win when candidates have the same name.
+    completers: Vec<Box<dyn Completer>>,
+    /// Group directories before other candidates in the listing.
+    directories_first: bool,
+    /// List candidates by name rather than in the order they were found.
+    sort: bool,
+}
 
-        let mut matching_file_names: Vec<Suggestion> = vec![];
+impl AutoComplete {
+    pub fn from_config(config: &Config, scripts: &Scripts) -> Self {
+        let mut autocomplete = AutoComplete {
+            matcher: Matcher::from_config(config),
+            completers: vec![],
+            directories_first: config
+                .get_bool("completion", "directories_first")
+                .unwrap_or(true),
+            sort: config.get_bool("completion", "sort").unwrap_or(true),
+        };
+        autocomplete.register(Box::new(VariableCompleter));
+        autocomplete.register(Box::new(CommandCompleter::new()));
+        autocomplete.register(Box::new(UserCompleter::from_config(config)));
+        autocomplete.register(Box::new(ScriptCompleter::new(scripts)));
+        autocomplete.register(Box::new(BashCompleter::from_config(config)));
+        autocomplete.register(Box::new(CargoCompleter::new()));
+        autocomplete.register(Box::new(HostCompleter));
+        autocomplete.register(Box::new(ProcessCompleter));
+        autocomplete.register(Box::new(FlagCompleter::from_config(config)));
+        autocomplete.register(Box::new(FileCompleter::from_config(config)));
+        autocomplete.register(Box::new(RecentDirectoryCompleter));
+        autocomplete.register(Box::new(HistoryCompleter));
+        autocomplete
+    }
 
-        for (_i, entry) in entries.iter().enumerate() {
-            let file_name = entry.file_name().unwrap().to_string_lossy().to_string();
-            if searched_file.len() == 0 || file_name.starts_with(&searched_file) {
-                matching_file_names.push(Suggestion {
-                    file_name: file_name.clone(),
-                    is_dir: entry.is_dir(),
-                });
+    /// Adds a source of candidates, asked after those registered before it.
+    pub fn register(&mut self, completer: Box<dyn Completer>) {
+        self.completers.push(completer);
+    }
+
+    /// Completes the word at the end of `line`, the text before the cursor,
+    /// from the first tier of completers that has candidates for it, merged
+    /// and without duplicate names. Returns the new
+    /// line and, when the match is ambiguous, the candidates to list below
+    /// the prompt.
+    pub fn autocomplete(&mut self, line: &str, sources: &Sources) -> (String, Vec<Suggestion>) {
+        let mut line = line.to_string();
+        let mut context = CompletionContext::new(&line);
+        for completer in &self.completers {
+            if let Some(expanded) = completer.expand(&line, &context, &self.matcher) {
+                line = expanded;
+                context = CompletionContext::new(&line);
             }
         }
 
-        if matching_file_names.len() > 1 {
-            let longest_match = self.get_longest_match(&matching_file_names, searched_file);
+        let mut matching = vec![];
+        for tier in [Tier::Specific, Tier::General, Tier::Fallback] {
+            for completer in self.completers.iter_mut().filter(|c| c.tier() == tier) {
+                matching.extend(completer.complete(&context, &self.matcher, sources));
+            }
+            if !matching.is_empty() {
+                break;
+            }
+        }
+        let mut seen = HashSet::new();
+        matching.retain(|suggestion| seen.insert(suggestion.file_name.clone()));
+        if self.sort {
+            matching.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        }
+        if self.directories_first {
+            matching.sort_by_key(|suggestion| suggestion.kind != Kind::Directory);
+        }
 
-            if longest_match.len() > searched_file.len() {
-                new_value = command.replace(&searched_file, &format!("{}", longest_match));
-            } else {
-                let max_width = entries
+        match matching.len() {
+            0 => (line, vec![]),
+            1 => (apply(&line, &matching[0]), vec![]),
+            _ => {
+                // Like bash, extend the word as far as all candidates agree,
+                // but list them right away. Candidates that replace different
+                // parts of the word have nothing to agree on.
+                let (start, fragment, quote) = replaced_part(&line, &context, matching[0].kind);
+                let same_part = matching
                     .iter()
-                    .map(|entry| entry.file_name().unwrap().to_string_lossy().len())
-                    .max()
-                    .unwrap_or(0);
-                let columns = terminal_width / (max_width + 2); // Add 4 for padding
-                println!("");
-
-                for (i, suggestion) in matching_file_names.iter().enumerate() {
-                    print!("{:<width$}", suggestion.file_name, width = max_width);
-                    if (i + 1) % columns == 0 {
-                        println!();
-                    }
-                }
-
-                // Ensure we end with a new line
-                if entries.len() % columns != 0 {
-                    println!();
+                    .all(|s| replaced_part(&line, &context, s.kind).0 == start);
+                let ignore_case = self.matcher.ignore_case(fragment);
+                let longest_match = self.get_longest_match(&matching, fragment, ignore_case);
+                if same_part && longest_match.chars().count() > fragment.chars().count() {
+                    line = format!("{}{}", &line[..start], escape(&longest_match, quote));
                 }
+                (line, matching)
             }
-        } else if matching_file_names.len() == 1 {
-            let matched = matching_file_names.first().unwrap();
-            new_value = command.replace(
-                &searched_file,
-                &format!(
-                    "{}{}",
-                    matched.file_name,
-                    if matched.is_dir { "/" } else { "" }
-                ),
-            );
         }
-        Ok(new_value)
     }
 
-    fn get_longest_match(&self, entries: &Vec<Suggestion>, search: &str) -> String {
-        let mut longest_match = String::from(search);
-        let mut len = longest_match.len();
-        let first_entry = entries.first().unwrap().to_owned();
-        loop {
-            len += 1;
-            let mut success = true;
-            let (trying_match, _) = first_entry.file_name.split_at(len);
-            for entry in entries {
-                if !entry.file_name.starts_with(trying_match) {
-                    success = false;
-                    break;
-                }
-            }
-            if success {
-                longest_match = String::from(trying_match);
-            } else {
-                break;
-            }
+    /// The longest prefix all `entries` share, spelled as in the first.
+    fn get_longest_match(&self, entries: &[Suggestion], search: &str, ignore_case: bool) -> String {
+        let Some(first_entry) = entries.first() else {
+            return String::from(search);
+        };
+        let mut longest_match = first_entry.file_name.as_str();
+        for entry in &entries[1..] {
+            let common = longest_match
+                .char_indices()
+                .zip(entry.file_name.chars())
+                .find(|((_, a), b)| !same_char(*a, *b, ignore_case))
+                .map_or(
+                    longest_match.len().min(entry.file_name.len()),
+                    |((i, _), _)| i,
+                );
+            longest_match = &longest_match[..common];
         }
-        longest_match
+        String::from(longest_match)
     }
 }
+
+/// Completes `line`, the text before the cursor, with `suggestion` in
+/// place of the partial word. Command names and words are followed by a
+/// space, hosts by a space or `:`, braced variables by `}` and files by the
+/// closing quote if one was left open.
+pub fn apply(line: &str, suggestion: &Suggestion) -> String {
+    let context = CompletionContext::new(line);
+    let (start, _, quote) = replaced_part(line, &context, suggestion.kind);
+    let suffix = match suggestion.kind {
+        Kind::Variable if context.variable.as_ref().is_some_and(|(_, braced)| *braced) => "}",
+        Kind::Variable => "",
+        Kind::Directory if suggestion.file_name.ends_with('/') => "",
+        Kind::Directory => "/",
+        Kind::Command | Kind::Builtin | Kind::Alias | Kind::Word => " ",
+        Kind::Host => match context.words.first().map(String::as_str) {
+            Some("scp" | "rsync") => ":",
+            _ => " ",
+        },
+        Kind::File => match quote {
+            Some('"') => "\"",
+            Some(_) => "'",
+            None => "",
+        },
+    };
+    format!(
+        "{}{}{}",
+        &line[..start],
+        escape(&suggestion.file_name, quote),
+        suffix
+    )
+}
+
+/// Where a candidate of `kind` goes on the line: the byte offset it
+/// replaces from, the typed text it replaces with quotes and escapes
+/// removed, and the quote to write it in.
+fn replaced_part<'a>(
+    line: &str,
+    context: &'a CompletionContext,
+    kind: Kind,
+) -> (usize, &'a str, Option<char>) {
+    match (kind, &context.variable) {
+        (Kind::Variable, Some((name, _))) => (line.len() - name.len(), name.as_str(), None),
+        (Kind::Word | Kind::Host, _) => (context.word_start, context.word.as_str(), None),
+        _ => (
+            context.segment_start,
+            context.segment.as_str(),
+            context.quote,
+        ),
+    }
+}
+
+/// Lays the candidates out in as many columns as fit in `width`, each in
+/// its style and the `selected` one in the theme's selection style.
+pub fn format_grid(
+    suggestions: &[Suggestion],
+    width: usize,
+    selected: Option<usize>,
+    theme: &Theme,
+) -> Vec<String> {
+    let Some(max_width) = suggestions.iter().map(cell_width).max() else {
+        return vec![];
+    };
+    let columns = (width / (max_width + 2)).max(1);
+    suggestions
+        .chunks(columns)
+        .enumerate()
+        .map(|(row_index, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(column, s)| {
+                    let padding = " ".repeat(max_width + 2 - cell_width(s));
+                    let description = s
+                        .description
+                        .as_ref()
+                        .map(|d| format!("  {}", theme.paint(Role::Description, d)))
+                        .unwrap_or_default();
+                    let mut style = s.style.clone().unwrap_or_default();
+                    let selection = theme.style(Role::Selection);
+                    if selected == Some(row_index * columns + column) && !selection.is_empty() {
+                        if !style.is_empty() {
+                            style.push(';');
+                        }
+                        style.push_str(selection);
+                    }
+                    if style.is_empty() {
+                        format!("{}{}{}", s.file_name, description, padding)
+                    } else {
+                        format!(
+                            "\x1b[{}m{}\x1b[0m{}{}",
+                            style, s.file_name, description, padding
+                        )
+                    }
+                })
+                .collect::<String>()
+                .trim_end()
+                .to_string()
+        })
+        .collect()
+}
+
+/// Index of the candidate drawn at `row`/`column` of the grid.
+pub fn grid_entry_at(
+    suggestions: &[Suggestion],
+    width: usize,
+    row: usize,
+    column: usize,
+) -> Option<usize> {
+    let max_width = suggestions.iter().map(cell_width).max()?;
+    let columns = (width / (max_width + 2)).max(1);
+    let grid_column = column / (max_width + 2);
+    let index = row * columns + grid_column;
+    (grid_column < columns && index < suggestions.len()).then_some(index)
+}
+
+/// Columns a candidate takes in the grid, description included.
+fn cell_width(suggestion: &Suggestion) -> usize {
+    suggestion.file_name.chars().count()
+        + suggestion
+            .description
+            .as_ref()
+            .map_or(0, |d| d.chars().count() + 2)
+}
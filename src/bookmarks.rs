@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::PathBuf;
+
+use crate::config::{home_dir, Config};
+
+/// Named directories from the `[bookmarks]` table of the config file, which
+/// `cd` accepts as the first part of a path, e.g. `cd proj/src`.
+#[derive(Clone)]
+pub struct Bookmarks {
+    map: BTreeMap<String, PathBuf>,
+}
+
+impl Bookmarks {
+    pub fn from_config(config: &Config) -> Self {
+        let map = config
+            .section("bookmarks")
+            .map(|table| {
+                table
+                    .iter()
+                    .filter_map(|(name, path)| {
+                        let path = path.as_str()?;
+                        let path = match path.strip_prefix("~/") {
+                            Some(rest) => home_dir().join(rest),
+                            None => PathBuf::from(path),
+                        };
+                        Some((name.clone(), path))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Bookmarks { map }
+    }
+
+    /// Names and directories, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &PathBuf)> {
+        self.map.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Where else `cd` looks for a relative `path` that is not below the
+    /// working directory: under each `$CDPATH` entry, then in the bookmark
+    /// its first part names. Each comes with where it was found.
+    pub fn alternatives(&self, path: &str) -> Vec<(PathBuf, String)> {
+        if path.starts_with(['/', '~', '$'])
+            || path == "."
+            || path == ".."
+            || path.starts_with("./")
+            || path.starts_with("../")
+        {
+            return vec![];
+        }
+        let cdpath = env::var("CDPATH").unwrap_or_default();
+        let mut alternatives = cdpath
+            .split(':')
+            .filter(|dir| !dir.is_empty() && *dir != ".")
+            .map(|dir| (PathBuf::from(dir).join(path), dir.to_string()))
+            .collect::<Vec<_>>();
+        let (name, rest) = path.split_once('/').unwrap_or((path, ""));
+        if let Some(bookmark) = self.map.get(name) {
+            alternatives.push((bookmark.join(rest), format!("bookmark {}", name)));
+        }
+        alternatives
+    }
+}
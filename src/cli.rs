@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: a-shell [options] [script]
+
+Options:
+  -c <command>         run the command and exit
+  -i                   read the rc file even when running a command or script
+  -l, --login          run as a login shell
+      --norc           do not read the rc file
+      --config <path>  read the config from <path>
+      --check-config   report problems in the config and rc files and exit
+  -h, --help           show this help and exit
+  -V, --version        show the version and exit
+
+Without -c or a script the shell reads commands interactively.
+
+The config and rc files are $XDG_CONFIG_HOME/a-shell/config.toml and
+$XDG_CONFIG_HOME/a-shell/rc, ~/.config/a-shell by default.
+";
+
+/// What the shell was asked to do on the command line.
+pub enum Invocation {
+    Run(Options),
+    Help,
+    Version,
+}
+
+pub struct Options {
+    /// The command given with `-c`.
+    pub command: Option<String>,
+    /// The file of commands to run instead of reading them interactively.
+    pub script: Option<PathBuf>,
+    /// Whether to read the rc file, as an interactive shell does.
+    pub interactive: bool,
+    pub login: bool,
+    pub norc: bool,
+    pub config: Option<PathBuf>,
+    pub check_config: bool,
+}
+
+/// Parses the arguments the shell was started with, its name first. Short
+/// options can be grouped as in `-lc command`.
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Invocation, String> {
+    let name = args.next().unwrap_or_default();
+    let mut options = Options {
+        command: None,
+        script: None,
+        interactive: false,
+        // `login` and the like start login shells as `-a-shell`.
+        login: name.starts_with('-'),
+        norc: false,
+        config: None,
+        check_config: false,
+    };
+    let mut forced_interactive = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" => return Ok(Invocation::Help),
+            "--version" => return Ok(Invocation::Version),
+            "--login" => options.login = true,
+            "--norc" => options.norc = true,
+            "--check-config" => options.check_config = true,
+            "--config" => {
+                let path = args.next().ok_or("--config needs a path")?;
+                options.config = Some(PathBuf::from(path));
+            }
+            "--" => {
+                options.script = args.next().map(PathBuf::from);
+                break;
+            }
+            long if long.starts_with("--") => return Err(format!("{}: unknown option", long)),
+            short if short.starts_with('-') && short.len() > 1 => {
+                for flag in short.chars().skip(1) {
+                    match flag {
+                        'h' => return Ok(Invocation::Help),
+                        'V' => return Ok(Invocation::Version),
+                        'i' => forced_interactive = true,
+                        'l' => options.login = true,
+                        'c' => {
+                            let command = args.next().ok_or("-c needs a command")?;
+                            options.command = Some(command);
+                        }
+                        _ => return Err(format!("-{}: unknown option", flag)),
+                    }
+                }
+            }
+            _ if options.command.is_some() => {
+                return Err(format!("{}: arguments to commands are not supported", arg));
+            }
+            _ => {
+                options.script = Some(PathBuf::from(arg));
+                break;
+            }
+        }
+    }
+    if let Some(extra) = args.next() {
+        return Err(format!("{}: arguments to scripts are not supported", extra));
+    }
+    options.interactive =
+        forced_interactive || (options.command.is_none() && options.script.is_none());
+    Ok(Invocation::Run(options))
+}
@@ -0,0 +1,114 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use crate::config::Config;
+
+const BASE64_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Tried in order; the first one that runs successfully wins.
+const COPY_COMMANDS: [&[&str]; 4] = [
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard"],
+    &["xsel", "--clipboard", "--input"],
+    &["pbcopy"],
+];
+const PASTE_COMMANDS: [&[&str]; 4] = [
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-o"],
+    &["xsel", "--clipboard", "--output"],
+    &["pbpaste"],
+];
+
+pub enum Clipboard {
+    Off,
+    /// Copy through the terminal with OSC 52, which also works over SSH.
+    /// Terminals rarely allow reading the clipboard back, so pasting is
+    /// left to the terminal itself.
+    Osc52,
+    /// Copy and paste through the desktop's clipboard tools.
+    System,
+}
+
+impl Clipboard {
+    pub fn from_config(config: &Config) -> Self {
+        match config.get_str("editor", "clipboard") {
+            Some("osc52") => Clipboard::Osc52,
+            Some("system") => Clipboard::System,
+            _ => Clipboard::Off,
+        }
+    }
+
+    pub fn copy(&self, text: &str) {
+        match self {
+            Clipboard::Off => {}
+            Clipboard::Osc52 => {
+                print!("\x1b]52;c;{}\x07", base64_encode(text.as_bytes()));
+                let _ = io::stdout().flush();
+            }
+            Clipboard::System => {
+                for command in COPY_COMMANDS {
+                    if run_with_input(command, text).is_ok() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn paste(&self) -> Option<String> {
+        match self {
+            Clipboard::System => PASTE_COMMANDS.iter().find_map(|command| {
+                let output = Command::new(command[0])
+                    .args(&command[1..])
+                    .stderr(Stdio::null())
+                    .output()
+                    .ok()?;
+                if !output.status.success() {
+                    return None;
+                }
+                // Multi-line clipboard contents would submit the line early.
+                let text = String::from_utf8_lossy(&output.stdout).replace(['\n', '\r'], " ");
+                Some(text.trim_end().to_string())
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn run_with_input(command: &[&str], input: &str) -> io::Result<()> {
+    let mut child = Command::new(command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("{} failed", command[0])))
+    }
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,159 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Formats a duration compactly, e.g. `350ms`, `4.2s` or `3m07s`.
+pub fn format_duration(millis: u64) -> String {
+    match millis {
+        0..=999 => format!("{}ms", millis),
+        1000..=59_999 => format!("{:.1}s", millis as f64 / 1000.0),
+        _ if millis < 3_600_000 => format!("{}m{:02}s", millis / 60_000, millis / 1000 % 60),
+        _ => format!("{}h{:02}m", millis / 3_600_000, millis / 60_000 % 60),
+    }
+}
+
+/// Broken-down local time; `weekday` counts from Sunday as 0.
+pub struct LocalTime {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub weekday: u32,
+}
+
+pub fn local(secs: u64) -> Option<LocalTime> {
+    let time = secs as libc::time_t;
+    // SAFETY: `tm` is plain data and both pointers outlive the call.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return None;
+    }
+    Some(LocalTime {
+        year: tm.tm_year + 1900,
+        month: tm.tm_mon as u32 + 1,
+        day: tm.tm_mday as u32,
+        hour: tm.tm_hour as u32,
+        minute: tm.tm_min as u32,
+        second: tm.tm_sec as u32,
+        weekday: tm.tm_wday as u32,
+    })
+}
+
+/// Formats epoch seconds as an RFC 3339 timestamp in UTC, e.g.
+/// `2024-05-01T09:30:00Z`.
+pub fn format_utc(secs: u64) -> String {
+    let time = secs as libc::time_t;
+    // SAFETY: `tm` is plain data and both pointers outlive the call.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::gmtime_r(&time, &mut tm) }.is_null() {
+        return secs.to_string();
+    }
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// Epoch seconds of local midnight on the given date; out-of-range days
+/// roll over into the neighbouring month.
+pub fn local_midnight(year: i32, month: u32, day: i32) -> Option<u64> {
+    // SAFETY: `tm` is plain data and outlives the call.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = year - 1900;
+    tm.tm_mon = month as i32 - 1;
+    tm.tm_mday = day;
+    tm.tm_isdst = -1;
+    let time = unsafe { libc::mktime(&mut tm) };
+    u64::try_from(time).ok()
+}
+
+/// Parses a point in time relative to `now`: `today`, `yesterday`, a date
+/// like `2024-05-01`, or an age like `2 days ago`, `3h` or `90 minutes`.
+pub fn parse_since(text: &str, now: u64) -> Option<u64> {
+    let text = text.trim().to_lowercase();
+    let today = local(now)?;
+    match text.as_str() {
+        "today" => return local_midnight(today.year, today.month, today.day as i32),
+        "yesterday" => return local_midnight(today.year, today.month, today.day as i32 - 1),
+        _ => {}
+    }
+    let mut date = text.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    if let (Some(Some(year)), Some(Some(month)), Some(Some(day))) =
+        (date.next(), date.next(), date.next())
+    {
+        return local_midnight(year as i32, month as u32, day as i32);
+    }
+
+    let age = text.strip_suffix("ago").unwrap_or(&text).trim();
+    let digits = age.chars().take_while(char::is_ascii_digit).count();
+    let amount = age[..digits].parse::<u64>().ok()?;
+    let seconds = match age[digits..].trim().trim_end_matches('s') {
+        "" | "sec" | "second" => 1,
+        "m" | "min" | "minute" => 60,
+        "h" | "hr" | "hour" => 3600,
+        "d" | "day" => 86_400,
+        "w" | "week" => 7 * 86_400,
+        "month" => 30 * 86_400,
+        "y" | "year" => 365 * 86_400,
+        _ => return None,
+    };
+    Some(now.saturating_sub(amount * seconds))
+}
+
+/// Formats epoch seconds as `YYYY-MM-DD HH:MM:SS` in the local time zone.
+pub fn format_local(secs: u64) -> String {
+    let Some(t) = local(secs) else {
+        return secs.to_string();
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        t.year, t.month, t.day, t.hour, t.minute, t.second
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn ages_count_back_from_now() {
+        assert_eq!(parse_since("3h", NOW), Some(NOW - 3 * 3600));
+        assert_eq!(parse_since("2 days ago", NOW), Some(NOW - 2 * 86_400));
+        assert_eq!(parse_since(" 90 Minutes ", NOW), Some(NOW - 90 * 60));
+        assert_eq!(parse_since("1 week", NOW), Some(NOW - 7 * 86_400));
+        assert_eq!(parse_since("45", NOW), Some(NOW - 45));
+        assert_eq!(parse_since("100 years", 10), Some(0));
+    }
+
+    #[test]
+    fn days_start_at_local_midnight() {
+        let today = local(NOW).unwrap();
+        let midnight = local_midnight(today.year, today.month, today.day as i32);
+        assert_eq!(parse_since("today", NOW), midnight);
+        assert!(midnight.is_some_and(|midnight| midnight <= NOW && NOW - midnight < 86_400));
+        let yesterday = parse_since("Yesterday", NOW).unwrap();
+        assert!((82_800..=90_000).contains(&(midnight.unwrap() - yesterday)));
+        assert_eq!(parse_since("2024-05-01", NOW), local_midnight(2024, 5, 1));
+    }
+
+    #[test]
+    fn anything_else_is_not_a_time() {
+        for text in ["", "soon", "h", "3 fortnights", "-2d", "2024-05"] {
+            assert_eq!(parse_since(text, NOW), None, "{}", text);
+        }
+    }
+}
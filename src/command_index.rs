@@ -0,0 +1,76 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Executables found in `$PATH`, cached until `PATH` or one of its
+/// directories changes.
+pub struct CommandIndex {
+    path: String,
+    modified: Vec<Option<SystemTime>>,
+    commands: Vec<String>,
+}
+
+impl CommandIndex {
+    pub fn new() -> Self {
+        CommandIndex {
+            path: String::new(),
+            modified: vec![],
+            commands: vec![],
+        }
+    }
+
+    /// Sorted, deduplicated command names.
+    pub fn commands(&mut self) -> &[String] {
+        let path = env::var("PATH").unwrap_or_default();
+        let modified = directory_times(&path);
+        if path != self.path || modified != self.modified || self.commands.is_empty() {
+            self.commands = scan(&path);
+            self.path = path;
+            self.modified = modified;
+        }
+        &self.commands
+    }
+}
+
+fn directory_times(path: &str) -> Vec<Option<SystemTime>> {
+    path.split(':')
+        .map(|dir| fs::metadata(dir).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Whether `path` is a regular file, or a link to one, that someone may
+/// execute.
+pub fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// Where `name` is found through the absolute directories of `$PATH`.
+/// Relative entries like `.` are skipped, so this never picks a program
+/// out of the current directory.
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    env::var("PATH")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|dir| dir.starts_with('/'))
+        .map(|dir| Path::new(dir).join(name))
+        .find(|path| is_executable(path))
+}
+
+fn scan(path: &str) -> Vec<String> {
+    let mut commands = path
+        .split(':')
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .flatten()
+        .filter(|entry| is_executable(&entry.path()))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    commands.sort();
+    commands.dedup();
+    commands
+}
@@ -0,0 +1,1195 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::{
+    alias::Aliases,
+    autocomplete::{Kind, Suggestion},
+    bookmarks::Bookmarks,
+    command_index::{find_in_path, is_executable, CommandIndex},
+    completion_context::{escape, CompletionContext},
+    config::{home_dir, Config},
+    fuzzy::fuzzy_match,
+    history::Entry,
+    ls_colors::LsColors,
+    output::run_for_output,
+    script::Scripts,
+    shell::BUILTINS,
+    theme,
+};
+
+/// History completion looks at no more than this many distinct words.
+const MAX_HISTORY_WORDS: usize = 50;
+
+/// How readily a completer's candidates are offered. `AutoComplete` merges
+/// the candidates of all completers in a tier and only asks the next tier
+/// when that finds nothing.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Tier {
+    /// Candidates that know about the command, like its flags or hosts.
+    Specific,
+    /// Files and directories, good for any argument.
+    General,
+    /// Guesses, such as words from the history.
+    Fallback,
+}
+
+/// A source of completion candidates. Completion runs on a worker thread,
+/// so completers must be `Send`.
+pub trait Completer: Send {
+    /// Candidates for the word in `context`, best first, or none when this
+    /// completer has nothing to offer there.
+    fn complete(
+        &mut self,
+        context: &CompletionContext,
+        matcher: &Matcher,
+        sources: &Sources,
+    ) -> Vec<Suggestion>;
+
+    fn tier(&self) -> Tier {
+        Tier::Specific
+    }
+
+    /// Rewrites the line before any completer runs, e.g. to expand an
+    /// abbreviated directory. Returns `None` to leave it alone.
+    fn expand(
+        &self,
+        _line: &str,
+        _context: &CompletionContext,
+        _matcher: &Matcher,
+    ) -> Option<String> {
+        None
+    }
+}
+
+/// Shell state completers may draw on.
+pub struct Sources {
+    pub aliases: Aliases,
+    pub bookmarks: Bookmarks,
+    /// Directories visited with `cd`, best first.
+    pub directories: Vec<PathBuf>,
+    /// Newest first.
+    pub history: Vec<Entry>,
+    /// The shell's jobs, by id with their command line.
+    pub jobs: Vec<(usize, String)>,
+    /// Set when the user typed on, so slow completers can give up early.
+    pub cancelled: Arc<AtomicBool>,
+}
+
+impl Sources {
+    pub fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// How typed letters are compared with candidates.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CaseMode {
+    Sensitive,
+    Insensitive,
+    /// Insensitive unless the word being completed has an uppercase letter.
+    Smart,
+}
+
+/// The matching rules shared by every completer.
+pub struct Matcher {
+    case: CaseMode,
+    /// Fall back to fzf-style matching when no candidate starts with the
+    /// typed fragment.
+    fuzzy: bool,
+}
+
+impl Matcher {
+    pub fn from_config(config: &Config) -> Self {
+        let case = match config.get_str("completion", "case") {
+            Some("sensitive") => CaseMode::Sensitive,
+            Some("insensitive") => CaseMode::Insensitive,
+            _ => CaseMode::Smart,
+        };
+        Matcher {
+            case,
+            fuzzy: config.get_bool("completion", "fuzzy").unwrap_or(false),
+        }
+    }
+
+    pub fn ignore_case(&self, word: &str) -> bool {
+        match self.case {
+            CaseMode::Sensitive => false,
+            CaseMode::Insensitive => true,
+            CaseMode::Smart => !word.chars().any(char::is_uppercase),
+        }
+    }
+
+    /// Keeps the candidates starting with `word`. When there are none and
+    /// fuzzy matching is on, keeps those matching it fuzzily instead, best
+    /// match first.
+    pub fn filter(&self, candidates: Vec<Suggestion>, word: &str) -> Vec<Suggestion> {
+        let ignore_case = self.ignore_case(word);
+        let prefixed = candidates
+            .iter()
+            .any(|c| starts_with(&c.file_name, word, ignore_case));
+        if prefixed || !self.fuzzy {
+            return candidates
+                .into_iter()
+                .filter(|c| starts_with(&c.file_name, word, ignore_case))
+                .collect();
+        }
+        let mut scored = candidates
+            .into_iter()
+            .filter_map(|c| Some((fuzzy_match(word, &c.file_name)?.score, c)))
+            .collect::<Vec<_>>();
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        scored.into_iter().map(|(_, c)| c).collect()
+    }
+}
+
+pub fn same_char(a: char, b: char, ignore_case: bool) -> bool {
+    a == b || (ignore_case && a.to_lowercase().eq(b.to_lowercase()))
+}
+
+pub fn starts_with(name: &str, prefix: &str, ignore_case: bool) -> bool {
+    let mut name_chars = name.chars();
+    prefix.chars().all(|p| {
+        name_chars
+            .next()
+            .is_some_and(|n| same_char(n, p, ignore_case))
+    })
+}
+
+/// Environment variable names after `$` or `${`.
+pub struct VariableCompleter;
+
+impl Completer for VariableCompleter {
+    fn complete(
+        &mut self,
+        context: &CompletionContext,
+        matcher: &Matcher,
+        _sources: &Sources,
+    ) -> Vec<Suggestion> {
+        let Some((name, _)) = &context.variable else {
+            return vec![];
+        };
+        let mut candidates = env::vars_os()
+            .map(|(key, _)| Suggestion::new(key.to_string_lossy().into_owned(), Kind::Variable))
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        matcher.filter(candidates, name)
+    }
+}
+
+/// Aliases, builtins and executables from `$PATH` for the command name.
+pub struct CommandCompleter {
+    commands: CommandIndex,
+}
+
+impl CommandCompleter {
+    pub fn new() -> Self {
+        CommandCompleter {
+            commands: CommandIndex::new(),
+        }
+    }
+}
+
+impl Completer for CommandCompleter {
+    fn complete(
+        &mut self,
+        context: &CompletionContext,
+        matcher: &Matcher,
+        sources: &Sources,
+    ) -> Vec<Suggestion> {
+        if !context.is_command() {
+            return vec![];
+        }
+        // Listed in order of precedence so deduplication keeps the one that
+        // would actually run.
+        let names = sources
+            .aliases
+            .iter()
+            .map(|(name, _)| (name, Kind::Alias))
+            .chain(BUILTINS.iter().map(|name| (*name, Kind::Builtin)))
+            .chain(
+                self.commands
+                    .commands()
+                    .iter()
+                    .map(|name| (name.as_str(), Kind::Command)),
+            );
+        let mut candidates = names
+            .map(|(name, kind)| {
+                let mut suggestion = Suggestion::new(name.to_string(), kind);
+                suggestion.style = match kind {
+                    Kind::Builtin => Some("1".to_string()),
+                    Kind::Alias => Some("36".to_string()),
+                    _ => None,
+                };
+                suggestion
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        candidates.dedup_by(|a, b| a.file_name == b.file_name);
+        matcher.filter(candidates, &context.word)
+    }
+}
+
+/// Entries of the directory the word points into.
+pub struct FileCompleter {
+    /// Offer dotfiles even when the typed fragment does not start with `.`.
+    hidden: bool,
+    /// Color names as `ls` would.
+    colors: bool,
+}
+
+impl FileCompleter {
+    pub fn from_config(config: &Config) -> Self {
+        FileCompleter {
+            hidden: config.get_bool("completion", "hidden").unwrap_or(false),
+            colors: theme::use_color(),
+        }
+    }
+}
+
+impl Completer for FileCompleter {
+    fn tier(&self) -> Tier {
+        Tier::General
+    }
+
+    fn complete(
+        &mut self,
+        context: &CompletionContext,
+        matcher: &Matcher,
+        sources: &Sources,
+    ) -> Vec<Suggestion> {
+        if context.is_command() {
+            return vec![];
+        }
+        // `cd` also finds directories through `$CDPATH` and the bookmarks;
+        // those are labelled with where they come from.
+        let is_cd = context.words.first().is_some_and(|command| command == "cd");
+        let mut roots = vec![(expand_directory(context.directory()), None)];
+        if is_cd {
+            roots.extend(
+                sources
+                    .bookmarks
+                    .alternatives(context.directory())
+                    .into_iter()
+                    .map(|(dir, origin)| (dir, Some(origin))),
+            );
+        }
+
+        let show_hidden = self.hidden || context.segment.starts_with('.');
+        let colors = self.colors.then(LsColors::from_env);
+        let mut seen = HashSet::new();
+        let mut candidates = vec![];
+        for (root, origin) in roots {
+            let Ok(entries) = fs::read_dir(root) else {
+                continue;
+            };
+            // Huge or slow directories are read until the user types on.
+            let mut entries = entries
+                .flatten()
+                .take_while(|_| !sources.cancelled())
+                .map(|entry| entry.path())
+                .collect::<Vec<_>>();
+            if sources.cancelled() {
+                return vec![];
+            }
+            entries.sort();
+            if is_cd {
+                entries.retain(|f| f.is_dir());
+            } else if context.words.is_empty() {
+                // A path in command position names a program to run.
+                entries.retain(|f| f.is_dir() || is_executable(f));
+            }
+            for entry in entries.iter() {
+                let file_name = entry.file_name().unwrap().to_string_lossy().to_string();
+                if (!show_hidden && file_name.starts_with('.')) || !seen.insert(file_name.clone()) {
+                    continue;
+                }
+                let kind = if entry.is_dir() {
+                    Kind::Directory
+                } else {
+                    Kind::File
+                };
+                let mut suggestion = Suggestion::new(file_name, kind);
+                suggestion.style = colors
+                    .as_ref()
+                    .and_then(|colors| colors.style(entry))
+                    .map(str::to_string);
+                suggestion.description = origin.clone();
+                candidates.push(suggestion);
+            }
+        }
+        if is_cd && context.directory().is_empty() {
+            for (name, dir) in sources.bookmarks.iter() {
+                if seen.insert(name.to_string()) {
+                    let mut suggestion = Suggestion::new(name.to_string(), Kind::Directory);
+                    suggestion.description = Some(format!("bookmark {}", dir.display()));
+                    candidates.push(suggestion);
+                }
+            }
+        }
+        matcher.filter(candidates, &context.segment)
+    }
+
+    /// Expands each segment of a directory part that does not exist to the
+    /// one directory it is a prefix of, zsh style, so `/u/lo/b` becomes
+    /// `/usr/local/b`. Only plain words are expanded, and only when every
+    /// segment is unambiguous.
+    fn expand(&self, line: &str, context: &CompletionContext, matcher: &Matcher) -> Option<String> {
+        let typed = &line[context.word_start..context.segment_start];
+        if context.is_command()
+            || typed.is_empty()
+            || typed.contains(['\\', '\'', '"', '~', '$'])
+            || expand_directory(context.directory()).is_dir()
+        {
+            return None;
+        }
+
+        let directory = context.directory();
+        let (mut path, root, relative) = match directory.strip_prefix('/') {
+            Some(rest) => (PathBuf::from("/"), "/", rest),
+            None => (PathBuf::from("."), "", directory),
+        };
+        let mut expanded = vec![];
+        for part in relative.trim_end_matches('/').split('/') {
+            if part.is_empty() || part == "." || part == ".." || path.join(part).is_dir() {
+                path.push(part);
+                expanded.push(part.to_string());
+                continue;
+            }
+            let ignore_case = matcher.ignore_case(part);
+            let matching = fs::read_dir(&path)
+                .ok()?
+                .flatten()
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|name| starts_with(name, part, ignore_case))
+                .collect::<Vec<_>>();
+            let [name] = matching.as_slice() else {
+                return None;
+            };
+            path.push(name);
+            expanded.push(name.clone());
+        }
+
+        let rewritten = expanded
+            .iter()
+            .map(|part| escape(part, None))
+            .collect::<Vec<_>>()
+            .join("/");
+        Some(format!(
+            "{}{}{}/{}",
+            &line[..context.word_start],
+            root,
+            rewritten,
+            &line[context.segment_start..]
+        ))
+    }
+}
+
+/// Directories visited before, wherever they are, for a plain `cd` word.
+/// The fragment is matched against their last component and replaced by
+/// the full path.
+pub struct RecentDirectoryCompleter;
+
+impl Completer for RecentDirectoryCompleter {
+    fn tier(&self) -> Tier {
+        Tier::General
+    }
+
+    fn complete(
+        &mut self,
+        context: &CompletionContext,
+        matcher: &Matcher,
+        sources: &Sources,
+    ) -> Vec<Suggestion> {
+        if context.words.first().map(String::as_str) != Some("cd")
+            || context.word.is_empty()
+            || context.word.contains('/')
+            || context.word.starts_with(['~', '$'])
+        {
+            return vec![];
+        }
+        let cwd = env::current_dir().ok();
+        let candidates = sources
+            .directories
+            .iter()
+            .filter(|directory| Some(*directory) != cwd.as_ref())
+            .filter_map(|directory| {
+                let name = directory.file_name()?.to_string_lossy().into_owned();
+                let mut suggestion = Suggestion::new(name, Kind::Directory);
+                suggestion.description = Some(directory.to_string_lossy().into_owned());
+                Some(suggestion)
+            })
+            .collect();
+        matcher
+            .filter(candidates, &context.word)
+            .into_iter()
+            .map(|mut suggestion| {
+                suggestion.file_name = suggestion.description.take().unwrap_or_default();
+                suggestion.description = Some("recent".to_string());
+                suggestion
+            })
+            .collect()
+    }
+}
+
+/// Completions declared in the config file, e.g.
+///
+/// ```toml
+/// [[completion.rules]]
+/// command = "kubectl"
+/// position = 1
+/// words = ["get", "describe", "apply", "delete"]
+///
+/// [[completion.rules]]
+/// command = "kubectl"
+/// position = 3
+/// run = "kubectl get pods -o name"
+/// ```
+///
+/// `position` counts arguments from 1 and matches any when left out; `run`
+/// is a shell command printing one candidate per line. It gets the words
+/// before the cursor as `$1`, `$2` and so on, and the partial word being
+/// completed as the last argument, so any program can complete a
+/// command:
+///
+/// ```toml
+/// [[completion.rules]]
+/// command = "make"
+/// run = "~/.config/a-shell/complete-make \"$@\""
+/// ```
+pub struct UserCompleter {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    command: String,
+    position: Option<usize>,
+    source: RuleSource,
+}
+
+enum RuleSource {
+    Words(Vec<String>),
+    Run(String),
+}
+
+impl UserCompleter {
+    pub fn from_config(config: &Config) -> Self {
+        let rules = config
+            .get("completion", "rules")
+            .and_then(|rules| rules.as_array())
+            .map(|rules| {
+                rules
+                    .iter()
+                    .filter_map(|rule| {
+                        let source = match (rule.get("words"), rule.get("run")) {
+                            (Some(words), _) => RuleSource::Words(
+                                words
+                                    .as_array()?
+                                    .iter()
+                                    .filter_map(|w| Some(w.as_str()?.to_string()))
+                                    .collect(),
+                            ),
+                            (None, Some(run)) => RuleSource::Run(run.as_str()?.to_string()),
+                            (None, None) => return None,
+                        };
+                        Some(Rule {
+                            command: rule.get("command")?.as_str()?.to_string(),
+                            position: rule
+                                .get("position")
+                                .and_then(|p| p.as_integer())
+                                .and_then(|p| usize::try_from(p).ok()),
+                            source,
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        UserCompleter { rules }
+    }
+}
+
+impl Completer for UserCompleter {
+    fn complete(
+        &mut self,
+        context: &CompletionContext,
+        matcher: &Matcher,
+        _sources: &Sources,
+    ) -> Vec<Suggestion> {
+        let Some(command) = context.words.first() else {
+            return vec![];
+        };
+        let position = context.words.len();
+        let mut candidates = vec![];
+        for rule in &self.rules {
+            if rule.command != *command || rule.position.is_some_and(|p| p != position) {
+                continue;
+            }
+            match &rule.source {
+                RuleSource::Words(words) => candidates.extend(words.iter().cloned()),
+                RuleSource::Run(run) => candidates.extend(
+                    run_for_output(
+                        Command::new("sh")
+                            .arg("-c")
+                            .arg(run)
+                            .arg("a-shell")
+                            .args(&context.words)
+                            .arg(&context.word),
+                    )
+                    .unwrap_or_default()
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string),
+                ),
+            }
+        }
+        let candidates = candidates
+            .into_iter()
+            .map(|word| Suggestion::new(word, Kind::Word))
+            .collect();
+        matcher.filter(candidates, &context.word)
+    }
+}
+
+/// Completes with the functions scripts gave `complete`, without starting
+/// a process for each. See [`Scripts`].
+pub struct ScriptCompleter {
+    scripts: Scripts,
+}
+
+impl ScriptCompleter {
+    pub fn new(scripts: &Scripts) -> Self {
+        ScriptCompleter {
+            scripts: scripts.clone(),
+        }
+    }
+}
+
+impl Completer for ScriptCompleter {
+    fn complete(
+        &mut self,
+        context: &CompletionContext,
+        matcher: &Matcher,
+        _sources: &Sources,
+    ) -> Vec<Suggestion> {
+        let candidates = self
+            .scripts
+            .complete(&context.words, &context.word)
+            .into_iter()
+            .map(|word| Suggestion::new(word, Kind::Word))
+            .collect();
+        matcher.filter(candidates, &context.word)
+    }
+}
+
+/// Sources the completion scripts named in the environment.
+const SOURCE_SCRIPTS: &str = r#"
+IFS=: read -ra scripts <<< "$A_SHELL_COMPLETION_SCRIPTS"
+for script in "${scripts[@]}"; do . "$script"; done >/dev/null 2>&1
+"#;
+
+/// Runs `$2`, the completion function, the way bash would for the line in
+/// `$1` with the words from `$4` on and the cursor in word `$3`, printing
+/// what it put in COMPREPLY.
+const BASH_COMPLETE: &str = r#"
+COMP_LINE=$1 COMP_POINT=${#1} COMP_TYPE=9 COMP_KEY=9
+function=$2 COMP_CWORD=$3
+shift 3
+COMP_WORDS=("$@")
+COMPREPLY=()
+"$function" "${COMP_WORDS[0]}" "${COMP_WORDS[COMP_CWORD]}" "${COMP_WORDS[COMP_CWORD-1]}" >/dev/null 2>&1
+printf '%s\n' "${COMPREPLY[@]}"
+"#;
+
+/// Completions defined by bash completion scripts listed in the config
+/// file, e.g. `[completion] bash = ["/usr/share/bash-completion/completions/git"]`.
+/// Word lists (`complete -W`) and functions (`complete -F`) are
+/// supported; the functions run in a bash child process.
+pub struct BashCompleter {
+    scripts: Vec<String>,
+    /// What `complete -p` printed after sourcing the scripts, by command.
+    /// Read the first time it is needed.
+    specs: Option<HashMap<String, BashSpec>>,
+}
+
+#[derive(Clone)]
+enum BashSpec {
+    Words(Vec<String>),
+    Function(String),
+}
+
+impl BashCompleter {
+    pub fn from_config(config: &Config) -> Self {
+        let scripts = config
+            .get_str_list("completion", "bash")
+            .unwrap_or_default()
+            .into_iter()
+            .map(|script| match script.strip_prefix("~/") {
+                Some(rest) => home_dir().join(rest).to_string_lossy().into_owned(),
+                None => script.to_string(),
+            })
+            .collect();
+        BashCompleter {
+            scripts,
+            specs: None,
+        }
+    }
+
+    /// A bash running `script` after sourcing the scripts.
+    fn bash(&self, script: &str) -> Command {
+        let mut command = Command::new("bash");
+        command
+            .arg("-c")
+            .arg(format!("{}{}", SOURCE_SCRIPTS, script))
+            .arg("bash")
+            .env("A_SHELL_COMPLETION_SCRIPTS", self.scripts.join(":"));
+        command
+    }
+
+    fn load_specs(&self) -> HashMap<String, BashSpec> {
+        let output = run_for_output(&mut self.bash("complete -p")).unwrap_or_default();
+        let mut specs = HashMap::new();
+        for line in output.lines() {
+            // Lines look like `complete -o default -F _git git gitk`.
+            let words = CompletionContext::new(&format!("{} ", line)).words;
+            let mut spec = None;
+            let mut commands = vec![];
+            let mut args = words.iter().skip(1);
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "-W" => {
+                        spec = args.next().map(|w| {
+                            BashSpec::Words(w.split_whitespace().map(str::to_string).collect())
+                        })
+                    }
+                    "-F" => spec = args.next().map(|f| BashSpec::Function(f.clone())),
+                    "-o" | "-A" | "-G" | "-C" | "-X" | "-P" | "-S" => {
+                        args.next();
+                    }
+                    option if option.starts_with('-') => {}
+                    command => commands.push(command.to_string()),
+                }
+            }
+            if let Some(spec) = spec {
+                for command in commands {
+                    specs.insert(command, spec.clone());
+                }
+            }
+        }
+        specs
+    }
+}
+
+impl Completer for BashCompleter {
+    fn complete(
+        &mut self,
+        context: &CompletionContext,
+        matcher: &Matcher,
+        _sources: &Sources,
+    ) -> Vec<Suggestion> {
+        if self.scripts.is_empty() || context.words.is_empty() {
+            return vec![];
+        }
+        if self.specs.is_none() {
+            self.specs = Some(self.load_specs());
+        }
+        let Some(spec) = self
+            .specs
+            .as_ref()
+            .and_then(|specs| specs.get(&context.words[0]))
+        else {
+            return vec![];
+        };
+        let words = match spec {
+            BashSpec::Words(words) => words.clone(),
+            BashSpec::Function(function) => {
+                let line = format!("{} {}", context.words.join(" "), context.word);
+                let mut bash = self.bash(BASH_COMPLETE);
+                bash.arg(&line)
+                    .arg(function)
+                    .arg(context.words.len().to_string())
+                    .args(&context.words)
+                    .arg(&context.word);
+                run_for_output(&mut bash)
+                    .unwrap_or_default()
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }
+        };
+        let candidates = words
+            .into_iter()
+            .map(|word| Suggestion::new(word, Kind::Word))
+            .collect();
+        matcher.filter(candidates, &context.word)
+    }
+}
+
+/// Subcommands Cargo ships with.
+const CARGO_SUBCOMMANDS: &[&str] = &[
+    "add",
+    "bench",
+    "build",
+    "check",
+    "clean",
+    "clippy",
+    "doc",
+    "fetch",
+    "fix",
+    "fmt",
+    "generate-lockfile",
+    "help",
+    "init",
+    "install",
+    "locate-project",
+    "login",
+    "logout",
+    "metadata",
+    "new",
+    "owner",
+    "package",
+    "pkgid",
+    "publish",
+    "remove",
+    "report",
+    "run",
+    "rustc",
+    "rustdoc",
+    "search",
+    "test",
+    "tree",
+    "uninstall",
+    "update",
+    "vendor",
+    "verify-project",
+    "version",
+    "yank",
+];
+
+/// `cargo` subcommands, including `cargo-*` plugins on the `$PATH`, and the
+/// targets of the current project after `--bin` and `--example`.
+pub struct CargoCompleter {
+    commands: CommandIndex,
+}
+
+impl CargoCompleter {
+    pub fn new() -> Self {
+        CargoCompleter {
+            commands: CommandIndex::new(),
+        }
+    }
+}
+
+impl Completer for CargoCompleter {
+    fn complete(
+        &mut self,
+        context: &CompletionContext,
+        matcher: &Matcher,
+        _sources: &Sources,
+    ) -> Vec<Suggestion> {
+        if context.words.first().map(String::as_str) != Some("cargo") {
+            return vec![];
+        }
+        let names = match context.words.last().map(String::as_str) {
+            Some("--bin") => cargo_targets("bin"),
+            Some("--example") => cargo_targets("example"),
+            _ if context.words.len() == 1 && !context.word.starts_with('-') => {
+                let mut names = CARGO_SUBCOMMANDS
+                    .iter()
+                    .map(|name| name.to_string())
+                    .chain(
+                        self.commands
+                            .commands()
+                            .iter()
+                            .filter_map(|name| name.strip_prefix("cargo-"))
+                            .map(str::to_string),
+                    )
+                    .collect::<Vec<_>>();
+                names.sort();
+                names.dedup();
+                names
+            }
+            _ => return vec![],
+        };
+        let candidates = names
+            .into_iter()
+            .map(|name| Suggestion::new(name, Kind::Word))
+            .collect();
+        matcher.filter(candidates, &context.word)
+    }
+}
+
+/// Names of the `bin` or `example` targets of the package around the
+/// working directory, declared in its Cargo.toml or found by Cargo's
+/// layout conventions.
+fn cargo_targets(kind: &str) -> Vec<String> {
+    let Some(root) = env::current_dir().ok().and_then(|dir| {
+        dir.ancestors()
+            .find(|dir| dir.join("Cargo.toml").is_file())
+            .map(Path::to_path_buf)
+    }) else {
+        return vec![];
+    };
+    let manifest = fs::read_to_string(root.join("Cargo.toml"))
+        .ok()
+        .and_then(|content| content.parse::<toml::Table>().ok())
+        .unwrap_or_default();
+
+    let mut names = manifest
+        .get(kind)
+        .and_then(|targets| targets.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|target| Some(target.get("name")?.as_str()?.to_string()))
+        .collect::<Vec<_>>();
+    let directory = match kind {
+        "bin" => {
+            let package = manifest.get("package").and_then(|p| p.get("name"));
+            if let (Some(name), true) = (package, root.join("src/main.rs").is_file()) {
+                names.extend(name.as_str().map(str::to_string));
+            }
+            root.join("src/bin")
+        }
+        _ => root.join("examples"),
+    };
+    // Each `name.rs` or `name/main.rs` in the directory is a target.
+    for entry in fs::read_dir(directory).into_iter().flatten().flatten() {
+        let path = entry.path();
+        let name = match path.extension() {
+            Some(extension) if extension == "rs" => path.file_stem(),
+            _ if path.join("main.rs").is_file() => path.file_name(),
+            _ => None,
+        };
+        names.extend(name.and_then(|name| name.to_str()).map(str::to_string));
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Running processes from /proc: PIDs labelled with their command for
+/// `kill`, command names for `pkill` and `killall`. The shell's own jobs
+/// are offered as `%N` for `kill`, `fg` and `bg`.
+pub struct ProcessCompleter;
+
+impl Completer for ProcessCompleter {
+    fn complete(
+        &mut self,
+        context: &CompletionContext,
+        matcher: &Matcher,
+        sources: &Sources,
+    ) -> Vec<Suggestion> {
+        let command = context.words.first().map(String::as_str);
+        if context.word.starts_with('%') || matches!(command, Some("fg" | "bg")) {
+            if !matches!(command, Some("kill" | "fg" | "bg")) {
+                return vec![];
+            }
+            let candidates = sources
+                .jobs
+                .iter()
+                .map(|(id, line)| {
+                    let mut suggestion = Suggestion::new(format!("%{}", id), Kind::Word);
+                    suggestion.description = Some(line.clone());
+                    suggestion
+                })
+                .collect();
+            return matcher.filter(candidates, &context.word);
+        }
+        let by_pid = match command {
+            Some("kill") => true,
+            Some("pkill" | "killall") => false,
+            _ => return vec![],
+        };
+        if context.word.starts_with('-') {
+            return vec![];
+        }
+        let mut candidates = processes()
+            .into_iter()
+            .map(|(pid, name)| {
+                if by_pid {
+                    let mut suggestion = Suggestion::new(pid.to_string(), Kind::Word);
+                    suggestion.description = Some(name);
+                    suggestion
+                } else {
+                    Suggestion::new(name, Kind::Word)
+                }
+            })
+            .collect::<Vec<_>>();
+        if !by_pid {
+            candidates.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+            candidates.dedup_by(|a, b| a.file_name == b.file_name);
+        }
+        matcher.filter(candidates, &context.word)
+    }
+}
+
+/// PIDs and command names of the processes in /proc, by PID.
+fn processes() -> Vec<(u32, String)> {
+    let mut processes = fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+            let name = fs::read_to_string(entry.path().join("comm")).ok()?;
+            Some((pid, name.trim_end().to_string()))
+        })
+        .collect::<Vec<_>>();
+    processes.sort();
+    processes
+}
+
+/// Host names for `ssh`, `scp` and `rsync` from ~/.ssh/config and
+/// ~/.ssh/known_hosts, keeping a typed `user@`.
+pub struct HostCompleter;
+
+impl Completer for HostCompleter {
+    fn complete(
+        &mut self,
+        context: &CompletionContext,
+        matcher: &Matcher,
+        _sources: &Sources,
+    ) -> Vec<Suggestion> {
+        let Some(command) = context.words.first() else {
+            return vec![];
+        };
+        // scp and rsync take local paths too; those fall through to files.
+        let remote = match command.as_str() {
+            "ssh" => true,
+            "scp" | "rsync" => !context.word.contains(['/', ':']),
+            _ => false,
+        };
+        if !remote || context.word.starts_with('-') {
+            return vec![];
+        }
+        let (user, host) = match context.word.split_once('@') {
+            Some((user, host)) => (format!("{}@", user), host),
+            None => (String::new(), context.word.as_str()),
+        };
+        let candidates = ssh_hosts()
+            .into_iter()
+            .map(|name| Suggestion::new(name, Kind::Host))
+            .collect();
+        matcher
+            .filter(candidates, host)
+            .into_iter()
+            .map(|mut suggestion| {
+                suggestion.file_name.insert_str(0, &user);
+                suggestion
+            })
+            .collect()
+    }
+}
+
+/// The hosts named in the ssh config and known hosts. Patterns and hashed
+/// known hosts cannot be completed, so they are left out.
+fn ssh_hosts() -> Vec<String> {
+    let ssh = home_dir().join(".ssh");
+    let config = fs::read_to_string(ssh.join("config")).unwrap_or_default();
+    let known_hosts = fs::read_to_string(ssh.join("known_hosts")).unwrap_or_default();
+
+    let configured = config.lines().flat_map(|line| {
+        let mut fields = line.split_whitespace();
+        let is_host = fields
+            .next()
+            .is_some_and(|key| key.eq_ignore_ascii_case("host"));
+        fields.filter(move |_| is_host)
+    });
+    let known = known_hosts
+        .lines()
+        .filter(|line| !line.starts_with(['#', '|', '@']))
+        .filter_map(|line| line.split_whitespace().next())
+        .flat_map(|hosts| hosts.split(','))
+        // Hosts on another port are written `[host]:port`.
+        .map(|host| {
+            host.strip_prefix('[')
+                .and_then(|host| host.split_once(']'))
+                .map_or(host, |(host, _)| host)
+        });
+    let mut hosts = configured
+        .chain(known)
+        .filter(|host| !host.is_empty() && !host.contains(['*', '?', '!']))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    hosts.sort();
+    hosts.dedup();
+    hosts
+}
+
+/// Options for words starting with `-`, picked out of the command's
+/// `--help` output. That runs the command, so it is only done for those
+/// listed in `[completion]`, and only as found through `$PATH`, never a
+/// script in the current directory:
+///
+/// ```toml
+/// [completion]
+/// # Or ["*"] for any command in $PATH.
+/// help_flags = ["git", "cargo", "rg"]
+/// ```
+///
+/// Each command is asked once per session.
+pub struct FlagCompleter {
+    allowed: Vec<String>,
+    flags: HashMap<PathBuf, Vec<String>>,
+}
+
+impl FlagCompleter {
+    pub fn from_config(config: &Config) -> Self {
+        FlagCompleter {
+            allowed: config
+                .get_str_list("completion", "help_flags")
+                .unwrap_or_default()
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            flags: HashMap::new(),
+        }
+    }
+
+    fn allows(&self, command: &str) -> bool {
+        self.allowed
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == command)
+    }
+}
+
+impl Completer for FlagCompleter {
+    fn complete(
+        &mut self,
+        context: &CompletionContext,
+        matcher: &Matcher,
+        sources: &Sources,
+    ) -> Vec<Suggestion> {
+        let Some(command) = context.words.first() else {
+            return vec![];
+        };
+        if !context.word.starts_with('-') {
+            return vec![];
+        }
+        // An alias is asked about as the command it runs.
+        let expanded = sources.aliases.expand(command);
+        let command = expanded.split_whitespace().next().unwrap_or_default();
+        if !self.allows(command) {
+            return vec![];
+        }
+        let Some(path) = find_in_path(command) else {
+            return vec![];
+        };
+        let flags = self.flags.entry(path).or_insert_with_key(|path| {
+            run_for_output(Command::new(path).arg("--help"))
+                .map(|help| parse_flags(&help))
+                .unwrap_or_default()
+        });
+        let candidates = flags
+            .iter()
+            .map(|flag| Suggestion::new(flag.clone(), Kind::Word))
+            .collect();
+        matcher.filter(candidates, &context.word)
+    }
+}
+
+/// The distinct `-x` and `--long` options mentioned in help text, in the
+/// order they first appear, without any `=VALUE` or `[=VALUE]` part.
+fn parse_flags(help: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    help.split(|c: char| c.is_whitespace() || matches!(c, ',' | '|' | '(' | ')'))
+        .filter_map(|token| {
+            let flag = token
+                .split(['=', '[', '<'])
+                .next()?
+                .trim_end_matches(['.', ':', ';']);
+            let name = flag.trim_start_matches('-');
+            let dashes = flag.len() - name.len();
+            let valid = (dashes == 1 || dashes == 2)
+                && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            (valid && seen.insert(flag)).then(|| flag.to_string())
+        })
+        .collect()
+}
+
+/// Words from earlier commands, most recent first, for arguments nothing
+/// else completes.
+pub struct HistoryCompleter;
+
+impl Completer for HistoryCompleter {
+    fn tier(&self) -> Tier {
+        Tier::Fallback
+    }
+
+    fn complete(
+        &mut self,
+        context: &CompletionContext,
+        matcher: &Matcher,
+        sources: &Sources,
+    ) -> Vec<Suggestion> {
+        if context.word.is_empty() || context.is_command() {
+            return vec![];
+        }
+        let ignore_case = matcher.ignore_case(&context.word);
+        let mut seen = HashSet::new();
+        sources
+            .history
+            .iter()
+            .flat_map(|entry| {
+                let parsed = CompletionContext::new(&entry.command);
+                parsed.words.into_iter().chain([parsed.word])
+            })
+            .filter(|word| {
+                *word != context.word
+                    && starts_with(word, &context.word, ignore_case)
+                    && seen.insert(word.clone())
+            })
+            .take(MAX_HISTORY_WORDS)
+            .map(|word| Suggestion::new(word, Kind::Word))
+            .collect()
+    }
+}
+
+/// The directory to list for the typed directory part of a word, with a
+/// leading `~` or `$VAR` expanded.
+pub fn expand_directory(directory: &str) -> PathBuf {
+    if directory.is_empty() {
+        return PathBuf::from(".");
+    }
+    if directory == "~" {
+        return home_dir();
+    }
+    if let Some(rest) = directory.strip_prefix("~/") {
+        return home_dir().join(rest);
+    }
+    if let Some(rest) = directory.strip_prefix('$') {
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        let value = env::var(&rest[..end]).unwrap_or_default();
+        return PathBuf::from(format!("{}{}", value, &rest[end..]));
+    }
+    PathBuf::from(directory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_are_read_from_help_text_once_each() {
+        let help = "\
+  -o, --output=FILE   write to FILE.
+  -l, --long[=WHEN]   colorize (see --output)
+      --level <N>     -1 for none
+  --, -, ---bad, -_x, 5-3, well-known, --e.g.";
+        assert_eq!(
+            parse_flags(help),
+            ["-o", "--output", "-l", "--long", "--level", "-1"]
+        );
+    }
+}
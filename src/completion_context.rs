@@ -0,0 +1,251 @@
+/// Commands that run the command named after them, which is completed as
+/// if it stood alone.
+pub const COMMAND_PREFIXES: &[&str] = &[
+    "command", "doas", "env", "exec", "nice", "nohup", "sudo", "time", "xargs",
+];
+
+/// The word being completed and what surrounds it, found by splitting the
+/// line up to the cursor the way the parser will split it.
+pub struct CompletionContext {
+    /// The earlier words of the current command, with quotes and escapes
+    /// removed. Empty while typing the command name.
+    pub words: Vec<String>,
+    /// The word being completed, with quotes and escapes removed.
+    pub word: String,
+    /// The part of `word` after its last `/`.
+    pub segment: String,
+    /// Where the word starts in the line as typed.
+    pub word_start: usize,
+    /// Where `segment` starts in the line as typed. Completing replaces the
+    /// line from here on.
+    pub segment_start: usize,
+    /// The quote the segment is inside. A quote opened within the segment
+    /// itself is replaced along with it, so it does not count.
+    pub quote: Option<char>,
+    /// The variable name being typed after an unescaped `$` or `${`
+    /// outside single quotes, and whether it is braced.
+    pub variable: Option<(String, bool)>,
+}
+
+impl CompletionContext {
+    pub fn new(line: &str) -> Self {
+        let mut words = vec![];
+        let mut word = String::new();
+        let mut segment = String::new();
+        let mut in_word = false;
+        let mut word_start = line.len();
+        let mut segment_start = line.len();
+        let mut quote: Option<(char, usize)> = None;
+        let mut chars = line.char_indices().peekable();
+
+        while let Some((i, c)) = chars.next() {
+            if quote.is_none() && (c.is_whitespace() || matches!(c, '|' | ';' | '&')) {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    segment.clear();
+                    in_word = false;
+                }
+                // A new command starts after a pipe or separator.
+                if !c.is_whitespace() {
+                    words.clear();
+                }
+                continue;
+            }
+            if !in_word {
+                in_word = true;
+                word_start = i;
+                segment_start = i;
+            }
+            match c {
+                '\\' if quote.is_none() => {
+                    if let Some((_, next)) = chars.next() {
+                        word.push(next);
+                        segment.push(next);
+                    }
+                }
+                // Inside double quotes only the characters that are special
+                // there can be escaped.
+                '\\' if quote.is_some_and(|(q, _)| q == '"') => {
+                    let c = match chars.peek() {
+                        Some(&(_, next)) if matches!(next, '"' | '\\' | '$' | '`') => {
+                            chars.next();
+                            next
+                        }
+                        _ => c,
+                    };
+                    word.push(c);
+                    segment.push(c);
+                }
+                '"' | '\'' if quote.is_some_and(|(q, _)| q == c) => quote = None,
+                '"' | '\'' if quote.is_none() => {
+                    quote = Some((c, i));
+                    if i == segment_start {
+                        segment_start = i + 1;
+                    }
+                }
+                '/' => {
+                    word.push(c);
+                    segment.clear();
+                    segment_start = i + 1;
+                }
+                _ => {
+                    word.push(c);
+                    segment.push(c);
+                }
+            }
+        }
+        if !in_word {
+            word_start = line.len();
+            segment_start = line.len();
+        }
+
+        strip_command_prefixes(&mut words);
+
+        let open_quote = quote.map(|(q, _)| q);
+        CompletionContext {
+            words,
+            word,
+            segment,
+            word_start,
+            segment_start,
+            quote: quote.filter(|(_, at)| *at < segment_start).map(|(q, _)| q),
+            variable: variable_at_end(line).filter(|_| open_quote != Some('\'')),
+        }
+    }
+
+    /// Whether the word is the command name.
+    pub fn is_command(&self) -> bool {
+        self.words.is_empty() && !self.word.is_empty() && !self.word.contains('/')
+    }
+
+    /// The part of `word` up to and including its last `/`.
+    pub fn directory(&self) -> &str {
+        &self.word[..self.word.len() - self.segment.len()]
+    }
+}
+
+/// Drops leading words like `sudo -E` or `env FOO=1` so the words start
+/// at the command they run.
+fn strip_command_prefixes(words: &mut Vec<String>) {
+    let mut start = 0;
+    while words
+        .get(start)
+        .is_some_and(|word| COMMAND_PREFIXES.contains(&word.as_str()))
+    {
+        start += 1;
+        while words
+            .get(start)
+            .is_some_and(|word| word.starts_with('-') || word.contains('='))
+        {
+            start += 1;
+        }
+    }
+    words.drain(..start);
+}
+
+fn variable_at_end(line: &str) -> Option<(String, bool)> {
+    let name_start = line
+        .trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        .len();
+    let before = &line[..name_start];
+    let (dollar, braced) = match before.strip_suffix("${") {
+        Some(rest) => (rest, true),
+        None => (before.strip_suffix('$')?, false),
+    };
+    if dollar.ends_with('\\') {
+        return None;
+    }
+    Some((line[name_start..].to_string(), braced))
+}
+
+/// Escapes `name` for use inside `quote`, or unquoted, so the parser reads
+/// it back unchanged. A leading `~` is escaped too, since it would stand
+/// for the home directory.
+pub fn escape(name: &str, quote: Option<char>) -> String {
+    let mut escaped = String::new();
+    for c in name.chars() {
+        match quote {
+            Some('\'') if c == '\'' => escaped.push_str("'\\''"),
+            Some('"') if matches!(c, '"' | '\\' | '$' | '`') => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            None if c.is_whitespace()
+                || "'\"\\$`*?[]{}()<>|&;!#".contains(c)
+                || (c == '~' && escaped.is_empty()) =>
+            {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::CommandParser;
+
+    #[test]
+    fn the_word_is_split_at_its_last_slash() {
+        let context = CompletionContext::new("ls -l src/ma");
+        assert_eq!(context.words, ["ls", "-l"]);
+        assert_eq!(context.word, "src/ma");
+        assert_eq!(context.segment, "ma");
+        assert_eq!(context.directory(), "src/");
+        assert_eq!((context.word_start, context.segment_start), (6, 10));
+        assert!(!context.is_command());
+    }
+
+    #[test]
+    fn quotes_and_escapes_are_removed() {
+        let context = CompletionContext::new(r#"cat "my dir/a b"#);
+        assert_eq!(context.word, "my dir/a b");
+        assert_eq!(context.segment, "a b");
+        assert_eq!(context.quote, Some('"'));
+        let context = CompletionContext::new(r"cat my\ fi");
+        assert_eq!(context.word, "my fi");
+        assert_eq!(context.quote, None);
+        // Opened within the segment, the quote is replaced with it.
+        let context = CompletionContext::new("cat a'b c");
+        assert_eq!(context.word, "ab c");
+        assert_eq!(context.quote, None);
+    }
+
+    #[test]
+    fn a_new_command_starts_after_separators_and_prefixes() {
+        let context = CompletionContext::new("make | sudo -E env A=1 git ch");
+        assert_eq!(context.words, ["git"]);
+        assert_eq!(context.word, "ch");
+        assert!(CompletionContext::new("ls; gi").is_command());
+        let context = CompletionContext::new("echo a ");
+        assert_eq!(context.words, ["echo", "a"]);
+        assert_eq!(context.word, "");
+        assert_eq!(context.word_start, 7);
+    }
+
+    #[test]
+    fn variables_are_found_unless_escaped_or_single_quoted() {
+        let variable = |line| CompletionContext::new(line).variable;
+        assert_eq!(variable("echo $HO"), Some(("HO".to_string(), false)));
+        assert_eq!(variable("echo \"${PA"), Some(("PA".to_string(), true)));
+        assert_eq!(variable("echo \\$HO"), None);
+        assert_eq!(variable("echo '$HO"), None);
+    }
+
+    #[test]
+    fn escaped_names_read_back_unchanged() {
+        assert_eq!(escape("a b&c", None), r"a\ b\&c");
+        assert_eq!(escape("it's", Some('\'')), r"it'\''s");
+        assert_eq!(escape("a\"$b c", Some('"')), r#"a\"\$b c"#);
+        let parser = CommandParser::new();
+        for name in ["a b", "it's", "$x*", "tab\there", "(a)|b;c", "~a", "a\\b"] {
+            for (open, quote) in [("", None), ("'", Some('\'')), ("\"", Some('"'))] {
+                let line = format!("echo {}{}{}", open, escape(name, quote), open);
+                assert_eq!(parser.parse(&line).args, [name], "{}", line);
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use toml::{Table, Value};
+
+use crate::paths::{self, File};
+
+pub fn home_dir() -> PathBuf {
+    env::var("HOME").map(PathBuf::from).unwrap_or_else(|_| {
+        PathBuf::from(format!(
+            "/home/{}",
+            env::var("USER").unwrap_or_else(|_| "Unknown".to_string())
+        ))
+    })
+}
+
+pub struct Config {
+    table: Table,
+}
+
+impl Config {
+    /// Where the config is read from unless `--config` says otherwise.
+    pub fn default_path() -> PathBuf {
+        paths::path(File::Config)
+    }
+
+    pub fn load(path: &Path) -> Self {
+        let table = match fs::read_to_string(path) {
+            Ok(content) => content.parse::<Table>().unwrap_or_else(|e| {
+                eprintln!("Cannot parse {}: {}", path.display(), e);
+                Table::new()
+            }),
+            Err(_) => Table::new(),
+        };
+        Config { table }
+    }
+
+    /// The config with the sections of `[profiles.<name>]` laid over it,
+    /// key by key:
+    ///
+    /// ```toml
+    /// [profiles.work.theme]
+    /// name = "solarized"
+    /// [profiles.work.env]
+    /// AWS_PROFILE = "work"
+    /// [profiles.work.history]
+    /// file = "~/.local/state/a-shell/work-history"
+    /// ```
+    pub fn with_profile(mut self, name: &str) -> Result<Self, String> {
+        let profile = self
+            .section("profiles")
+            .and_then(|profiles| profiles.get(name)?.as_table())
+            .cloned()
+            .ok_or_else(|| format!("{}: no such profile", name))?;
+        for (section, value) in profile {
+            match (self.table.get_mut(&section), value) {
+                (Some(Value::Table(base)), Value::Table(overrides)) => base.extend(overrides),
+                (_, value) => {
+                    self.table.insert(section, value);
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// The names of the profiles defined in `[profiles]`.
+    pub fn profiles(&self) -> Vec<&str> {
+        self.section("profiles")
+            .map(|profiles| profiles.keys().map(String::as_str).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn section(&self, section: &str) -> Option<&Table> {
+        self.table.get(section)?.as_table()
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&Value> {
+        self.table.get(section)?.get(key)
+    }
+
+    pub fn get_bool(&self, section: &str, key: &str) -> Option<bool> {
+        self.get(section, key)?.as_bool()
+    }
+
+    pub fn get_int(&self, section: &str, key: &str) -> Option<i64> {
+        self.get(section, key)?.as_integer()
+    }
+
+    pub fn get_str(&self, section: &str, key: &str) -> Option<&str> {
+        self.get(section, key)?.as_str()
+    }
+
+    /// Reads an array of strings, skipping any non-string items.
+    pub fn get_str_list(&self, section: &str, key: &str) -> Option<Vec<&str>> {
+        let list = self.get(section, key)?.as_array()?;
+        Some(list.iter().filter_map(Value::as_str).collect())
+    }
+}
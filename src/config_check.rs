@@ -0,0 +1,474 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+use crate::{
+    config::Config,
+    hooks::Hook,
+    keymap,
+    parser::{self, CommandParser},
+    prompt::check_template,
+    script, theme,
+};
+
+/// What a config value has to be.
+#[derive(Clone, Copy)]
+enum Kind {
+    Bool,
+    Int,
+    Str,
+    StrList,
+    /// Checked elsewhere, or anything goes.
+    Any,
+}
+
+impl Kind {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            Kind::Bool => value.is_bool(),
+            Kind::Int => value.is_integer(),
+            Kind::Str => value.is_str(),
+            Kind::StrList => value
+                .as_array()
+                .is_some_and(|list| list.iter().all(Value::is_str)),
+            Kind::Any => true,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Bool => "true or false",
+            Kind::Int => "a number",
+            Kind::Str => "a string",
+            Kind::StrList => "a list of strings",
+            Kind::Any => "anything",
+        }
+    }
+}
+
+/// The keys of each section the shell reads. `[theme]` also takes a key
+/// per role.
+const SECTIONS: &[(&str, &[(&str, Kind)])] = &[
+    ("about", &[("logo", Kind::Str)]),
+    (
+        "completion",
+        &[
+            ("bash", Kind::StrList),
+            ("case", Kind::Str),
+            ("directories_first", Kind::Bool),
+            ("fuzzy", Kind::Bool),
+            ("help_flags", Kind::StrList),
+            ("hidden", Kind::Bool),
+            ("rules", Kind::Any),
+            ("sort", Kind::Bool),
+        ],
+    ),
+    (
+        "audit",
+        &[
+            ("enabled", Kind::Bool),
+            ("file", Kind::Str),
+            ("syslog", Kind::Bool),
+        ],
+    ),
+    ("config", &[("auto_reload", Kind::Bool)]),
+    ("editor", &[("clipboard", Kind::Str), ("mouse", Kind::Bool)]),
+    (
+        "history",
+        &[
+            ("control", Kind::StrList),
+            ("encrypt", Kind::Bool),
+            ("file", Kind::Str),
+            ("file_size", Kind::Int),
+            ("fsync", Kind::Bool),
+            ("ignore", Kind::StrList),
+            ("passphrase_command", Kind::Str),
+            ("size", Kind::Int),
+            ("sync", Kind::Bool),
+        ],
+    ),
+    (
+        "hooks",
+        &[
+            ("chpwd", Kind::StrList),
+            ("exit", Kind::StrList),
+            ("job-finished", Kind::StrList),
+            ("logout", Kind::StrList),
+            ("precmd", Kind::StrList),
+            ("preexec", Kind::StrList),
+        ],
+    ),
+    (
+        "notify",
+        &[
+            ("after_ms", Kind::Int),
+            ("command", Kind::Str),
+            ("enabled", Kind::Bool),
+        ],
+    ),
+    (
+        "prompt",
+        &[
+            ("abbreviate", Kind::Bool),
+            ("command", Kind::Str),
+            ("continuation", Kind::Str),
+            ("directory_depth", Kind::Int),
+            ("duration_threshold_ms", Kind::Int),
+            ("eol_mark", Kind::Str),
+            ("format", Kind::Str),
+            ("segments", Kind::Any),
+            ("show_duration", Kind::Bool),
+        ],
+    ),
+    ("scripts", &[("files", Kind::StrList)]),
+    ("terminal", &[("integration", Kind::Bool)]),
+    ("theme", &[("name", Kind::Str)]),
+    (
+        "title",
+        &[
+            ("enabled", Kind::Bool),
+            ("idle", Kind::Str),
+            ("running", Kind::Str),
+        ],
+    ),
+];
+
+/// Sections whose keys are names the user picks.
+const FREE_SECTIONS: &[(&str, Kind)] = &[
+    ("aliases", Kind::Str),
+    ("bookmarks", Kind::Str),
+    ("env", Kind::Any),
+    ("keys", Kind::Str),
+];
+
+const SEGMENT_TYPES: &[&str] = &[
+    "cwd", "git", "python", "node", "rust", "duration", "status", "jobs", "command", "script",
+    "text", "newline",
+];
+
+const SEGMENT_KEYS: &[(&str, Kind)] = &[
+    ("bg", Kind::Str),
+    ("bold", Kind::Bool),
+    ("fg", Kind::Str),
+    ("format", Kind::Str),
+    ("name", Kind::Str),
+    ("run", Kind::Str),
+    ("separator", Kind::Str),
+    ("text", Kind::Str),
+    ("threshold_ms", Kind::Int),
+    ("type", Kind::Str),
+];
+
+/// Something wrong in a config or rc file.
+pub struct Problem {
+    file: PathBuf,
+    line: Option<usize>,
+    message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file.display(), line, self.message),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+/// Checks the config file at `config`, the rc file at `rc`, either of
+/// which may be missing, and the scripts the shell would load.
+pub fn check(config: &Path, rc: &Path) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut parsed = true;
+    if let Ok(source) = fs::read_to_string(config) {
+        parsed = source.parse::<Table>().is_ok();
+        Checker::new(config, &source, &mut problems).check_config();
+        problems.sort_by_key(|problem| problem.line);
+    }
+    if let Ok(source) = fs::read_to_string(rc) {
+        check_rc(rc, &source, &mut problems);
+    }
+    // Loading a config that does not parse would complain about it again.
+    if parsed {
+        for file in script::files(&Config::load(config)) {
+            let checked = fs::read_to_string(&file).map(|source| script::check(&source));
+            let (line, message) = match checked {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => (Some(e.line), e.message),
+                Err(e) => (None, e.to_string()),
+            };
+            problems.push(Problem {
+                file,
+                line,
+                message,
+            });
+        }
+    }
+    problems
+}
+
+struct Checker<'a> {
+    file: &'a Path,
+    source: &'a str,
+    problems: &'a mut Vec<Problem>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(file: &'a Path, source: &'a str, problems: &'a mut Vec<Problem>) -> Self {
+        Checker {
+            file,
+            source,
+            problems,
+        }
+    }
+
+    fn check_config(&mut self) {
+        let table = match self.source.parse::<Table>() {
+            Ok(table) => table,
+            Err(e) => {
+                let message = e.message().to_string();
+                let line = e
+                    .span()
+                    .map(|span| self.source[..span.start].lines().count().max(1));
+                self.report(line, message);
+                return;
+            }
+        };
+        self.check_tables(&table, &[]);
+        let Some(profiles) = table.get("profiles") else {
+            return;
+        };
+        let Some(profiles) = profiles.as_table() else {
+            return self.report_key(&[], "profiles", "expected a table of profiles");
+        };
+        for (name, profile) in profiles {
+            match profile.as_table() {
+                Some(profile) => self.check_tables(profile, &["profiles", name]),
+                None => self.report_key(&["profiles"], name, "expected a table"),
+            }
+        }
+    }
+
+    /// Checks the sections in `table`, found under the tables `at`.
+    fn check_tables(&mut self, table: &Table, at: &[&str]) {
+        for (name, value) in table {
+            if at.is_empty() && name == "profiles" {
+                continue;
+            }
+            let path = [at, &[name.as_str()]].concat();
+            let Some(section) = value.as_table() else {
+                self.report_key(at, name, "expected a table");
+                continue;
+            };
+            if let Some((_, keys)) = SECTIONS.iter().find(|(known, _)| known == name) {
+                self.check_section(section, &path, keys);
+            } else if let Some((_, kind)) = FREE_SECTIONS.iter().find(|(known, _)| known == name) {
+                for (key, value) in section {
+                    self.check_kind(&path, key, value, *kind);
+                }
+            } else {
+                self.report_header(&path, 0, format!("unknown section [{}]", name));
+            }
+        }
+    }
+
+    fn check_section(&mut self, section: &Table, path: &[&str], keys: &[(&str, Kind)]) {
+        let name = path[path.len() - 1];
+        for (key, value) in section {
+            let kind = keys
+                .iter()
+                .find(|(known, _)| known == key)
+                .map(|(_, kind)| *kind)
+                .or_else(|| {
+                    (name == "theme" && theme::ROLES.contains(&key.as_str())).then_some(Kind::Str)
+                });
+            match kind {
+                Some(kind) => self.check_kind(path, key, value, kind),
+                None => self.report_key(path, key, format!("unknown key {} in [{}]", key, name)),
+            }
+        }
+        match name {
+            "theme" => {
+                if let Some(theme) = section.get("name").and_then(Value::as_str) {
+                    if !theme::is_theme(theme) {
+                        self.report_key(path, "name", format!("unknown theme {}", theme));
+                    }
+                }
+            }
+            "prompt" => {
+                let format = section.get("format").and_then(Value::as_str);
+                if let Some(Err(e)) = format.map(check_template) {
+                    self.report_key(path, "format", format!("invalid template: {}", e));
+                }
+                if let Some(segments) = section.get("segments") {
+                    self.check_segments(segments, path);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_kind(&mut self, path: &[&str], key: &str, value: &Value, kind: Kind) {
+        if !kind.matches(value) {
+            return self.report_key(path, key, format!("{} must be {}", key, kind.name()));
+        }
+        if path.last() == Some(&"keys") {
+            if let Some(Err(e)) = value.as_str().map(|action| keymap::check(key, action)) {
+                self.report_key(path, key, format!("cannot bind key: {}", e));
+            }
+        }
+    }
+
+    fn check_segments(&mut self, segments: &Value, prompt: &[&str]) {
+        let path = [prompt, &["segments"]].concat();
+        let Some(segments) = segments.as_array() else {
+            return self.report_key(prompt, "segments", "segments must be a list of tables");
+        };
+        for (n, segment) in segments.iter().enumerate() {
+            let Some(segment) = segment.as_table() else {
+                self.report_header(&path, n, "a segment must be a table".to_string());
+                continue;
+            };
+            for (key, value) in segment {
+                match SEGMENT_KEYS.iter().find(|(known, _)| known == key) {
+                    Some((_, kind)) if !kind.matches(value) => {
+                        let line = self.locate(&path, n, Some(key));
+                        self.report(line, format!("{} must be {}", key, kind.name()));
+                    }
+                    Some(_) => {}
+                    None => {
+                        let line = self.locate(&path, n, Some(key));
+                        self.report(line, format!("unknown segment key {}", key));
+                    }
+                }
+            }
+            match segment.get("type").and_then(Value::as_str) {
+                Some(kind) if SEGMENT_TYPES.contains(&kind) => {}
+                Some(kind) => {
+                    let line = self.locate(&path, n, Some("type"));
+                    self.report(line, format!("unknown segment type {}", kind));
+                }
+                None => self.report_header(&path, n, "segment without a type".to_string()),
+            }
+        }
+    }
+
+    fn report_key(&mut self, path: &[&str], key: &str, message: impl Into<String>) {
+        let line = self.locate(path, 0, Some(key));
+        self.report(line, message.into());
+    }
+
+    fn report_header(&mut self, path: &[&str], occurrence: usize, message: String) {
+        let line = self.locate(path, occurrence, None);
+        self.report(line, message);
+    }
+
+    fn report(&mut self, line: Option<usize>, message: String) {
+        self.problems.push(Problem {
+            file: self.file.to_path_buf(),
+            line,
+            message,
+        });
+    }
+
+    /// The line number of `key` in the `occurrence`th table headed `path`,
+    /// or of the header itself. Keys written as dotted keys or in inline
+    /// tables are not found.
+    fn locate(&self, path: &[&str], occurrence: usize, key: Option<&str>) -> Option<usize> {
+        let mut header: Vec<String> = vec![];
+        let mut seen = 0;
+        for (number, line) in self.source.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with('[') {
+                let name = line.trim_start_matches('[');
+                let name = name.split(']').next().unwrap_or_default();
+                header = name.split('.').map(|part| unquote(part.trim())).collect();
+                if header == path {
+                    if key.is_none() && seen == occurrence {
+                        return Some(number + 1);
+                    }
+                    seen += 1;
+                }
+                continue;
+            }
+            let Some(key) = key else {
+                continue;
+            };
+            let Some((name, _)) = line.split_once('=') else {
+                continue;
+            };
+            if header == path && seen == occurrence + 1 && unquote(name.trim()) == key {
+                return Some(number + 1);
+            }
+            // Sections that only hold subtables have no header of their own.
+            if path.is_empty() && header.is_empty() && unquote(name.trim()) == key {
+                return Some(number + 1);
+            }
+        }
+        None
+    }
+}
+
+fn unquote(name: &str) -> String {
+    ['"', '\'']
+        .iter()
+        .find_map(|q| name.strip_prefix(*q)?.strip_suffix(*q))
+        .unwrap_or(name)
+        .to_string()
+}
+
+/// Checks the builtins in the rc file that take a fixed syntax, and that
+/// no line is left unfinished.
+fn check_rc(file: &Path, source: &str, problems: &mut Vec<Problem>) {
+    let parser = CommandParser::new();
+    let mut report = |line, message: String| {
+        problems.push(Problem {
+            file: file.to_path_buf(),
+            line: Some(line),
+            message,
+        })
+    };
+    let mut pending: Option<(String, parser::Continuation, usize)> = None;
+    for (number, line) in source.lines().enumerate() {
+        let (line, start) = match pending.take() {
+            Some((earlier, open, start)) => (open.join(&earlier) + line, start),
+            None if line.trim().is_empty() || line.trim_start().starts_with('#') => continue,
+            None => (line.to_string(), number + 1),
+        };
+        if let Some(open) = parser::continuation(&line) {
+            pending = Some((line, open, start));
+            continue;
+        }
+        for command in parser.split_pipeline(&line).into_iter().flatten() {
+            let parsed = parser.parse(command.trim());
+            match (parsed.command.as_str(), parsed.args.as_slice()) {
+                ("bind", [key, action]) => {
+                    if let Err(e) = keymap::check(key, action) {
+                        report(start, format!("bind: {}", e));
+                    }
+                }
+                ("bind", [_] | [_, _, _, ..]) => {
+                    report(start, "bind: usage: bind [key action]".to_string())
+                }
+                ("hook", [name, _, ..]) if name != "-c" && Hook::from_name(name).is_none() => {
+                    report(start, format!("hook: {}: no such hook", name))
+                }
+                ("export", args) => {
+                    for arg in args.iter().filter(|arg| !arg.contains('=')) {
+                        report(start, format!("export: {}: expected name=value", arg));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    if let Some((_, open, start)) = pending {
+        let what = match open.name() {
+            "" => "a trailing backslash".to_string(),
+            name => format!("an unfinished {}", name),
+        };
+        report(start, format!("the file ends in {}", what));
+    }
+}
@@ -0,0 +1,424 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::Config;
+
+type Aes256Ctr = ctr::Ctr128BE<aes::Aes256>;
+type HmacSha256 = Hmac<Sha256>;
+
+/// Header `openssl enc -salt` writes before the ciphertext, which is how
+/// history files were encrypted as a whole before records.
+const LEGACY_MAGIC: &[u8] = b"Salted__";
+/// Starts the first line of an encrypted history file, followed by the
+/// format version, the salt and a check of the key.
+const MAGIC: &str = "#a-shell-encrypted ";
+const VERSION: &str = "2";
+/// Authenticated with the key into the header, to tell a wrong passphrase
+/// from a damaged file.
+const CHECK: &[u8] = b"a-shell history";
+const NONCE_LEN: usize = 16;
+const TAG_LEN: usize = 32;
+const PASSPHRASE_VAR: &str = "A_SHELL_HISTORY_PASSPHRASE";
+const ITERATIONS: &str = "200000";
+
+/// A salt and the keys derived from it.
+type SaltedKeys = ([u8; 8], Keys);
+
+/// Encrypts the history file with AES-256 in CTR mode and authenticates
+/// each record with HMAC-SHA256, so a changed record is refused rather
+/// than recalled as some other command. The key is derived from a
+/// passphrase with PBKDF2 through the `openssl` command, once per session,
+/// and kept in memory.
+///
+/// The file is a header line with the salt, then one line per save with a
+/// fresh nonce, the ciphertext and its tag in hex, so saving appends a
+/// line rather than encrypting the file again.
+#[derive(Clone)]
+pub struct Cipher {
+    passphrase: String,
+    /// As configured, to tell whether a reloaded config asks for the same
+    /// cipher.
+    passphrase_command: Option<String>,
+    /// The key in use, shared between clones.
+    keys: Arc<Mutex<Option<SaltedKeys>>>,
+}
+
+impl Cipher {
+    /// Enabled with `[history] encrypt = true`. The passphrase comes from
+    /// `passphrase_command` (e.g. a keyring lookup such as `secret-tool
+    /// lookup service a-shell`), then `$A_SHELL_HISTORY_PASSPHRASE`, and
+    /// otherwise is asked for on the terminal.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if !config.get_bool("history", "encrypt").unwrap_or(false) {
+            return None;
+        }
+        let passphrase_command = config
+            .get_str("history", "passphrase_command")
+            .map(str::to_string);
+        let passphrase = passphrase_command
+            .as_deref()
+            .and_then(run_passphrase_command)
+            .or_else(|| env::var(PASSPHRASE_VAR).ok())
+            .or_else(|| read_passphrase().ok())
+            .filter(|passphrase| !passphrase.is_empty());
+        if passphrase.is_none() {
+            eprintln!("No history passphrase given; history will not be read or saved");
+        }
+        Some(Cipher {
+            passphrase: passphrase?,
+            passphrase_command,
+            keys: Arc::default(),
+        })
+    }
+
+    /// Whether `config` asks for this cipher, so it can be kept without
+    /// asking for the passphrase again.
+    pub fn matches(&self, config: &Config) -> bool {
+        config.get_bool("history", "encrypt").unwrap_or(false)
+            && config.get_str("history", "passphrase_command") == self.passphrase_command.as_deref()
+    }
+
+    /// A whole file holding `plain`: the header for the key in use, or a new
+    /// one, and a single record.
+    pub fn encrypt(&self, plain: &[u8]) -> io::Result<Vec<u8>> {
+        let (salt, keys) = self.keys(None)?;
+        let check = keys.mac(&[CHECK]).finalize().into_bytes();
+        let mut data =
+            format!("{}{} {} {}\n", MAGIC, VERSION, hex(&salt), hex(&check)).into_bytes();
+        data.extend(keys.seal(plain)?);
+        Ok(data)
+    }
+
+    /// Whether a file starting with `head` was written with the key in use,
+    /// so `encrypt_record` can append to it.
+    pub fn can_append(&self, head: &[u8]) -> bool {
+        let cached = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        match (parse_header(head), cached.as_ref()) {
+            (Some((salt, _)), Some((cached_salt, _))) => salt == *cached_salt,
+            _ => false,
+        }
+    }
+
+    /// A line to append to a file `can_append` accepts.
+    pub fn encrypt_record(&self, plain: &[u8]) -> io::Result<Vec<u8>> {
+        let (_, keys) = self.keys(None)?;
+        keys.seal(plain)
+    }
+
+    /// Decrypts a whole file, passing through files that were never
+    /// encrypted so turning encryption on keeps the existing history.
+    pub fn decrypt(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        if data.starts_with(LEGACY_MAGIC) {
+            return self.legacy_decrypt(data);
+        }
+        if !data.starts_with(MAGIC.as_bytes()) {
+            return Ok(data.to_vec());
+        }
+        let (salt, check) = parse_header(data).ok_or_else(damaged)?;
+        let (_, keys) = self.keys(Some(salt))?;
+        if keys.mac(&[CHECK]).verify_slice(&check).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "cannot decrypt history file (wrong passphrase?)",
+            ));
+        }
+        let start = data
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(data.len(), |i| i + 1);
+        self.decrypt_records(&data[start..])
+    }
+
+    /// Decrypts the records another session appended to a file already
+    /// read with `decrypt`.
+    pub fn decrypt_records(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let (_, keys) = self.cached_keys().ok_or_else(damaged)?;
+        let mut plain = vec![];
+        for line in data.split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+            plain.extend(keys.open(line)?);
+        }
+        Ok(plain)
+    }
+
+    fn cached_keys(&self) -> Option<SaltedKeys> {
+        self.keys.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// The keys for `salt`, or for the salt in use or a new one when
+    /// `None`, derived only when they are not the ones already cached.
+    fn keys(&self, salt: Option<[u8; 8]>) -> io::Result<SaltedKeys> {
+        let mut cached = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((cached_salt, keys)) = cached.as_ref() {
+            if salt.is_none_or(|salt| salt == *cached_salt) {
+                return Ok((*cached_salt, keys.clone()));
+            }
+        }
+        let salt = match salt {
+            Some(salt) => salt,
+            None => random()?,
+        };
+        let keys = Keys::new(&self.derive(&salt)?);
+        *cached = Some((salt, keys.clone()));
+        Ok((salt, keys))
+    }
+
+    /// Runs PBKDF2 through `openssl enc -P`, which prints the key it would
+    /// use without encrypting anything.
+    fn derive(&self, salt: &[u8; 8]) -> io::Result<[u8; 32]> {
+        let output = Command::new("openssl")
+            .args(["enc", "-aes-256-ctr", "-pbkdf2", "-iter", ITERATIONS])
+            .args(["-md", "sha256", "-S", &hex(salt), "-P"])
+            .args(["-pass", &format!("env:{}", PASSPHRASE_VAR)])
+            .env(PASSPHRASE_VAR, &self.passphrase)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| line.strip_prefix("key="))
+            .and_then(|key| unhex(key.trim().as_bytes()))
+            .and_then(|key| key.try_into().ok())
+            .filter(|_| output.status.success())
+            .ok_or_else(|| io::Error::other("cannot derive the history key with openssl"))
+    }
+
+    /// Decrypts a file `openssl enc -aes-256-cbc` encrypted as a whole.
+    fn legacy_decrypt(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut child = Command::new("openssl")
+            .args([
+                "enc",
+                "-aes-256-cbc",
+                "-pbkdf2",
+                "-iter",
+                ITERATIONS,
+                "-salt",
+                "-d",
+            ])
+            .args(["-pass", &format!("env:{}", PASSPHRASE_VAR)])
+            .env(PASSPHRASE_VAR, &self.passphrase)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        // Write from another thread so a full stdout pipe cannot deadlock us.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = data.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&input));
+        let output = child.wait_with_output()?;
+        writer.join().expect("writer thread panicked")?;
+        if !output.status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "cannot decrypt history file (wrong passphrase?)",
+            ));
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// The keys for encrypting and for authenticating records.
+#[derive(Clone)]
+struct Keys {
+    encryption: [u8; 32],
+    authentication: [u8; 32],
+}
+
+impl Keys {
+    /// Expands the key PBKDF2 derived into one key for each use.
+    fn new(derived: &[u8; 32]) -> Self {
+        let hkdf = Hkdf::<Sha256>::new(None, derived);
+        let mut keys = Keys {
+            encryption: [0; 32],
+            authentication: [0; 32],
+        };
+        hkdf.expand(b"a-shell history encryption", &mut keys.encryption)
+            .expect("32 bytes is a valid HKDF length");
+        hkdf.expand(b"a-shell history authentication", &mut keys.authentication)
+            .expect("32 bytes is a valid HKDF length");
+        keys
+    }
+
+    fn mac(&self, parts: &[&[u8]]) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.authentication).expect("HMAC takes any key length");
+        for part in parts {
+            mac.update(part);
+        }
+        mac
+    }
+
+    /// One line: a random nonce, `plain` encrypted with it and the tag of
+    /// both, in hex.
+    fn seal(&self, plain: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce: [u8; NONCE_LEN] = random()?;
+        let mut ciphertext = plain.to_vec();
+        Aes256Ctr::new(&self.encryption.into(), &nonce.into()).apply_keystream(&mut ciphertext);
+        let tag = self.mac(&[&nonce, &ciphertext]).finalize().into_bytes();
+        Ok(format!("{}{}{}\n", hex(&nonce), hex(&ciphertext), hex(&tag)).into_bytes())
+    }
+
+    /// The plaintext of a line `seal` wrote, checking its tag first.
+    fn open(&self, line: &[u8]) -> io::Result<Vec<u8>> {
+        let bytes = unhex(line)
+            .filter(|bytes| bytes.len() >= NONCE_LEN + TAG_LEN)
+            .ok_or_else(damaged)?;
+        let (nonce, rest) = bytes.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        self.mac(&[nonce, ciphertext])
+            .verify_slice(tag)
+            .map_err(|_| damaged())?;
+        let mut plain = ciphertext.to_vec();
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("split at the nonce length");
+        Aes256Ctr::new(&self.encryption.into(), &nonce.into()).apply_keystream(&mut plain);
+        Ok(plain)
+    }
+}
+
+/// The salt and key check of a header line, for the version written here.
+fn parse_header(data: &[u8]) -> Option<([u8; 8], Vec<u8>)> {
+    let line = data.split(|&b| b == b'\n').next()?;
+    let rest = line.strip_prefix(MAGIC.as_bytes())?;
+    let mut fields = rest.split(|&b| b == b' ').filter(|f| !f.is_empty());
+    if fields.next()? != VERSION.as_bytes() {
+        return None;
+    }
+    let salt = unhex(fields.next()?)?.try_into().ok()?;
+    let check = unhex(fields.next()?)?;
+    Some((salt, check))
+}
+
+fn damaged() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "history file is damaged or was changed",
+    )
+}
+
+fn random<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &[u8]) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    text.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn run_passphrase_command(command: &str) -> Option<String> {
+    let output = Command::new("sh")
+        .args(["-c", command])
+        .stderr(Stdio::inherit())
+        .output()
+        .ok()?;
+    output.status.success().then(|| {
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end_matches(['\r', '\n'])
+            .to_string()
+    })
+}
+
+/// Reads a line from the terminal without echoing it.
+fn read_passphrase() -> io::Result<String> {
+    eprint!("History passphrase: ");
+    enable_raw_mode()?;
+    let mut passphrase = Vec::new();
+    let mut byte = [0];
+    let result = loop {
+        match io::stdin().read(&mut byte) {
+            Ok(0) => break Ok(()),
+            Ok(_) => match byte[0] {
+                b'\r' | b'\n' => break Ok(()),
+                // Ctrl-C gives up on encryption rather than the shell.
+                3 => {
+                    passphrase.clear();
+                    break Ok(());
+                }
+                127 | 8 => {
+                    passphrase.pop();
+                }
+                b => passphrase.push(b),
+            },
+            Err(e) => break Err(e),
+        }
+    };
+    disable_raw_mode()?;
+    eprintln!();
+    result?;
+    Ok(String::from_utf8_lossy(&passphrase).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher() -> Cipher {
+        Cipher {
+            passphrase: "secret".to_string(),
+            passphrase_command: None,
+            keys: Arc::new(Mutex::new(Some(([7; 8], Keys::new(&[9; 32]))))),
+        }
+    }
+
+    #[test]
+    fn appended_records_decrypt_with_the_file() {
+        let cipher = cipher();
+        let mut file = cipher.encrypt(b"#1 - - -\nls\n").unwrap();
+        assert!(cipher.can_append(&file));
+        file.extend(cipher.encrypt_record(b"pwd\n").unwrap());
+        assert_eq!(cipher.decrypt(&file).unwrap(), b"#1 - - -\nls\npwd\n");
+        assert!(!String::from_utf8_lossy(&file).contains("pwd"));
+    }
+
+    #[test]
+    fn changed_records_are_refused() {
+        let cipher = cipher();
+        let mut file = cipher.encrypt(b"ls\n").unwrap();
+        let header_len = file.iter().position(|&b| b == b'\n').unwrap() + 1;
+        // Flip a bit of the ciphertext, just after the nonce.
+        let at = header_len + NONCE_LEN * 2;
+        file[at] = if file[at] == b'0' { b'1' } else { b'0' };
+        assert!(cipher.decrypt(&file).is_err());
+        assert!(cipher.decrypt_records(b"echo leaked\n").is_err());
+    }
+
+    #[test]
+    fn a_wrong_key_is_reported() {
+        let file = cipher().encrypt(b"ls\n").unwrap();
+        let other = Cipher {
+            keys: Arc::new(Mutex::new(Some(([7; 8], Keys::new(&[1; 32]))))),
+            ..cipher()
+        };
+        assert!(other.decrypt(&file).is_err());
+    }
+
+    #[test]
+    fn other_versions_are_not_read_as_plain_text() {
+        let file = b"#a-shell-encrypted 1 0707070707070707 00\nabcd\n";
+        assert!(cipher().decrypt(file).is_err());
+        assert!(!cipher().can_append(file));
+    }
+
+    #[test]
+    fn plain_files_pass_through() {
+        assert_eq!(cipher().decrypt(b"ls\n").unwrap(), b"ls\n");
+        assert!(!cipher().can_append(b"ls\n"));
+    }
+}
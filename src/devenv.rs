@@ -0,0 +1,58 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::Table;
+
+/// The active Python virtualenv or conda environment.
+pub fn python() -> Option<String> {
+    if let Some(venv) = env::var_os("VIRTUAL_ENV").filter(|venv| !venv.is_empty()) {
+        let venv = PathBuf::from(venv);
+        // Tools like poetry keep every virtualenv in a `.venv` of its
+        // project, so the project names it better.
+        let name = match venv.file_name()?.to_str()? {
+            ".venv" | "venv" => venv.parent()?.file_name()?,
+            _ => venv.file_name()?,
+        };
+        return Some(name.to_string_lossy().into_owned());
+    }
+    env::var("CONDA_DEFAULT_ENV")
+        .ok()
+        .filter(|conda| !conda.is_empty())
+}
+
+/// The Node version asked for by the nearest `.nvmrc`.
+pub fn node() -> Option<String> {
+    let nvmrc = find_upwards(&[".nvmrc"])?;
+    let version = fs::read_to_string(nvmrc).ok()?;
+    let version = version.lines().next()?.trim();
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+/// The Rust toolchain overriding the default here, from `RUSTUP_TOOLCHAIN`
+/// or the nearest `rust-toolchain.toml` or `rust-toolchain`.
+pub fn rust() -> Option<String> {
+    if let Some(toolchain) = env::var("RUSTUP_TOOLCHAIN").ok().filter(|t| !t.is_empty()) {
+        return Some(toolchain);
+    }
+    let file = find_upwards(&["rust-toolchain.toml", "rust-toolchain"])?;
+    let content = fs::read_to_string(file).ok()?;
+    // The old format is just the toolchain's name.
+    match content.parse::<Table>() {
+        Ok(table) => table
+            .get("toolchain")?
+            .get("channel")?
+            .as_str()
+            .map(str::to_string),
+        Err(_) => Some(content.trim().to_string()).filter(|name| !name.is_empty()),
+    }
+}
+
+/// The first of the files called `names` in the working directory or the
+/// closest of its parents that has one.
+pub fn find_upwards(names: &[&str]) -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+        .find(|path| Path::is_file(path))
+}
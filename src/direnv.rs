@@ -0,0 +1,266 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::devenv::find_upwards;
+
+/// Names of the files that set variables for a directory tree, the first
+/// taking precedence.
+const ENV_FILES: [&str; 2] = [".a-shell-env", ".env"];
+
+/// Variables set for a directory and everything below it, like direnv, by
+/// the nearest `.a-shell-env` or `.env` file. Its lines are `NAME=VALUE`,
+/// optionally after `export` and with the value in quotes. The file is
+/// read, never run.
+///
+/// Only files the user allowed with `allow` are loaded, and an edit needs
+/// another `allow`. Leaving the directory puts the variables back as they
+/// were.
+pub struct DirEnv {
+    /// Where the allowed and denied files are remembered.
+    trust_path: PathBuf,
+    trust: Vec<(PathBuf, Trust)>,
+    loaded: Option<Loaded>,
+    /// The file last reported as needing `allow`, so it is reported once.
+    reported: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Trust {
+    /// Allowed with the content that had this fingerprint.
+    Allowed(Fingerprint),
+    Denied,
+}
+
+struct Loaded {
+    file: PathBuf,
+    fingerprint: Fingerprint,
+    /// Each variable set, with its value from before.
+    previous: Vec<(String, Option<OsString>)>,
+}
+
+impl DirEnv {
+    pub fn load(trust_path: impl Into<PathBuf>) -> Self {
+        let trust_path = trust_path.into();
+        let trust = fs::read_to_string(&trust_path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(2, '|');
+                let trust = match fields.next()? {
+                    "deny" => Trust::Denied,
+                    // Entries from before SHA-256 are dropped, so those
+                    // files need another `allow`.
+                    allowed => Trust::Allowed(parse_fingerprint(allowed)?),
+                };
+                Some((PathBuf::from(fields.next()?), trust))
+            })
+            .collect();
+        DirEnv {
+            trust_path,
+            trust,
+            loaded: None,
+            reported: None,
+        }
+    }
+
+    /// Loads the variables of the file that applies in the working
+    /// directory, after putting back those of a file that no longer does.
+    pub fn update(&mut self) {
+        let file = find_upwards(&ENV_FILES);
+        let content = file.as_ref().and_then(|file| fs::read_to_string(file).ok());
+        let fingerprint = content.as_deref().map(fingerprint);
+        if let Some(loaded) = &self.loaded {
+            if Some(&loaded.file) == file.as_ref() && Some(loaded.fingerprint) == fingerprint {
+                return;
+            }
+        }
+        self.unload();
+        let (Some(file), Some(content), Some(fingerprint)) = (file, content, fingerprint) else {
+            self.reported = None;
+            return;
+        };
+        match self.trust_of(&file) {
+            Some(Trust::Allowed(allowed)) if allowed == fingerprint => {
+                self.reported = None;
+                let previous = set_vars(&file, &content);
+                let names = previous
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>();
+                println!("Loaded {}: {}", file.display(), names.join(" "));
+                self.loaded = Some(Loaded {
+                    file,
+                    fingerprint,
+                    previous,
+                });
+            }
+            Some(Trust::Denied) => {}
+            _ if self.reported.as_ref() == Some(&file) => {}
+            trust => {
+                let why = match trust {
+                    Some(_) => "changed since it was allowed",
+                    None => "is not allowed",
+                };
+                println!(
+                    "{} {}; run `allow` to load it or `deny` to ignore it",
+                    file.display(),
+                    why
+                );
+                self.reported = Some(file);
+            }
+        }
+    }
+
+    /// Allows the file that applies in the working directory as it is now.
+    pub fn allow(&mut self) -> io::Result<PathBuf> {
+        let file = current_file()?;
+        let content = fs::read_to_string(&file)?;
+        self.set_trust(file.clone(), Trust::Allowed(fingerprint(&content)))?;
+        Ok(file)
+    }
+
+    /// Stops the file that applies in the working directory from being
+    /// loaded or reported.
+    pub fn deny(&mut self) -> io::Result<PathBuf> {
+        let file = current_file()?;
+        self.set_trust(file.clone(), Trust::Denied)?;
+        Ok(file)
+    }
+
+    fn trust_of(&self, file: &Path) -> Option<Trust> {
+        self.trust
+            .iter()
+            .find(|(path, _)| path == file)
+            .map(|(_, trust)| *trust)
+    }
+
+    fn set_trust(&mut self, file: PathBuf, trust: Trust) -> io::Result<()> {
+        self.trust.retain(|(path, _)| *path != file);
+        self.trust.push((file, trust));
+        self.save()
+    }
+
+    /// Puts back the variables the loaded file changed.
+    fn unload(&mut self) {
+        let Some(loaded) = self.loaded.take() else {
+            return;
+        };
+        for (name, value) in loaded.previous.into_iter().rev() {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+        println!("Unloaded {}", loaded.file.display());
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.trust_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = self
+            .trust
+            .iter()
+            .map(|(path, trust)| match trust {
+                Trust::Allowed(fingerprint) => {
+                    let hex = fingerprint
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect::<String>();
+                    format!("{}|{}\n", hex, path.display())
+                }
+                Trust::Denied => format!("deny|{}\n", path.display()),
+            })
+            .collect::<String>();
+        fs::write(&self.trust_path, content)
+    }
+}
+
+fn current_file() -> io::Result<PathBuf> {
+    find_upwards(&ENV_FILES).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "no .a-shell-env or .env file here or above",
+        )
+    })
+}
+
+/// Sets the variables `content` assigns and returns them with their values
+/// from before. Lines that assign nothing are reported with `file`.
+fn set_vars(file: &Path, content: &str) -> Vec<(String, Option<OsString>)> {
+    let mut previous = vec![];
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((name, value)) = line.split_once('=').filter(|(name, _)| is_name(name)) else {
+            eprintln!("{}:{}: expected NAME=VALUE", file.display(), number + 1);
+            continue;
+        };
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|q| value.strip_prefix(*q)?.strip_suffix(*q))
+            .unwrap_or(value);
+        previous.push((name.to_string(), env::var_os(name)));
+        env::set_var(name, value);
+    }
+    previous
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The SHA-256 of a file's content.
+type Fingerprint = [u8; 32];
+
+/// The SHA-256 of `content`, to tell whether a file changed since it was
+/// allowed. Like direnv, a hash no one can find another content for.
+fn fingerprint(content: &str) -> Fingerprint {
+    Sha256::digest(content.as_bytes()).into()
+}
+
+fn parse_fingerprint(hex: &str) -> Option<Fingerprint> {
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut fingerprint = [0; 32];
+    for (byte, pair) in fingerprint.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(fingerprint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trust_survives_a_save_and_load_but_old_fingerprints_do_not() {
+        let trust_path = env::temp_dir().join(format!("a-shell-trust-{}", std::process::id()));
+        let file = PathBuf::from("/project/.env");
+        let content = "A=1\n";
+        fs::write(&trust_path, "cbf29ce484222325|/old/.env\n").unwrap();
+        let mut direnv = DirEnv::load(&trust_path);
+        assert!(direnv.trust_of(Path::new("/old/.env")).is_none());
+        direnv
+            .set_trust(file.clone(), Trust::Allowed(fingerprint(content)))
+            .unwrap();
+        let direnv = DirEnv::load(&trust_path);
+        assert!(direnv.trust_of(&file) == Some(Trust::Allowed(fingerprint(content))));
+        assert!(direnv.trust_of(&file) != Some(Trust::Allowed(fingerprint("A=2\n"))));
+        fs::remove_file(&trust_path).unwrap();
+    }
+}
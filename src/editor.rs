@@ -0,0 +1,78 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+pub enum WordCase {
+    Upper,
+    Lower,
+    Capitalize,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+/// Byte range of the word at or after `cursor`, skipping any leading
+/// non-word characters the way readline does.
+pub fn next_word(input: &str, cursor: usize) -> Option<(usize, usize)> {
+    let rest = &input[cursor..];
+    let start = cursor + rest.find(is_word_char)?;
+    let end = input[start..]
+        .find(|c: char| !is_word_char(c))
+        .map_or(input.len(), |i| start + i);
+    Some((start, end))
+}
+
+/// Start of the word before `cursor`, skipping trailing non-word characters.
+pub fn prev_word_start(input: &str, cursor: usize) -> usize {
+    let before = input[..cursor].trim_end_matches(|c: char| !is_word_char(c));
+    before.rfind(|c: char| !is_word_char(c)).map_or(0, |i| {
+        i + before[i..].chars().next().map_or(1, char::len_utf8)
+    })
+}
+
+/// Start of the whitespace-delimited word before `cursor`, as used by Ctrl-W.
+pub fn prev_unix_word_start(input: &str, cursor: usize) -> usize {
+    let before = input[..cursor].trim_end();
+    before.rfind(char::is_whitespace).map_or(0, |i| {
+        i + before[i..].chars().next().map_or(1, char::len_utf8)
+    })
+}
+
+/// Changes the case of the next word and returns the new cursor position,
+/// which is the end of that word.
+pub fn change_word_case(input: &mut String, cursor: usize, case: WordCase) -> usize {
+    let Some((start, end)) = next_word(input, cursor) else {
+        return input.len();
+    };
+    let word = &input[start..end];
+    let changed = match case {
+        WordCase::Upper => word.to_uppercase(),
+        WordCase::Lower => word.to_lowercase(),
+        WordCase::Capitalize => {
+            let mut chars = word.chars();
+            chars.next().map_or(String::new(), |first| {
+                first.to_uppercase().collect::<String>() + chars.as_str().to_lowercase().as_str()
+            })
+        }
+    };
+    let new_end = start + changed.len();
+    input.replace_range(start..end, &changed);
+    new_end
+}
+
+pub fn prev_grapheme(input: &str, cursor: usize) -> usize {
+    input[..cursor]
+        .grapheme_indices(true)
+        .next_back()
+        .map_or(0, |(i, _)| i)
+}
+
+pub fn next_grapheme(input: &str, cursor: usize) -> usize {
+    input[cursor..]
+        .graphemes(true)
+        .next()
+        .map_or(cursor, |g| cursor + g.len())
+}
+
+pub fn display_width(input: &str) -> u16 {
+    input.graphemes(true).count() as u16
+}
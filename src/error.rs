@@ -0,0 +1,97 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Why a command line could not run.
+#[derive(Debug)]
+pub enum ShellError {
+    /// The line could not be made sense of, such as a history expansion
+    /// with no matching entry.
+    Parse(String),
+    /// No builtin, alias or program in `$PATH` has this name.
+    CommandNotFound(String),
+    /// The file cannot be run or read by this user.
+    PermissionDenied(PathBuf),
+    /// A file named in a redirection could not be opened.
+    Redirect(PathBuf, io::Error),
+    /// Cut short by Ctrl-C.
+    Interrupted,
+    /// A builtin was used wrongly: its name and what was wrong.
+    Builtin {
+        name: &'static str,
+        message: String,
+    },
+    Io(io::Error),
+}
+
+impl ShellError {
+    pub fn builtin(name: &'static str, message: impl Into<String>) -> Self {
+        ShellError::Builtin {
+            name,
+            message: message.into(),
+        }
+    }
+
+    /// The exit status a command line that failed this way gets, as in
+    /// POSIX shells.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ShellError::CommandNotFound(_) => 127,
+            ShellError::PermissionDenied(_) => 126,
+            ShellError::Interrupted => 130,
+            _ => 1,
+        }
+    }
+
+    /// What to do about the error, when there is something to suggest.
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            ShellError::CommandNotFound(name) if name.contains('/') => {
+                Some("check the path for typos".to_string())
+            }
+            ShellError::CommandNotFound(_) => {
+                Some("check the spelling, or that its directory is in $PATH".to_string())
+            }
+            ShellError::PermissionDenied(path) => Some(format!(
+                "check `ls -l {}`; scripts need `chmod +x` to run",
+                path.display()
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShellError::Parse(message) => write!(f, "{}", message),
+            ShellError::CommandNotFound(name) => write!(f, "Command not found: {}", name),
+            ShellError::PermissionDenied(path) => {
+                write!(f, "Permission denied: {}", path.display())
+            }
+            ShellError::Redirect(path, e) => write!(f, "Cannot open {}: {}", path.display(), e),
+            ShellError::Interrupted => write!(f, "Interrupted"),
+            ShellError::Builtin { name, message } => write!(f, "{}: {}", name, message),
+            ShellError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ShellError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ShellError::Io(e) | ShellError::Redirect(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ShellError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::Interrupted => ShellError::Interrupted,
+            _ => ShellError::Io(e),
+        }
+    }
+}
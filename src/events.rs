@@ -0,0 +1,100 @@
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use crossterm::event::{self, Event};
+
+use crate::{autocomplete::Suggestion, history::Entry};
+
+pub enum ShellEvent {
+    Terminal(Event),
+    /// Another session wrote to the history file.
+    HistoryChanged,
+    /// The config file was written, with `auto_reload` on.
+    ConfigChanged,
+    /// No more input will come.
+    InputClosed,
+    /// Older history parsed in the background after startup.
+    HistoryLoaded(Vec<Entry>),
+    /// A completion worker finished with `line`, the text that was before
+    /// the cursor.
+    Completed {
+        line: String,
+        completed: String,
+        completions: Vec<Suggestion>,
+    },
+}
+
+/// Delivers terminal input (and, later, results from background work) over
+/// a single channel so the input loop can block until something happens.
+/// Input can come from an iterator of events instead of the terminal.
+///
+/// The reader thread only reads an event after being handed a permit.
+/// Permits are issued while the line editor waits for input, so the thread
+/// never steals keystrokes meant for a foreground child process.
+pub struct EventLoop {
+    sender: Sender<ShellEvent>,
+    receiver: Receiver<ShellEvent>,
+    permits: Sender<()>,
+    reader_armed: bool,
+}
+
+impl EventLoop {
+    pub fn new(mut input: Option<Box<dyn Iterator<Item = Event> + Send>>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let (permits, permit_receiver) = mpsc::channel::<()>();
+
+        let reader_sender = sender.clone();
+        thread::spawn(move || {
+            let sender = reader_sender;
+            for () in permit_receiver {
+                let event = match &mut input {
+                    Some(input) => input.next(),
+                    None => event::read().ok(),
+                };
+                let Some(event) = event else {
+                    let _ = sender.send(ShellEvent::InputClosed);
+                    break;
+                };
+                if sender.send(ShellEvent::Terminal(event)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        EventLoop {
+            sender,
+            receiver,
+            permits,
+            reader_armed: false,
+        }
+    }
+
+    /// For background threads to post their own events.
+    pub fn sender(&self) -> Sender<ShellEvent> {
+        self.sender.clone()
+    }
+
+    /// Blocks until the next event arrives.
+    pub fn next(&mut self) -> io::Result<ShellEvent> {
+        if !self.reader_armed {
+            self.permits
+                .send(())
+                .map_err(|_| io::Error::other("terminal reader stopped"))?;
+            self.reader_armed = true;
+        }
+        let event = self
+            .receiver
+            .recv()
+            .map_err(|_| io::Error::other("terminal reader stopped"))?;
+        match event {
+            ShellEvent::Terminal(_) => self.reader_armed = false,
+            ShellEvent::HistoryChanged
+            | ShellEvent::ConfigChanged
+            | ShellEvent::InputClosed
+            | ShellEvent::HistoryLoaded(_)
+            | ShellEvent::Completed { .. } => {}
+        }
+        Ok(event)
+    }
+}
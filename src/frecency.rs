@@ -0,0 +1,103 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::clock;
+
+/// Once the ranks add up to more than this, they all decay so old
+/// favourites eventually make room for new ones.
+const MAX_TOTAL_RANK: f64 = 9000.0;
+
+/// Directories changed to with `cd`, ranked by how often and how recently
+/// they were visited, like z. Stored as `path|rank|time` lines, the format
+/// z uses.
+pub struct Directories {
+    path: PathBuf,
+    visits: Vec<Visit>,
+}
+
+struct Visit {
+    directory: PathBuf,
+    rank: f64,
+    /// Last visit, in seconds since the Unix epoch.
+    time: u64,
+}
+
+impl Directories {
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let visits = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.rsplitn(3, '|');
+                let time = fields.next()?.parse().ok()?;
+                let rank = fields.next()?.parse().ok()?;
+                let directory = PathBuf::from(fields.next()?);
+                Some(Visit {
+                    directory,
+                    rank,
+                    time,
+                })
+            })
+            .collect();
+        Directories { path, visits }
+    }
+
+    /// Counts a visit to `directory` and saves the list.
+    pub fn visit(&mut self, directory: &Path) -> io::Result<()> {
+        let now = clock::now();
+        match self.visits.iter_mut().find(|v| v.directory == directory) {
+            Some(visit) => {
+                visit.rank += 1.0;
+                visit.time = now;
+            }
+            None => self.visits.push(Visit {
+                directory: directory.to_path_buf(),
+                rank: 1.0,
+                time: now,
+            }),
+        }
+        if self.visits.iter().map(|v| v.rank).sum::<f64>() > MAX_TOTAL_RANK {
+            self.visits.iter_mut().for_each(|v| v.rank *= 0.99);
+            self.visits.retain(|v| v.rank >= 1.0);
+        }
+        self.save()
+    }
+
+    /// The visited directories that still exist, best first.
+    pub fn ranked(&self) -> Vec<PathBuf> {
+        let now = clock::now();
+        let mut visits = self
+            .visits
+            .iter()
+            .filter(|v| v.directory.is_dir())
+            .map(|v| (frecency(v, now), &v.directory))
+            .collect::<Vec<_>>();
+        visits.sort_by(|a, b| b.0.total_cmp(&a.0));
+        visits.into_iter().map(|(_, d)| d.clone()).collect()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = self
+            .visits
+            .iter()
+            .map(|v| format!("{}|{}|{}\n", v.directory.display(), v.rank, v.time))
+            .collect::<String>();
+        fs::write(&self.path, content)
+    }
+}
+
+/// The rank weighted by how long ago the last visit was, as z does it.
+fn frecency(visit: &Visit, now: u64) -> f64 {
+    let age = now.saturating_sub(visit.time);
+    match age {
+        0..3600 => visit.rank * 4.0,
+        3600..86_400 => visit.rank * 2.0,
+        86_400..604_800 => visit.rank / 2.0,
+        _ => visit.rank / 4.0,
+    }
+}
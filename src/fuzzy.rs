@@ -0,0 +1,75 @@
+pub struct FuzzyMatch {
+    pub score: i64,
+    /// Char indices of the matched characters in the candidate.
+    pub positions: Vec<usize>,
+}
+
+/// Matches `pattern` as a subsequence of `candidate`, fzf style: the match
+/// is tightened to the shortest window ending at the first complete match,
+/// and scored higher for consecutive characters and word starts. Matching
+/// is case-insensitive unless the pattern contains an uppercase letter.
+pub fn fuzzy_match(pattern: &str, candidate: &str) -> Option<FuzzyMatch> {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    if pattern.is_empty() {
+        return Some(FuzzyMatch {
+            score: 0,
+            positions: vec![],
+        });
+    }
+    let case_sensitive = pattern.iter().any(|c| c.is_uppercase());
+    let eq = |a: char, b: char| {
+        if case_sensitive {
+            a == b
+        } else {
+            a.to_lowercase().eq(b.to_lowercase())
+        }
+    };
+    let chars = candidate.chars().collect::<Vec<_>>();
+
+    let mut matched = 0;
+    let mut end = None;
+    for (i, &c) in chars.iter().enumerate() {
+        if eq(c, pattern[matched]) {
+            matched += 1;
+            if matched == pattern.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end = end?;
+
+    let mut start = end;
+    let mut remaining = pattern.len();
+    for i in (0..=end).rev() {
+        if eq(chars[i], pattern[remaining - 1]) {
+            remaining -= 1;
+            if remaining == 0 {
+                start = i;
+                break;
+            }
+        }
+    }
+
+    let mut positions = Vec::with_capacity(pattern.len());
+    for (i, &c) in chars.iter().enumerate().take(end + 1).skip(start) {
+        if positions.len() < pattern.len() && eq(c, pattern[positions.len()]) {
+            positions.push(i);
+        }
+    }
+
+    let mut score = 0;
+    for (k, &p) in positions.iter().enumerate() {
+        score += 16;
+        if k > 0 && positions[k - 1] + 1 == p {
+            score += 12;
+        }
+        if p == 0 || !chars[p - 1].is_alphanumeric() {
+            score += 8;
+        }
+    }
+    score -= (end - start + 1 - pattern.len()) as i64 * 2;
+    score -= chars.len() as i64 / 8;
+
+    Some(FuzzyMatch { score, positions })
+}
@@ -0,0 +1,151 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::output::run_for_output;
+
+/// How long a status is reused while HEAD and the index stay unchanged.
+/// Edits to the working tree only show up after this.
+const STATUS_TTL: Duration = Duration::from_secs(5);
+
+/// What the prompt shows about the repository around the working directory.
+#[derive(Clone, Default)]
+pub struct GitStatus {
+    /// The branch, or the short commit hash when detached.
+    pub branch: String,
+    pub ahead: u32,
+    pub behind: u32,
+    /// Uncommitted changes or untracked files.
+    pub dirty: bool,
+}
+
+impl GitStatus {
+    /// Like `main ↑1 ↓2 *`, leaving out what does not apply.
+    pub fn summary(&self) -> String {
+        let mut summary = self.branch.clone();
+        if self.ahead > 0 {
+            summary.push_str(&format!(" ↑{}", self.ahead));
+        }
+        if self.behind > 0 {
+            summary.push_str(&format!(" ↓{}", self.behind));
+        }
+        if self.dirty {
+            summary.push_str(" *");
+        }
+        summary
+    }
+}
+
+/// Runs `git status` at most once per repository state and `STATUS_TTL`.
+#[derive(Default)]
+pub struct GitCache {
+    cached: Option<(CacheKey, Instant, Option<GitStatus>)>,
+}
+
+#[derive(PartialEq)]
+struct CacheKey {
+    git_dir: PathBuf,
+    head: Option<SystemTime>,
+    index: Option<SystemTime>,
+}
+
+impl GitCache {
+    /// The status of the repository around the working directory, or `None`
+    /// outside one.
+    pub fn status(&mut self) -> Option<GitStatus> {
+        let git_dir = git_dir()?;
+        let modified = |name: &str| {
+            fs::metadata(git_dir.join(name))
+                .and_then(|m| m.modified())
+                .ok()
+        };
+        let key = CacheKey {
+            head: modified("HEAD"),
+            index: modified("index"),
+            git_dir,
+        };
+        if let Some((cached_key, at, status)) = &self.cached {
+            if *cached_key == key && at.elapsed() < STATUS_TTL {
+                return status.clone();
+            }
+        }
+        let status = query_status().or_else(|| {
+            Some(GitStatus {
+                branch: read_branch(&key.git_dir)?,
+                ..GitStatus::default()
+            })
+        });
+        self.cached = Some((key, Instant::now(), status.clone()));
+        status
+    }
+}
+
+/// Asks git itself, which also knows about upstream and working tree.
+fn query_status() -> Option<GitStatus> {
+    let output =
+        run_for_output(Command::new("git").args(["status", "--porcelain=v2", "--branch"]))?;
+    let mut status = GitStatus::default();
+    let mut commit = String::new();
+    for line in output.lines() {
+        if let Some(head) = line.strip_prefix("# branch.head ") {
+            status.branch = head.to_string();
+        } else if let Some(oid) = line.strip_prefix("# branch.oid ") {
+            commit = oid.chars().take(7).collect();
+        } else if let Some(counts) = line.strip_prefix("# branch.ab ") {
+            for count in counts.split_whitespace() {
+                if let Some(ahead) = count.strip_prefix('+') {
+                    status.ahead = ahead.parse().unwrap_or(0);
+                } else if let Some(behind) = count.strip_prefix('-') {
+                    status.behind = behind.parse().unwrap_or(0);
+                }
+            }
+        } else if !line.starts_with('#') {
+            status.dirty = true;
+        }
+    }
+    if status.branch.is_empty() {
+        return None;
+    }
+    if status.branch == "(detached)" {
+        status.branch = commit;
+    }
+    Some(status)
+}
+
+/// The checked-out branch, or the short commit hash when detached, read
+/// straight from the repository.
+pub fn branch() -> Option<String> {
+    read_branch(&git_dir()?)
+}
+
+fn read_branch(git_dir: &Path) -> Option<String> {
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(
+            reference
+                .strip_prefix("refs/heads/")
+                .unwrap_or(reference)
+                .to_string(),
+        ),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+/// The `.git` directory of the repository around the working directory,
+/// following the `gitdir:` file a worktree has instead.
+fn git_dir() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    let dot_git = cwd
+        .ancestors()
+        .map(|dir| dir.join(".git"))
+        .find(|dot_git| dot_git.exists())?;
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    let content = fs::read_to_string(&dot_git).ok()?;
+    let path = Path::new(content.trim().strip_prefix("gitdir: ")?);
+    Some(dot_git.parent()?.join(path))
+}
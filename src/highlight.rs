@@ -0,0 +1,204 @@
+use std::fs;
+use std::ops::Range;
+
+use crate::{
+    alias::Aliases,
+    command_index::{is_executable, CommandIndex},
+    completer::expand_directory,
+    completion_context::COMMAND_PREFIXES,
+    shell::BUILTINS,
+    theme::{Role, Theme},
+};
+
+/// Colors the input as it is typed: command names by whether they exist,
+/// arguments that are existing paths, the bracket or quote matching the one
+/// at the cursor, and quotes and brackets left unmatched.
+pub struct Highlighter {
+    commands: CommandIndex,
+}
+
+/// A word of the line, split the way the parser will split it.
+struct Word {
+    /// Where the word is in the line as typed.
+    range: Range<usize>,
+    /// The word with quotes and escapes removed.
+    text: String,
+    /// Whether the word names the command to run.
+    is_command: bool,
+}
+
+impl Highlighter {
+    pub fn new() -> Self {
+        Highlighter {
+            commands: CommandIndex::new(),
+        }
+    }
+
+    /// SGR parameters for the parts of `line` that get a style, with the
+    /// cursor at byte `cursor`. Earlier ranges take precedence.
+    pub fn highlight(
+        &mut self,
+        line: &str,
+        cursor: usize,
+        aliases: &Aliases,
+        theme: &Theme,
+    ) -> Vec<(Range<usize>, String)> {
+        let mut highlights = vec![];
+        let pairs = pairs(line);
+        // The character under the cursor wins over the one before it.
+        let at_cursor = [Some(cursor), cursor.checked_sub(1)]
+            .into_iter()
+            .flatten()
+            .find_map(|at| {
+                pairs.iter().find_map(|&(open, close)| match close {
+                    Some(close) if open == at => Some(close),
+                    Some(close) if close == at => Some(open),
+                    _ => None,
+                })
+            });
+        if let Some(partner) = at_cursor {
+            highlights.push((partner..partner + 1, theme.style(Role::Match).to_string()));
+        }
+        for (open, close) in pairs {
+            if close.is_none() {
+                highlights.push((open..open + 1, theme.style(Role::Error).to_string()));
+            }
+        }
+        for word in words(line) {
+            let role = if word.is_command {
+                if self.is_known(&word.text, aliases) {
+                    Role::Command
+                } else {
+                    Role::Error
+                }
+            } else if !word.text.is_empty()
+                && fs::symlink_metadata(expand_directory(&word.text)).is_ok()
+            {
+                Role::Path
+            } else {
+                continue;
+            };
+            highlights.push((word.range, theme.style(role).to_string()));
+        }
+        highlights.retain(|(_, style)| !style.is_empty());
+        highlights
+    }
+
+    /// Whether `name` runs something: a builtin, an alias, a program in
+    /// `$PATH` or the path of an executable.
+    fn is_known(&mut self, name: &str, aliases: &Aliases) -> bool {
+        if name.contains('/') {
+            return is_executable(&expand_directory(name));
+        }
+        BUILTINS.contains(&name)
+            || aliases.get(name).is_some()
+            || self
+                .commands
+                .commands()
+                .binary_search_by(|command| command.as_str().cmp(name))
+                .is_ok()
+    }
+}
+
+/// Splits `line` into words. The first word of each command, after
+/// variable assignments, names the command, and so does the first one after
+/// a prefix command such as `sudo` and its flags.
+fn words(line: &str) -> Vec<Word> {
+    let mut words = vec![];
+    let mut expect_command = true;
+    let mut after_prefix = false;
+    for (range, text, starts_command) in split(line) {
+        if starts_command {
+            expect_command = true;
+            after_prefix = false;
+        }
+        let is_assignment = text.find('=').is_some_and(|at| at > 0);
+        let is_flag = after_prefix && text.starts_with('-');
+        let is_command = expect_command && !is_assignment && !is_flag;
+        if is_command {
+            after_prefix = COMMAND_PREFIXES.contains(&text.as_str());
+            expect_command = after_prefix;
+        }
+        words.push(Word {
+            range,
+            text,
+            is_command,
+        });
+    }
+    words
+}
+
+/// Byte offsets of the quotes and brackets in `line`, each with the one
+/// closing it. Unmatched closing brackets are listed without a partner, as
+/// are quotes left open. Brackets inside quotes are text.
+fn pairs(line: &str) -> Vec<(usize, Option<usize>)> {
+    let mut pairs = vec![];
+    let mut open = vec![];
+    let mut quote: Option<(char, usize)> = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (c, quote) {
+            ('\\', Some(('\'', _))) => {}
+            ('\\', _) => {
+                chars.next();
+            }
+            (_, Some((q, start))) if c == q => {
+                pairs.push((start, Some(i)));
+                quote = None;
+            }
+            (_, Some(_)) => {}
+            ('"' | '\'', None) => quote = Some((c, i)),
+            ('(' | '[' | '{', None) => open.push((c, i)),
+            (')' | ']' | '}', None) => {
+                let opener = match c {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                match open.last() {
+                    Some(&(o, start)) if o == opener => {
+                        open.pop();
+                        pairs.push((start, Some(i)));
+                    }
+                    _ => pairs.push((i, None)),
+                }
+            }
+            _ => {}
+        }
+    }
+    pairs.extend(quote.map(|(_, start)| (start, None)));
+    pairs.extend(open.into_iter().map(|(_, start)| (start, None)));
+    pairs
+}
+
+/// The words of `line` with quotes and escapes removed, each with whether
+/// a pipe or separator comes before it.
+fn split(line: &str) -> Vec<(Range<usize>, String, bool)> {
+    let mut words = vec![];
+    let mut current: Option<(usize, String)> = None;
+    let mut starts_command = true;
+    let mut quote = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if quote.is_none() && (c.is_whitespace() || matches!(c, '|' | ';' | '&')) {
+            if let Some((start, text)) = current.take() {
+                words.push((start..i, text, std::mem::take(&mut starts_command)));
+            }
+            if !c.is_whitespace() {
+                starts_command = true;
+            }
+            continue;
+        }
+        let (_, text) = current.get_or_insert_with(|| (i, String::new()));
+        match c {
+            '\\' if quote != Some('\'') => text.extend(chars.next().map(|(_, next)| next)),
+            '"' | '\'' if quote == Some(c) => quote = None,
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            _ => text.push(c),
+        }
+    }
+    if let Some((start, text)) = current {
+        words.push((start..line.len(), text, starts_command));
+    }
+    words
+}
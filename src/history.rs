@@ -1,119 +1,972 @@
 use std::{
-    fs::File,
-    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
-    path::PathBuf,
+    collections::HashSet,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
+use crate::{clock, config::Config, crypt::Cipher, json};
+
+pub struct HistoryConfig {
+    /// Entries kept in memory (bash's HISTSIZE).
+    pub size: usize,
+    /// Entries kept in the history file (bash's HISTFILESIZE).
+    pub file_size: usize,
+    /// Skip a command identical to the previous one.
+    pub ignore_dups: bool,
+    /// Remove all earlier copies of a command when it is entered again. The
+    /// file keeps them until it next grows past `file_size`.
+    pub erase_dups: bool,
+    /// Skip commands starting with a space.
+    pub ignore_space: bool,
+    /// Glob patterns (`*` and `?`) matched against the whole command, like
+    /// bash's HISTIGNORE.
+    pub ignore: Vec<String>,
+    /// Flush each save to disk before returning.
+    pub fsync: bool,
+    /// Keeps the file encrypted, each save appending one encrypted record.
+    pub cipher: Option<Cipher>,
+    /// Encryption is on but no passphrase was given, so the file is
+    /// neither read nor written and the session is private.
+    pub locked: bool,
+    /// Merge commands other sessions write to the file as they happen.
+    pub sync: bool,
+}
+
+impl HistoryConfig {
+    pub fn from_config(config: &Config) -> Self {
+        Self::keeping_cipher(config, None)
+    }
+
+    /// Like `from_config`, but keeps `cipher` while the config still asks
+    /// for it, rather than asking for the passphrase again.
+    pub fn keeping_cipher(config: &Config, cipher: Option<&Cipher>) -> Self {
+        let limit = |key, default| {
+            config
+                .get_int("history", key)
+                .map_or(default, |n| n.max(0) as usize)
+        };
+        // Mirrors bash's HISTCONTROL, e.g. `control = ["erasedups"]`.
+        let control = config
+            .get_str_list("history", "control")
+            .unwrap_or_else(|| vec!["ignoredups"]);
+        let cipher = match cipher {
+            Some(cipher) if cipher.matches(config) => Some(cipher.clone()),
+            _ => Cipher::from_config(config),
+        };
+        HistoryConfig {
+            size: limit("size", 1000),
+            file_size: limit("file_size", 2000),
+            ignore_dups: control.contains(&"ignoredups"),
+            erase_dups: control.contains(&"erasedups"),
+            ignore_space: control.contains(&"ignorespace"),
+            ignore: config
+                .get_str_list("history", "ignore")
+                .unwrap_or_default()
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            fsync: config.get_bool("history", "fsync").unwrap_or(false),
+            locked: cipher.is_none() && config.get_bool("history", "encrypt").unwrap_or(false),
+            cipher,
+            sync: config.get_bool("history", "sync").unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Entry {
+    pub command: String,
+    /// When the command was entered, in seconds since the Unix epoch.
+    pub time: Option<u64>,
+    /// Wall-clock run time in milliseconds.
+    pub duration: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub cwd: Option<String>,
+    /// Entered in private mode, so never written to the file.
+    pub private: bool,
+}
+
+/// Bytes read from the end of the file at startup. Anything before that is
+/// parsed in the background through `older_loader`.
+const STARTUP_READ: u64 = 256 * 1024;
+
+/// Command history, newest first in memory and oldest first on disk so new
+/// commands can simply be appended to the file. On disk each command may be
+/// preceded by a `#<epoch> <duration> <exit code> <cwd>` line; bash reads
+/// the leading `#<epoch>` as its own timestamp format.
 pub struct History {
     path: PathBuf,
-    reader: LineReader,
-    pub commands: Vec<String>,
-    new_commands_count: u32,
+    config: HistoryConfig,
+    pub entries: Vec<Entry>,
+    new_commands_count: usize,
+    file_entries: usize,
+    /// Bytes of the file already read or written by this session.
+    file_len: u64,
+    /// Length of the start of the file left unread at startup.
+    unloaded: u64,
+    /// Whether entries from the unread start are still expected.
+    older_pending: bool,
+    /// Whether the newest entry is still waiting for `record_result`.
+    pending_result: bool,
+    /// Keeps new commands in memory only, for the `private` builtin.
+    pub private: bool,
 }
 
 impl History {
-    pub fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+    pub fn new(path: impl Into<PathBuf>, config: HistoryConfig) -> io::Result<Self> {
         let path = path.into();
 
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
         if !path.exists() {
             File::create(&path)?;
         }
 
-        let mut reader = LineReader::new(&path)?;
-        let commands = reader.read_lines(100)?;
-
-        Ok(Self {
+        let locked = config.locked;
+        let mut history = Self {
             path,
-            commands,
-            reader,
+            config,
+            entries: vec![],
             new_commands_count: 0,
+            file_entries: 0,
+            file_len: 0,
+            unloaded: 0,
+            older_pending: false,
+            pending_result: false,
+            private: locked,
+        };
+        if !locked {
+            history.read_tail()?;
+        }
+        Ok(history)
+    }
+
+    /// Reads just the end of a large file so startup stays fast.
+    fn read_tail(&mut self) -> io::Result<()> {
+        let mut file = File::open(&self.path)?;
+        let len = file.metadata()?.len();
+        if self.config.cipher.is_some() || len <= STARTUP_READ {
+            return self.read_file();
+        }
+        let tail_start = len - STARTUP_READ;
+        file.seek(SeekFrom::Start(tail_start))?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+
+        let start = entry_boundary(&tail);
+        let mut entries = parse_entries(&String::from_utf8_lossy(&tail[start..]));
+        self.file_entries = entries.len();
+        if self.config.erase_dups {
+            erase_dups(&mut entries, HashSet::new());
+        }
+        self.entries = entries
+            .iter()
+            .rev()
+            .take(self.config.size)
+            .cloned()
+            .collect();
+        self.file_len = tail_start + tail.len() as u64;
+        // Read the rest even with memory full, as it counts towards
+        // `file_size`.
+        self.unloaded = tail_start + start as u64;
+        self.older_pending = true;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn cipher(&self) -> Option<&Cipher> {
+        self.config.cipher.as_ref()
+    }
+
+    /// Hands out, once, a job that parses the part of the file skipped at
+    /// startup. Its result goes to `add_older`.
+    pub fn older_loader(&mut self) -> Option<impl FnOnce() -> Vec<Entry> + Send + 'static> {
+        let end = std::mem::take(&mut self.unloaded);
+        if end == 0 {
+            return None;
+        }
+        let path = self.path.clone();
+        Some(move || {
+            let mut head = Vec::new();
+            let read = File::open(path).and_then(|file| file.take(end).read_to_end(&mut head));
+            match read {
+                Ok(_) => parse_entries(&String::from_utf8_lossy(&head)),
+                Err(_) => vec![],
+            }
         })
     }
 
-    pub fn add_command(&mut self, command: &str) {
-        if self.commands.first().map_or("", |f| f) != command {
-            self.commands.insert(0, command.to_string());
-            self.new_commands_count += 1;
+    /// Appends entries older than everything loaded so far, so positions
+    /// into the history stay valid.
+    pub fn add_older(&mut self, mut older: Vec<Entry>) {
+        // A reload in the meantime already read the whole file.
+        if !std::mem::take(&mut self.older_pending) {
+            return;
+        }
+        self.file_entries += older.len();
+        if self.config.erase_dups {
+            let seen = self.entries.iter().map(|e| e.command.clone()).collect();
+            erase_dups(&mut older, seen);
         }
+        let room = self.config.size.saturating_sub(self.entries.len());
+        self.entries.extend(older.into_iter().rev().take(room));
     }
 
-    pub fn get_command(&self, index: usize) -> Option<&String> {
-        self.commands.get(index)
+    fn read_file(&mut self) -> io::Result<()> {
+        if self.config.locked {
+            return Ok(());
+        }
+        let data = fs::read(&self.path)?;
+        self.file_len = data.len() as u64;
+        let mut file_entries = parse_entries(&self.decode(data)?);
+        self.file_entries = file_entries.len();
+        if self.config.erase_dups {
+            erase_dups(&mut file_entries, HashSet::new());
+        }
+        self.entries = file_entries
+            .iter()
+            .rev()
+            .take(self.config.size)
+            .cloned()
+            .collect();
+        self.pending_result = false;
+        self.older_pending = false;
+        Ok(())
+    }
+
+    /// Merges commands other sessions appended to the file since this one
+    /// last read it. They go after our own unsaved commands; the number
+    /// added there is returned so positions into the history can follow.
+    /// A file that was rewritten instead is read again from scratch.
+    pub fn sync(&mut self) -> io::Result<usize> {
+        if self.config.locked {
+            return Ok(0);
+        }
+        let len = fs::metadata(&self.path)?.len();
+        if len == self.file_len {
+            return Ok(0);
+        }
+        let unsaved = self.new_commands_count.min(self.entries.len());
+        if len < self.file_len || !self.appendable()? {
+            let unsaved = self.entries.drain(..unsaved).collect::<Vec<_>>();
+            self.read_file()?;
+            self.entries.splice(..0, unsaved);
+            self.entries.truncate(self.config.size);
+            return Ok(0);
+        }
+
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.file_len))?;
+        let mut appended = Vec::new();
+        file.read_to_end(&mut appended)?;
+        self.file_len += appended.len() as u64;
+        if let Some(cipher) = &self.config.cipher {
+            appended = cipher.decrypt_records(&appended)?;
+        }
+        let appended = parse_entries(&String::from_utf8_lossy(&appended));
+        self.file_entries += appended.len();
+        let count = appended.len();
+        for entry in appended {
+            self.entries.insert(unsaved, entry);
+        }
+        self.entries.truncate(self.config.size);
+        Ok(count)
     }
 
-    pub fn fetch_more(&mut self) {
-        match self.reader.read_lines(10) {
-            Ok(mut cmds) => {
-                if cmds.len() > 0 {
-                    self.commands.append(&mut cmds);
+    pub fn add_command(&mut self, command: &str) {
+        self.pending_result = false;
+        if self.is_ignored(command) {
+            return;
+        }
+        if self.config.ignore_dups && self.entries.first().is_some_and(|f| f.command == command) {
+            return;
+        }
+        if self.config.erase_dups {
+            while let Some(i) = self.entries.iter().position(|e| e.command == command) {
+                self.entries.remove(i);
+                if i < self.new_commands_count {
+                    self.new_commands_count -= 1;
                 }
             }
-            Err(_) => (),
         }
+        self.entries.insert(
+            0,
+            Entry {
+                command: command.to_string(),
+                time: Some(clock::now()),
+                cwd: env::current_dir()
+                    .ok()
+                    .map(|cwd| cwd.to_string_lossy().into_owned()),
+                private: self.private,
+                ..Default::default()
+            },
+        );
+        self.pending_result = true;
+        self.new_commands_count += 1;
+        self.entries.truncate(self.config.size);
+    }
+
+    /// Fills in how the most recently added command went.
+    pub fn record_result(&mut self, duration: Duration, exit_code: i32) {
+        if !std::mem::take(&mut self.pending_result) {
+            return;
+        }
+        if let Some(entry) = self.entries.first_mut() {
+            entry.duration = Some(duration.as_millis() as u64);
+            entry.exit_code = Some(exit_code);
+        }
+    }
+
+    fn is_ignored(&self, command: &str) -> bool {
+        (self.config.ignore_space && command.starts_with(' '))
+            || self
+                .config
+                .ignore
+                .iter()
+                .any(|pattern| glob_match(pattern, command))
+    }
+
+    pub fn get_command(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(|e| e.command.as_str())
     }
 
     pub fn count(&self) -> usize {
-        self.commands.len()
+        self.entries.len()
+    }
+
+    /// Forgets the in-memory history. The file is left alone.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.new_commands_count = 0;
+        self.pending_result = false;
     }
 
-    fn prepend_to_file(&mut self, data: String) -> io::Result<()> {
-        let mut f = File::open(&self.path)?;
-        let mut content = data.as_bytes().to_owned();
-        f.read_to_end(&mut content)?;
-        let mut f = File::create(&self.path)?;
-        f.write_all(content.as_slice())?;
+    /// Removes an entry by the number `history` lists it under, where the
+    /// oldest entry is 1. Returns false if there is no such entry.
+    pub fn delete(&mut self, number: usize) -> bool {
+        let Some(index) = self.entries.len().checked_sub(number) else {
+            return false;
+        };
+        if number == 0 {
+            return false;
+        }
+        self.entries.remove(index);
+        if index < self.new_commands_count {
+            self.new_commands_count -= 1;
+        }
+        if index == 0 {
+            self.pending_result = false;
+        }
+        true
+    }
+
+    /// Replaces the file with the in-memory history.
+    pub fn write(&mut self) -> io::Result<()> {
+        self.check_unlocked()?;
+        let kept = self
+            .entries
+            .iter()
+            .filter(|e| !e.private)
+            .take(self.config.file_size)
+            .collect::<Vec<_>>();
+        let content = kept
+            .iter()
+            .rev()
+            .map(|e| format_entry(e))
+            .collect::<String>();
+        let kept = kept.len();
+        self.replace_file(&content)?;
+        self.file_entries = kept;
+        self.new_commands_count = 0;
         Ok(())
     }
-}
 
-impl Drop for History {
-    fn drop(&mut self) {
-        let mut s = self
-            .commands
+    /// Adds the commands from a bash or zsh history file ahead of our own,
+    /// since they predate them, and returns how many were imported.
+    pub fn import(&mut self, path: &Path) -> io::Result<usize> {
+        self.prepend(parse_foreign(&fs::read(path)?))
+    }
+
+    /// Adds the commands from the `~/.ash_history` of the first versions,
+    /// which kept one per line, newest first, ahead of our own.
+    pub fn import_newest_first(&mut self, path: &Path) -> io::Result<usize> {
+        let content = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+        let imported = content
+            .lines()
+            .rev()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Entry {
+                command: line.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        self.prepend(imported)
+    }
+
+    /// Writes oldest-first `imported` ahead of the file's entries and reloads.
+    fn prepend(&mut self, imported: Vec<Entry>) -> io::Result<usize> {
+        self.check_unlocked()?;
+        self.save()?;
+        let mut entries = imported.clone();
+        entries.extend(parse_entries(&self.read_content()?));
+        let kept = &entries[entries.len().saturating_sub(self.config.file_size)..];
+        self.replace_file(&kept.iter().map(format_entry).collect::<String>())?;
+        self.read_file()?;
+        Ok(imported.len())
+    }
+
+    /// Re-reads the file, first appending anything not yet written to it.
+    pub fn reload(&mut self) -> io::Result<()> {
+        self.save()?;
+        self.read_file()
+    }
+
+    /// Appends the commands not yet written to the file. Called after every
+    /// command so history survives the shell being killed.
+    pub fn save(&mut self) -> io::Result<()> {
+        if self.config.locked {
+            self.new_commands_count = 0;
+        }
+        if self.new_commands_count == 0 {
+            return Ok(());
+        }
+        // Pick up other sessions' commands first so our offset into the file
+        // stays accurate after appending.
+        if self.config.sync {
+            self.sync()?;
+        }
+        let new_commands = self.entries[..self.new_commands_count.min(self.entries.len())]
             .iter()
-            .filter(|f| !f.trim().is_empty())
-            .enumerate()
-            .filter(|(i, _)| *i < self.new_commands_count as usize)
-            .map(|(_, a)| a.as_str())
-            .collect::<Vec<_>>()
-            .join("\n");
+            .filter(|e| !e.private)
+            .collect::<Vec<_>>();
+        let content = new_commands
+            .iter()
+            .rev()
+            .map(|e| format_entry(e))
+            .collect::<String>();
+        let written = new_commands.len();
+
+        if self.appendable()? {
+            let data = match &self.config.cipher {
+                Some(cipher) => cipher.encrypt_record(content.as_bytes())?,
+                None => content.into_bytes(),
+            };
+            let mut file = OpenOptions::new().append(true).open(&self.path)?;
+            file.write_all(&data)?;
+            if self.config.fsync {
+                file.sync_data()?;
+            }
+            self.file_len += data.len() as u64;
+        } else {
+            // A file encrypted another way, or not at all, is converted once.
+            let mut existing = self.read_content()?;
+            existing.push_str(&content);
+            self.replace_file(&existing)?;
+        }
+        self.file_entries += written;
+        self.new_commands_count = 0;
+
+        // Compacting rewrites the whole file, so let it grow a little past
+        // `file_size`, duplicates included, before doing it.
+        let slack = (self.config.file_size / 10).max(1);
+        if self.file_entries > self.config.file_size + slack {
+            self.rewrite_file()?;
+        }
+        Ok(())
+    }
+
+    /// Drops the oldest lines so the file stays within `file_size` entries,
+    /// and with `erase_dups` all but the latest copy of each command.
+    fn rewrite_file(&mut self) -> io::Result<()> {
+        let mut entries = parse_entries(&self.read_content()?);
+        if self.config.erase_dups {
+            erase_dups(&mut entries, HashSet::new());
+        }
+        let kept = &entries[entries.len().saturating_sub(self.config.file_size)..];
+        let content = kept.iter().map(format_entry).collect::<String>();
+        self.replace_file(&content)?;
+        self.file_entries = kept.len();
+        Ok(())
+    }
+
+    fn check_unlocked(&self) -> io::Result<()> {
+        if self.config.locked {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the history file is encrypted and no passphrase was given",
+            ));
+        }
+        Ok(())
+    }
 
-        if s.len() > 0 {
-            s.push_str("\n")
+    /// Whether new commands can be appended to the file as it is.
+    fn appendable(&self) -> io::Result<bool> {
+        let Some(cipher) = &self.config.cipher else {
+            return Ok(true);
         };
+        let mut head = Vec::new();
+        File::open(&self.path)?.take(256).read_to_end(&mut head)?;
+        Ok(cipher.can_append(&head))
+    }
+
+    fn read_content(&self) -> io::Result<String> {
+        self.decode(fs::read(&self.path)?)
+    }
 
-        let _ = self.prepend_to_file(s);
+    fn decode(&self, data: Vec<u8>) -> io::Result<String> {
+        let data = match &self.config.cipher {
+            Some(cipher) => cipher.decrypt(&data)?,
+            None => data,
+        };
+        Ok(String::from_utf8_lossy(&data).into_owned())
+    }
+
+    fn replace_file(&mut self, content: &str) -> io::Result<()> {
+        self.check_unlocked()?;
+        let data = match &self.config.cipher {
+            Some(cipher) => cipher.encrypt(content.as_bytes())?,
+            None => content.as_bytes().to_vec(),
+        };
+        // Write to a sibling file first so a crash never leaves it half-written.
+        let temp_path = self.path.with_extension("tmp");
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&data)?;
+        if self.config.fsync {
+            file.sync_data()?;
+        }
+        fs::rename(&temp_path, &self.path)?;
+        self.file_len = data.len() as u64;
+        Ok(())
     }
 }
 
-pub struct LineReader {
-    reader: BufReader<File>,
-    position: u64,
+/// Narrows `history` listings down by how, where and when commands ran.
+#[derive(Default)]
+pub struct HistoryFilter {
+    pub failed: bool,
+    pub cwd: Option<String>,
+    /// Epoch seconds; older entries are left out.
+    pub since: Option<u64>,
 }
 
-impl LineReader {
-    pub fn new(path: &PathBuf) -> io::Result<Self> {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        Ok(Self {
-            reader,
-            position: 0,
-        })
+impl HistoryFilter {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        (!self.failed || entry.exit_code.is_some_and(|code| code != 0))
+            && self
+                .cwd
+                .as_ref()
+                .is_none_or(|cwd| entry.cwd.as_ref() == Some(cwd))
+            && self
+                .since
+                .is_none_or(|since| entry.time.is_some_and(|time| time >= since))
+    }
+}
+
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(ExportFormat::Json),
+            "csv" => Some(ExportFormat::Csv),
+            _ => None,
+        }
+    }
+
+    /// Formats entries oldest first, leaving out private ones. Unknown
+    /// metadata is `null` in JSON and an empty field in CSV.
+    pub fn format(&self, entries: &[Entry]) -> String {
+        let number = |n: Option<String>| n.unwrap_or_default();
+        match self {
+            ExportFormat::Json => {
+                let json_number = |n: Option<String>| n.unwrap_or_else(|| "null".to_string());
+                let rows = entries
+                    .iter()
+                    .rev()
+                    .filter(|e| !e.private)
+                    .map(|e| {
+                        format!(
+                            "  {{\"command\": {}, \"time\": {}, \"duration_ms\": {}, \"exit_code\": {}, \"cwd\": {}}}",
+                            json::quote(&e.command),
+                            json_number(e.time.map(|t| t.to_string())),
+                            json_number(e.duration.map(|d| d.to_string())),
+                            json_number(e.exit_code.map(|c| c.to_string())),
+                            e.cwd.as_deref().map_or("null".to_string(), json::quote),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                format!("[\n{}\n]\n", rows.join(",\n"))
+            }
+            ExportFormat::Csv => {
+                let mut out = String::from("command,time,duration_ms,exit_code,cwd\n");
+                for e in entries.iter().rev().filter(|e| !e.private) {
+                    out.push_str(&format!(
+                        "{},{},{},{},{}\n",
+                        csv_field(&e.command),
+                        number(e.time.map(|t| t.to_string())),
+                        number(e.duration.map(|d| d.to_string())),
+                        number(e.exit_code.map(|c| c.to_string())),
+                        csv_field(e.cwd.as_deref().unwrap_or("")),
+                    ));
+                }
+                out
+            }
+        }
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// State of an incremental reverse search (Ctrl-R) through the history.
+pub struct HistorySearch {
+    pub query: String,
+    /// Index into the history of the current match.
+    position: Option<usize>,
+    pub failed: bool,
+}
+
+impl HistorySearch {
+    pub fn new() -> Self {
+        HistorySearch {
+            query: String::new(),
+            position: None,
+            failed: false,
+        }
+    }
+
+    pub fn matched<'a>(&self, entries: &'a [Entry]) -> Option<&'a str> {
+        entries.get(self.position?).map(|e| e.command.as_str())
+    }
+
+    pub fn push(&mut self, c: char, entries: &[Entry]) {
+        self.query.push(c);
+        self.search(entries, self.position.unwrap_or(0));
+    }
+
+    pub fn pop(&mut self, entries: &[Entry]) {
+        self.query.pop();
+        self.search(entries, 0);
+    }
+
+    /// Keeps the match in place when entries are added ahead of it.
+    pub fn shift(&mut self, by: usize) {
+        if let Some(position) = self.position.as_mut() {
+            *position += by;
+        }
+    }
+
+    /// Steps to the next older match.
+    pub fn next(&mut self, entries: &[Entry]) {
+        self.search(entries, self.position.map_or(0, |p| p + 1));
+    }
+
+    fn search(&mut self, entries: &[Entry], from: usize) {
+        if self.query.is_empty() {
+            self.position = None;
+            self.failed = false;
+            return;
+        }
+        match entries
+            .iter()
+            .enumerate()
+            .skip(from)
+            .find(|(_, entry)| entry.command.contains(&self.query))
+        {
+            Some((i, _)) => {
+                self.position = Some(i);
+                self.failed = false;
+            }
+            None => self.failed = true,
+        }
     }
+}
+
+/// Offset of the first whole entry in a chunk cut from the middle of the
+/// file: the first metadata line, or failing that the first full line.
+fn entry_boundary(chunk: &[u8]) -> usize {
+    let line_starts = chunk
+        .iter()
+        .enumerate()
+        .filter(|(_, &b)| b == b'\n')
+        .map(|(i, _)| i + 1)
+        .collect::<Vec<_>>();
+    line_starts
+        .iter()
+        .find(|&&i| chunk.get(i) == Some(&b'#') && chunk.get(i + 1).is_some_and(u8::is_ascii_digit))
+        .or(line_starts.first())
+        .copied()
+        .unwrap_or(chunk.len())
+}
 
-    pub fn read_lines(&mut self, count: usize) -> io::Result<Vec<String>> {
-        let mut lines = Vec::new();
-        let _ = self.reader.seek(SeekFrom::Start(self.position));
-        for _ in 0..count {
-            let mut line = String::new();
-            let bytes_read = self.reader.read_line(&mut line)?;
-            if bytes_read == 0 {
-                break;
-            } else {
-                self.position += bytes_read as u64;
+/// Drops all but the latest copy of each command from oldest-first
+/// `entries`, taking the commands in `seen` as having later copies.
+fn erase_dups(entries: &mut Vec<Entry>, mut seen: HashSet<String>) {
+    entries.reverse();
+    entries.retain(|entry| seen.insert(entry.command.clone()));
+    entries.reverse();
+}
+
+fn parse_entries(content: &str) -> Vec<Entry> {
+    let mut entries = vec![];
+    let mut meta = Entry::default();
+    for line in content.lines() {
+        if let Some(fields) = line
+            .strip_prefix('#')
+            .filter(|s| s.starts_with(|c: char| c.is_ascii_digit()))
+        {
+            let mut fields = fields.splitn(4, ' ');
+            meta = Entry {
+                time: fields.next().and_then(|f| f.parse().ok()),
+                duration: fields.next().and_then(|f| f.parse().ok()),
+                exit_code: fields.next().and_then(|f| f.parse().ok()),
+                cwd: fields.next().map(String::from),
+                ..Default::default()
+            };
+        } else if !line.trim().is_empty() {
+            entries.push(Entry {
+                command: line.to_string(),
+                ..std::mem::take(&mut meta)
+            });
+        }
+    }
+    entries
+}
+
+/// Parses another shell's history file. Bash files, with or without `#<epoch>`
+/// lines, share our format; zsh's extended format has `: <start>:<elapsed>;`
+/// before each command and escapes some bytes with 0x83. Multi-line zsh
+/// commands are skipped since entries here are single lines.
+fn parse_foreign(bytes: &[u8]) -> Vec<Entry> {
+    let is_zsh = |line: &str| {
+        line.strip_prefix(": ")
+            .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+    };
+    if !String::from_utf8_lossy(bytes).lines().any(is_zsh) {
+        return parse_entries(&String::from_utf8_lossy(bytes));
+    }
+
+    let mut unmetafied = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            0x83 => unmetafied.extend(bytes.next().map(|next| next ^ 0x20)),
+            _ => unmetafied.push(b),
+        }
+    }
+
+    let mut entries = vec![];
+    let mut continued = false;
+    for line in String::from_utf8_lossy(&unmetafied).lines() {
+        // A trailing backslash continues the command on the next line.
+        let was_continued = std::mem::replace(&mut continued, line.ends_with('\\'));
+        if was_continued || continued {
+            continue;
+        }
+        let Some((meta, command)) = line.strip_prefix(": ").and_then(|l| l.split_once(';')) else {
+            continue;
+        };
+        let (start, elapsed) = meta.split_once(':').unwrap_or((meta, ""));
+        if command.trim().is_empty() {
+            continue;
+        }
+        entries.push(Entry {
+            command: command.to_string(),
+            time: start.parse().ok(),
+            duration: elapsed.parse::<u64>().ok().map(|secs| secs * 1000),
+            ..Default::default()
+        });
+    }
+    entries
+}
+
+fn format_entry(entry: &Entry) -> String {
+    let Some(time) = entry.time else {
+        return format!("{}\n", entry.command);
+    };
+    // Unknown fields are written as `-` so the ones after them keep their
+    // positions.
+    let field = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    format!(
+        "#{} {} {} {}\n{}\n",
+        time,
+        field(entry.duration.map(|d| d.to_string())),
+        field(entry.exit_code.map(|c| c.to_string())),
+        field(entry.cwd.clone()),
+        entry.command
+    )
+}
+
+/// Matches `text` against a glob where `*` matches any run of characters
+/// and `?` any single character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*` if the rest fails to match.
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
             }
-            lines.push(line.trim_end().to_string());
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
         }
-        Ok(lines)
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+impl Drop for History {
+    fn drop(&mut self) {
+        let _ = self.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.command.as_str()).collect()
+    }
+
+    #[test]
+    fn entries_take_the_metadata_line_before_them() {
+        let entries = parse_entries(
+            "echo plain\n#100 250 1 /tmp/a dir\necho timed\n\n#200 - -\necho later\n",
+        );
+        assert_eq!(
+            commands(&entries),
+            ["echo plain", "echo timed", "echo later"]
+        );
+        assert_eq!(entries[0].time, None);
+        assert_eq!(entries[1].time, Some(100));
+        assert_eq!(entries[1].duration, Some(250));
+        assert_eq!(entries[1].exit_code, Some(1));
+        assert_eq!(entries[1].cwd.as_deref(), Some("/tmp/a dir"));
+        assert_eq!(entries[2].time, Some(200));
+        assert_eq!((entries[2].duration, entries[2].exit_code), (None, None));
+    }
+
+    #[test]
+    fn formatted_entries_parse_back() {
+        let timed = Entry {
+            command: "ls -l".to_string(),
+            time: Some(5),
+            exit_code: Some(2),
+            cwd: Some("/a b".to_string()),
+            ..Default::default()
+        };
+        let plain = Entry {
+            command: "pwd".to_string(),
+            ..Default::default()
+        };
+        let entries = parse_entries(&(format_entry(&timed) + format_entry(&plain).as_str()));
+        assert_eq!(commands(&entries), ["ls -l", "pwd"]);
+        assert_eq!(entries[0].time, Some(5));
+        assert_eq!(entries[0].duration, None);
+        assert_eq!(entries[0].exit_code, Some(2));
+        assert_eq!(entries[0].cwd.as_deref(), Some("/a b"));
+        assert_eq!(entries[1].time, None);
+    }
+
+    #[test]
+    fn zsh_histories_are_unmetafied_and_skip_multiline_commands() {
+        // The arrow is E2 86 92, with the last two bytes metafied.
+        let history = b": 1700000000:3;ls -l\n: 1700000001:0;echo a\\\nb\n: 1700000002:0;echo \xe2\x83\xa6\x83\xb2\n";
+        let entries = parse_foreign(history);
+        assert_eq!(commands(&entries), ["ls -l", "echo →"]);
+        assert_eq!(entries[0].time, Some(1_700_000_000));
+        assert_eq!(entries[0].duration, Some(3000));
+    }
+
+    #[test]
+    fn later_copies_win_over_earlier_ones() {
+        let mut entries = parse_entries("a\nb\na\nc\nb\n");
+        erase_dups(&mut entries, HashSet::from(["c".to_string()]));
+        assert_eq!(commands(&entries), ["a", "b"]);
+    }
+
+    #[test]
+    fn a_locked_history_leaves_the_encrypted_file_alone() {
+        let path = env::temp_dir().join(format!("a-shell-locked-history-{}", std::process::id()));
+        let encrypted = "#a-shell-encrypted 2 0707070707070707 00\nabcd\n";
+        fs::write(&path, encrypted).unwrap();
+        let mut config = HistoryConfig::from_config(&Config::load(&path.with_extension("toml")));
+        config.locked = true;
+        let mut history = History::new(&path, config).unwrap();
+        assert_eq!(history.count(), 0);
+        assert!(history.private);
+        // Even with private mode turned off nothing is written.
+        history.private = false;
+        history.add_command("echo leaked");
+        history.save().unwrap();
+        history.reload().unwrap();
+        assert!(history.write().is_err());
+        drop(history);
+        assert_eq!(fs::read_to_string(&path).unwrap(), encrypted);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ash_history_is_reversed_and_imported_ahead_of_ours() {
+        let path = env::temp_dir().join(format!("a-shell-ash-history-{}", std::process::id()));
+        let legacy = path.with_extension("ash");
+        fs::write(&path, "echo ours\n").unwrap();
+        fs::write(&legacy, "echo newer\n\necho older\n").unwrap();
+        let config = HistoryConfig::from_config(&Config::load(&path.with_extension("toml")));
+        let mut history = History::new(&path, config).unwrap();
+        assert_eq!(history.import_newest_first(&legacy).unwrap(), 2);
+        assert_eq!(
+            commands(&history.entries),
+            ["echo ours", "echo newer", "echo older"]
+        );
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "echo older\necho newer\necho ours\n"
+        );
+        drop(history);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&legacy).unwrap();
+    }
+
+    #[test]
+    fn globs_match_any_run_or_single_character() {
+        assert!(glob_match("git *", "git commit -m x"));
+        assert!(glob_match("*.rs", "main.rs"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("l?", "ls"));
+        assert!(glob_match("é?", "éa"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("l?", "l"));
+        assert!(!glob_match("*.rs", "main.rs.bak"));
+        assert!(!glob_match("git", "git status"));
     }
 }
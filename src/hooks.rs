@@ -0,0 +1,188 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// A point in the shell's life that features and user commands hook into.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Hook {
+    /// Before each prompt.
+    Precmd,
+    /// Before each command line.
+    Preexec,
+    /// After the working directory changed.
+    Chpwd,
+    /// After each command line finished.
+    JobFinished,
+    /// When the shell exits.
+    Exit,
+    /// When a login shell exits, after `Exit`.
+    Logout,
+}
+
+pub const HOOKS: [Hook; 6] = [
+    Hook::Precmd,
+    Hook::Preexec,
+    Hook::Chpwd,
+    Hook::JobFinished,
+    Hook::Exit,
+    Hook::Logout,
+];
+
+impl Hook {
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::Precmd => "precmd",
+            Hook::Preexec => "preexec",
+            Hook::Chpwd => "chpwd",
+            Hook::JobFinished => "job-finished",
+            Hook::Exit => "exit",
+            Hook::Logout => "logout",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        HOOKS.into_iter().find(|hook| hook.name() == name)
+    }
+}
+
+/// What happened at a hook point, with what subscribers need to know.
+pub enum HookEvent<'a> {
+    Precmd,
+    Preexec {
+        line: &'a str,
+    },
+    Chpwd {
+        old: &'a Path,
+        new: &'a Path,
+    },
+    JobFinished {
+        line: &'a str,
+        exit_code: i32,
+        duration: Duration,
+        /// Whether it ran as a background job.
+        background: bool,
+    },
+    Exit {
+        exit_code: i32,
+    },
+    Logout,
+}
+
+impl HookEvent<'_> {
+    pub fn hook(&self) -> Hook {
+        match self {
+            HookEvent::Precmd => Hook::Precmd,
+            HookEvent::Preexec { .. } => Hook::Preexec,
+            HookEvent::Chpwd { .. } => Hook::Chpwd,
+            HookEvent::JobFinished { .. } => Hook::JobFinished,
+            HookEvent::Exit { .. } => Hook::Exit,
+            HookEvent::Logout => Hook::Logout,
+        }
+    }
+
+    /// The positional parameters user commands get.
+    pub fn arguments(&self) -> Vec<String> {
+        match self {
+            HookEvent::Precmd | HookEvent::Logout => vec![],
+            HookEvent::Preexec { line } => vec![line.to_string()],
+            HookEvent::Chpwd { old, new } => {
+                vec![old.display().to_string(), new.display().to_string()]
+            }
+            HookEvent::JobFinished {
+                line,
+                exit_code,
+                duration,
+                ..
+            } => vec![
+                line.to_string(),
+                exit_code.to_string(),
+                duration.as_millis().to_string(),
+            ],
+            HookEvent::Exit { exit_code } => vec![exit_code.to_string()],
+        }
+    }
+}
+
+/// Commands run at the hook points, from `[hooks]` in the config file or
+/// added with the `hook` builtin:
+///
+/// ```toml
+/// [hooks]
+/// # Before each prompt.
+/// precmd = ["printf '\\033]0;%s\\007' \"$PWD\""]
+/// # Before each command line, which is passed as `$1`.
+/// preexec = ["printf '\\033]0;%s\\007' \"$1\""]
+/// # After `cd`, with the old and new directory as `$1` and `$2`.
+/// chpwd = ["ls"]
+/// # After each command line, with the line, its exit code and how many
+/// # milliseconds it took as `$1`, `$2` and `$3`.
+/// job-finished = ["[ \"$3\" -lt 10000 ] || printf '\\a'"]
+/// # When the shell exits, with its exit code as `$1`.
+/// exit = ["echo bye"]
+/// # When a login shell exits, after the `exit` commands.
+/// logout = ["clear"]
+/// ```
+///
+/// Each runs with `sh -c` on the shell's terminal, and the shell waits for
+/// it. The shell's own features subscribe to the same points before them.
+pub struct Hooks {
+    commands: Vec<(Hook, String)>,
+}
+
+impl Hooks {
+    pub fn from_config(config: &Config) -> Self {
+        let commands = HOOKS
+            .into_iter()
+            .flat_map(|hook| {
+                config
+                    .get_str_list("hooks", hook.name())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(move |command| (hook, command.to_string()))
+            })
+            .collect();
+        Hooks { commands }
+    }
+
+    /// Subscribes `command` to `hook`, after the commands already there.
+    pub fn add(&mut self, hook: Hook, command: &str) {
+        self.commands.push((hook, command.to_string()));
+    }
+
+    /// Drops the commands subscribed to `hook`.
+    pub fn clear(&mut self, hook: Hook) {
+        self.commands.retain(|(subscribed, _)| *subscribed != hook);
+    }
+
+    /// The subscribed commands, by hook in the order they run.
+    pub fn describe(&self) -> Vec<(&'static str, &str)> {
+        HOOKS
+            .into_iter()
+            .flat_map(|hook| {
+                self.commands
+                    .iter()
+                    .filter(move |(subscribed, _)| *subscribed == hook)
+                    .map(|(hook, command)| (hook.name(), command.as_str()))
+            })
+            .collect()
+    }
+
+    /// Runs the commands subscribed to the point `event` happened at.
+    pub fn run(&self, event: &HookEvent) {
+        let hook = event.hook();
+        let arguments = event.arguments();
+        for (_, command) in self.commands.iter().filter(|(h, _)| *h == hook) {
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .arg("a-shell")
+                .args(&arguments)
+                .status();
+            if let Err(e) = status {
+                eprintln!("Cannot run hook {}: {}", command, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::env;
+use std::io::{self, stdout, IsTerminal, Write};
+
+use crate::{config::Config, prompt::hostname};
+
+/// Escape sequences that tell the terminal what the shell is doing, set
+/// from `[terminal]` in the config file:
+///
+/// ```toml
+/// [terminal]
+/// integration = false   # send none of them
+/// ```
+///
+/// OSC 7 reports the working directory, so new tabs and splits can open
+/// in it. OSC 133 marks where each prompt, command line and its output
+/// start and where the command finished, with its exit code, so the
+/// terminal can jump between prompts and select a command's output.
+pub struct Integration {
+    enabled: bool,
+    /// Whether a command line was marked as running, so its end can be.
+    running: bool,
+}
+
+impl Integration {
+    pub fn from_config(config: &Config) -> Self {
+        Integration {
+            enabled: config.get_bool("terminal", "integration").unwrap_or(true)
+                && stdout().is_terminal(),
+            running: false,
+        }
+    }
+
+    /// `prompt` with the marks for where the prompt and the input after it
+    /// start.
+    pub fn mark_prompt(&self, prompt: &str) -> String {
+        if !self.enabled {
+            return prompt.to_string();
+        }
+        format!("\x1b]133;A\x07{}\x1b]133;B\x07", prompt)
+    }
+
+    /// Reports the working directory.
+    pub fn directory(&self, out: &mut impl Write) -> io::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let Ok(cwd) = env::current_dir() else {
+            return Ok(());
+        };
+        let path = cwd.to_string_lossy();
+        write!(
+            out,
+            "\x1b]7;file://{}{}\x1b\\",
+            hostname(),
+            percent_encode(&path)
+        )
+    }
+
+    /// Marks where the output of the accepted command line starts.
+    pub fn command_started(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        self.running = true;
+        write!(out, "\x1b]133;C\x07")?;
+        out.flush()
+    }
+
+    /// Marks that the command line finished with `exit_code`.
+    pub fn command_finished(&mut self, out: &mut impl Write, exit_code: i32) -> io::Result<()> {
+        if !std::mem::take(&mut self.running) {
+            return Ok(());
+        }
+        write!(out, "\x1b]133;D;{}\x07", exit_code)?;
+        out.flush()
+    }
+}
+
+/// `path` with the bytes a `file:` URL cannot hold as they are escaped.
+fn percent_encode(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
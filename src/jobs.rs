@@ -0,0 +1,418 @@
+use std::io;
+use std::os::fd::RawFd;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// What a job is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    /// Every process exited; the exit code is the last one's.
+    Done(i32),
+}
+
+/// A pipeline the shell started, by its processes.
+pub struct Job {
+    pub id: usize,
+    pub line: String,
+    /// The process group of the processes, when the shell has job control.
+    pgid: libc::pid_t,
+    /// Each process with its exit code once it has exited.
+    processes: Vec<(libc::pid_t, Option<i32>)>,
+    stopped: bool,
+    started: Instant,
+    /// The terminal modes the job had set when it stopped, put back when it
+    /// is brought to the foreground again.
+    modes: Option<libc::termios>,
+}
+
+impl Job {
+    pub fn state(&self) -> JobState {
+        if self.processes.iter().all(|(_, code)| code.is_some()) {
+            let (_, code) = self.processes.last().copied().unwrap_or_default();
+            return JobState::Done(code.unwrap_or_default());
+        }
+        match self.stopped {
+            true => JobState::Stopped,
+            false => JobState::Running,
+        }
+    }
+
+    /// How long since the job started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// The line `jobs` shows, like `[1]+  Running    sleep 100 &`; `marker`
+    /// is `+` for the current job and `-` for the previous one.
+    pub fn describe(&self, marker: char) -> String {
+        let (state, line) = match self.state() {
+            JobState::Running => ("Running".to_string(), format!("{} &", self.line)),
+            JobState::Stopped => ("Stopped".to_string(), self.line.clone()),
+            JobState::Done(0) => ("Done".to_string(), self.line.clone()),
+            JobState::Done(code) => (format!("Exit {}", code), self.line.clone()),
+        };
+        format!("[{}]{}  {:<10} {}", self.id, marker, state, line)
+    }
+
+    /// Records what `waitpid` reported for `pid`.
+    fn update(&mut self, pid: libc::pid_t, status: libc::c_int) {
+        if libc::WIFSTOPPED(status) {
+            self.stopped = true;
+        } else if libc::WIFCONTINUED(status) {
+            self.stopped = false;
+        } else if let Some((_, code)) = self.processes.iter_mut().find(|(p, _)| *p == pid) {
+            *code = Some(match libc::WIFSIGNALED(status) {
+                true => 128 + libc::WTERMSIG(status),
+                false => libc::WEXITSTATUS(status),
+            });
+        }
+    }
+
+    /// Waits for `pid` to change as `flags` ask, and records it. Returns
+    /// false when there was nothing to report yet.
+    fn wait_for(&mut self, pid: libc::pid_t, flags: libc::c_int) -> bool {
+        let mut status = 0;
+        loop {
+            // SAFETY: `status` outlives the call.
+            match unsafe { libc::waitpid(pid, &mut status, flags) } {
+                0 => return false,
+                n if n > 0 => {
+                    self.update(pid, status);
+                    return true;
+                }
+                _ if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+                _ => {
+                    // Already reaped elsewhere; all there is to know is that
+                    // it is gone.
+                    if let Some((_, code)) = self.processes.iter_mut().find(|(p, _)| *p == pid) {
+                        code.get_or_insert(0);
+                    }
+                    return true;
+                }
+            }
+        }
+    }
+}
+
+/// The terminal the shell controls, and what it needs to give it back.
+struct Terminal {
+    /// A copy of standard input that children can still reach after their
+    /// own is redirected.
+    fd: RawFd,
+    /// The shell's process group.
+    pgid: libc::pid_t,
+    /// The group that had the terminal before the shell took it.
+    previous_pgid: libc::pid_t,
+    /// The shell's terminal modes, restored when a job stops or ends.
+    modes: libc::termios,
+}
+
+/// The job table: the commands run in the background or stopped with
+/// Ctrl-Z, for `jobs`, `fg` and `bg`.
+///
+/// With job control, taken by `take_terminal` in interactive shells, each
+/// pipeline runs in a process group of its own and the terminal is handed
+/// to the one in the foreground, so Ctrl-C and Ctrl-Z reach it and not the
+/// shell.
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+    terminal: Option<Terminal>,
+}
+
+impl Jobs {
+    /// Takes control of the terminal on standard input for job control.
+    pub fn take_terminal(&mut self) -> io::Result<()> {
+        // SAFETY: plain calls on the shell's own descriptors and process;
+        // `modes` outlives the call that fills it.
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // Started as a background job, wait to be brought to the
+            // foreground rather than take the terminal from under the shell
+            // that started us.
+            loop {
+                let foreground = libc::tcgetpgrp(libc::STDIN_FILENO);
+                if foreground < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                if foreground == libc::getpgrp() {
+                    break;
+                }
+                libc::kill(-libc::getpgrp(), libc::SIGTTIN);
+            }
+            let previous_pgid = libc::tcgetpgrp(libc::STDIN_FILENO);
+            for signal in [
+                libc::SIGINT,
+                libc::SIGQUIT,
+                libc::SIGTSTP,
+                libc::SIGTTIN,
+                libc::SIGTTOU,
+            ] {
+                libc::signal(signal, libc::SIG_IGN);
+            }
+            // A session leader, as under a terminal emulator, is already the
+            // leader of its group, and cannot move.
+            libc::setpgid(0, 0);
+            let pgid = libc::getpgrp();
+            let fd = libc::fcntl(libc::STDIN_FILENO, libc::F_DUPFD_CLOEXEC, 10);
+            let mut modes = std::mem::zeroed();
+            if fd < 0 || libc::tcsetpgrp(fd, pgid) < 0 || libc::tcgetattr(fd, &mut modes) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.terminal = Some(Terminal {
+                fd,
+                pgid,
+                previous_pgid,
+                modes,
+            });
+        }
+        Ok(())
+    }
+
+    /// Sets up `command` to run in the job with process group `pgid`, or to
+    /// start a group of its own, taking the terminal when `foreground`.
+    pub fn prepare(&self, command: &mut Command, pgid: Option<u32>, foreground: bool) {
+        let Some(terminal) = &self.terminal else {
+            return;
+        };
+        let fd = terminal.fd;
+        command.process_group(pgid.unwrap_or(0) as i32);
+        // SAFETY: only async-signal-safe calls between fork and exec.
+        unsafe {
+            command.pre_exec(move || {
+                // Also done by the shell; whichever comes first wins the race
+                // with the command reading the terminal.
+                if foreground {
+                    libc::tcsetpgrp(fd, libc::getpgrp());
+                }
+                for signal in [
+                    libc::SIGINT,
+                    libc::SIGQUIT,
+                    libc::SIGTSTP,
+                    libc::SIGTTIN,
+                    libc::SIGTTOU,
+                ] {
+                    libc::signal(signal, libc::SIG_DFL);
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Adds a job of the processes in `pids`, the first one leading their
+    /// group, and returns its id.
+    pub fn add(&mut self, line: &str, pids: &[u32]) -> usize {
+        let id = self.jobs.last().map_or(1, |job| job.id + 1);
+        self.jobs.push(Job {
+            id,
+            line: line.trim().to_string(),
+            pgid: pids.first().copied().unwrap_or_default() as libc::pid_t,
+            processes: pids.iter().map(|&pid| (pid as libc::pid_t, None)).collect(),
+            stopped: false,
+            started: Instant::now(),
+            modes: None,
+        });
+        id
+    }
+
+    /// Runs job `id` in the foreground until it ends, when it is removed, or
+    /// stops; `resume` continues it first if it was stopped.
+    pub fn foreground(&mut self, id: usize, resume: bool) -> io::Result<JobState> {
+        let index = self.index(id)?;
+        let job = &mut self.jobs[index];
+        if let Some(terminal) = &self.terminal {
+            // SAFETY: plain calls on the shell's descriptor; the modes are
+            // valid terminal settings.
+            unsafe {
+                if let Some(modes) = &job.modes {
+                    libc::tcsetattr(terminal.fd, libc::TCSADRAIN, modes);
+                }
+                libc::tcsetpgrp(terminal.fd, job.pgid);
+            }
+        }
+        if resume {
+            signal_group(&self.terminal, job.pgid, libc::SIGCONT)?;
+            job.stopped = false;
+        }
+        let pids = job
+            .processes
+            .iter()
+            .map(|(pid, _)| *pid)
+            .collect::<Vec<_>>();
+        for pid in pids {
+            while job.state() == JobState::Running
+                && job
+                    .processes
+                    .iter()
+                    .any(|(p, code)| *p == pid && code.is_none())
+            {
+                job.wait_for(pid, libc::WUNTRACED);
+            }
+        }
+        if let Some(terminal) = &self.terminal {
+            // SAFETY: as above; `modes` outlives the call that fills it.
+            unsafe {
+                if job.stopped {
+                    let mut modes = std::mem::zeroed();
+                    if libc::tcgetattr(terminal.fd, &mut modes) == 0 {
+                        job.modes = Some(modes);
+                    }
+                }
+                libc::tcsetpgrp(terminal.fd, terminal.pgid);
+                libc::tcsetattr(terminal.fd, libc::TCSADRAIN, &terminal.modes);
+            }
+        }
+        let state = job.state();
+        if let JobState::Done(_) = state {
+            self.jobs.remove(index);
+        }
+        Ok(state)
+    }
+
+    /// Continues stopped job `id` in the background.
+    pub fn background(&mut self, id: usize) -> io::Result<&Job> {
+        let index = self.index(id)?;
+        let job = &mut self.jobs[index];
+        signal_group(&self.terminal, job.pgid, libc::SIGCONT)?;
+        job.stopped = false;
+        Ok(job)
+    }
+
+    /// Notes what the jobs did since last asked, without waiting, and
+    /// removes and returns those that are done.
+    pub fn reap(&mut self) -> Vec<Job> {
+        for job in &mut self.jobs {
+            let pids = job
+                .processes
+                .iter()
+                .map(|(pid, _)| *pid)
+                .collect::<Vec<_>>();
+            for pid in pids {
+                let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+                while job
+                    .processes
+                    .iter()
+                    .any(|(p, code)| *p == pid && code.is_none())
+                    && job.wait_for(pid, flags)
+                {}
+            }
+        }
+        let (done, jobs) = std::mem::take(&mut self.jobs)
+            .into_iter()
+            .partition(|job| matches!(job.state(), JobState::Done(_)));
+        self.jobs = jobs;
+        done
+    }
+
+    /// The id of the job `spec` names, like `%2` or `2`, or of the current
+    /// job when there is none.
+    pub fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let Some(spec) = spec else {
+            return self
+                .jobs
+                .last()
+                .map(|job| job.id)
+                .ok_or_else(|| "no current job".to_string());
+        };
+        let number = spec.strip_prefix('%').unwrap_or(spec);
+        match number {
+            "%" | "+" | "" => self.find(None),
+            "-" => self
+                .jobs
+                .iter()
+                .rev()
+                .nth(1)
+                .map(|job| job.id)
+                .ok_or_else(|| format!("{}: no such job", spec)),
+            number => number
+                .parse()
+                .ok()
+                .filter(|id| self.jobs.iter().any(|job| job.id == *id))
+                .ok_or_else(|| format!("{}: no such job", spec)),
+        }
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    /// The processes of the job `spec` names that are still running, for
+    /// `kill %N`.
+    pub fn pids(&self, spec: &str) -> Result<Vec<libc::pid_t>, String> {
+        let id = self.find(Some(spec))?;
+        let job = self.jobs.iter().find(|job| job.id == id).expect("found");
+        Ok(job
+            .processes
+            .iter()
+            .filter(|(_, code)| code.is_none())
+            .map(|(pid, _)| *pid)
+            .collect())
+    }
+
+    /// The jobs with the marker `describe` takes for each.
+    pub fn iter(&self) -> impl Iterator<Item = (char, &Job)> {
+        let count = self.jobs.len();
+        self.jobs.iter().enumerate().map(move |(index, job)| {
+            let marker = match count - index {
+                1 => '+',
+                2 => '-',
+                _ => ' ',
+            };
+            (marker, job)
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    fn index(&self, id: usize) -> io::Result<usize> {
+        self.jobs
+            .iter()
+            .position(|job| job.id == id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such job"))
+    }
+}
+
+impl Drop for Jobs {
+    /// Stopped jobs would never be continued, so they are hung up on as
+    /// other shells do; running ones are left running.
+    fn drop(&mut self) {
+        for job in &self.jobs {
+            if job.state() == JobState::Stopped {
+                let _ = signal_group(&self.terminal, job.pgid, libc::SIGHUP);
+                let _ = signal_group(&self.terminal, job.pgid, libc::SIGCONT);
+            }
+        }
+        if let Some(terminal) = &self.terminal {
+            // SAFETY: gives the terminal back to whoever had it and closes
+            // the shell's copy of it.
+            unsafe {
+                libc::tcsetpgrp(terminal.fd, terminal.previous_pgid);
+                libc::close(terminal.fd);
+            }
+        }
+    }
+}
+
+/// Sends `signal` to the process group `pgid`. Only with job control do
+/// jobs have groups of their own; without it they share the shell's.
+fn signal_group(
+    terminal: &Option<Terminal>,
+    pgid: libc::pid_t,
+    signal: libc::c_int,
+) -> io::Result<()> {
+    if terminal.is_none() || pgid <= 0 {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "no job control"));
+    }
+    // SAFETY: signals a job's own process group.
+    if unsafe { libc::kill(-pgid, signal) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
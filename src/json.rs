@@ -0,0 +1,56 @@
+/// Quotes `s` as a JSON string.
+pub fn quote(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\u{7f}' => {
+                out.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Parses the JSON string at the start of `input`, returning it and the
+/// rest of the input.
+pub fn parse_string(input: &str) -> Option<(String, &str)> {
+    let mut chars = input.strip_prefix('"')?.char_indices();
+    let mut out = String::new();
+    // A high surrogate waiting for its pair.
+    let mut high = None;
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((out, &input[i + 2..])),
+            '\\' => match chars.next()?.1 {
+                'n' => out.push('\n'),
+                'r' => out.push('\r'),
+                't' => out.push('\t'),
+                'b' => out.push('\u{8}'),
+                'f' => out.push('\u{c}'),
+                'u' => {
+                    let hex = (0..4)
+                        .map(|_| chars.next().map(|(_, c)| c))
+                        .collect::<Option<String>>()?;
+                    let unit = u32::from_str_radix(&hex, 16).ok()?;
+                    match (high.take(), unit) {
+                        (None, 0xd800..=0xdbff) => high = Some(unit),
+                        (Some(h), 0xdc00..=0xdfff) => out.extend(char::from_u32(
+                            0x10000 + ((h - 0xd800) << 10) + (unit - 0xdc00),
+                        )),
+                        (_, unit) => out.push(char::from_u32(unit).unwrap_or('\u{fffd}')),
+                    }
+                }
+                other => out.push(other),
+            },
+            c => out.push(c),
+        }
+    }
+    None
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::config::Config;
+
+/// Editing commands a key can be bound to.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    AcceptLine,
+    BeginningOfLine,
+    EndOfLine,
+    KillLine,
+    UnixLineDiscard,
+    UnixWordRubout,
+    BackwardKillWord,
+    KillWord,
+    Yank,
+    YankPop,
+    ReverseSearchHistory,
+    HistoryPicker,
+    Abort,
+    UpcaseWord,
+    DowncaseWord,
+    CapitalizeWord,
+}
+
+/// The names actions are bound by, readline's where it has the action.
+const ACTIONS: &[(&str, Action)] = &[
+    ("accept-line", Action::AcceptLine),
+    ("beginning-of-line", Action::BeginningOfLine),
+    ("end-of-line", Action::EndOfLine),
+    ("kill-line", Action::KillLine),
+    ("unix-line-discard", Action::UnixLineDiscard),
+    ("unix-word-rubout", Action::UnixWordRubout),
+    ("backward-kill-word", Action::BackwardKillWord),
+    ("kill-word", Action::KillWord),
+    ("yank", Action::Yank),
+    ("yank-pop", Action::YankPop),
+    ("reverse-search-history", Action::ReverseSearchHistory),
+    ("history-picker", Action::HistoryPicker),
+    ("abort", Action::Abort),
+    ("upcase-word", Action::UpcaseWord),
+    ("downcase-word", Action::DowncaseWord),
+    ("capitalize-word", Action::CapitalizeWord),
+];
+
+const DEFAULT_BINDINGS: &[(&str, Action)] = &[
+    ("ctrl-a", Action::BeginningOfLine),
+    ("home", Action::BeginningOfLine),
+    ("ctrl-e", Action::EndOfLine),
+    ("end", Action::EndOfLine),
+    ("ctrl-j", Action::AcceptLine),
+    ("ctrl-k", Action::KillLine),
+    ("ctrl-u", Action::UnixLineDiscard),
+    ("ctrl-w", Action::UnixWordRubout),
+    ("alt-backspace", Action::BackwardKillWord),
+    ("alt-d", Action::KillWord),
+    ("ctrl-y", Action::Yank),
+    ("alt-y", Action::YankPop),
+    ("ctrl-r", Action::ReverseSearchHistory),
+    ("alt-r", Action::HistoryPicker),
+    ("ctrl-g", Action::Abort),
+    ("alt-u", Action::UpcaseWord),
+    ("alt-l", Action::DowncaseWord),
+    ("alt-c", Action::CapitalizeWord),
+];
+
+/// Which key runs which editing command. Keys are written like `ctrl-a`,
+/// `alt-f`, `home` or readline's `\C-a` and `\M-f`, and bound in `[keys]`
+/// in the config file or with the `bind` builtin:
+///
+/// ```toml
+/// [keys]
+/// "ctrl-x" = "kill-line"
+/// ```
+pub struct Keymap {
+    bindings: HashMap<(KeyCode, KeyModifiers), Action>,
+}
+
+impl Keymap {
+    pub fn from_config(config: &Config) -> Self {
+        let mut keymap = Keymap {
+            bindings: HashMap::new(),
+        };
+        for (key, action) in DEFAULT_BINDINGS {
+            keymap
+                .bindings
+                .extend(parse_key(key).map(|key| (key, *action)));
+        }
+        for (key, action) in config.section("keys").into_iter().flatten() {
+            let bound = match action.as_str() {
+                Some(action) => keymap.bind(key, action),
+                None => Err(format!("{}: action must be a string", key)),
+            };
+            if let Err(e) = bound {
+                eprintln!("Cannot bind key: {}", e);
+            }
+        }
+        keymap
+    }
+
+    /// The action bound to the key pressed, if any.
+    pub fn get(&self, event: &KeyEvent) -> Option<Action> {
+        let modifiers = event.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        let code = match event.code {
+            KeyCode::Char(c) if !modifiers.is_empty() => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code,
+        };
+        self.bindings.get(&(code, modifiers)).copied()
+    }
+
+    /// Binds `key` to the action called `action`.
+    pub fn bind(&mut self, key: &str, action: &str) -> Result<(), String> {
+        let (key, action) = resolve(key, action)?;
+        self.bindings.insert(key, action);
+        Ok(())
+    }
+
+    /// The bindings as key and action names, sorted by key.
+    pub fn describe(&self) -> Vec<(String, &'static str)> {
+        let mut bindings = self
+            .bindings
+            .iter()
+            .filter_map(|((code, modifiers), action)| {
+                let (name, _) = ACTIONS.iter().find(|(_, a)| a == action)?;
+                Some((key_name(*code, *modifiers)?, *name))
+            })
+            .collect::<Vec<_>>();
+        bindings.sort();
+        bindings
+    }
+}
+
+/// Whether `key` can be bound to the action called `action`.
+pub fn check(key: &str, action: &str) -> Result<(), String> {
+    resolve(key, action).map(|_| ())
+}
+
+fn resolve(key: &str, action: &str) -> Result<((KeyCode, KeyModifiers), Action), String> {
+    let key = parse_key(key).ok_or_else(|| format!("{}: unknown key", key))?;
+    let (_, action) = ACTIONS
+        .iter()
+        .find(|(name, _)| *name == action)
+        .ok_or_else(|| format!("{}: unknown action", action))?;
+    Ok((key, *action))
+}
+
+/// Names of keys other than characters.
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("backspace", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("down", KeyCode::Down),
+    ("end", KeyCode::End),
+    ("enter", KeyCode::Enter),
+    ("home", KeyCode::Home),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("tab", KeyCode::Tab),
+    ("up", KeyCode::Up),
+];
+
+fn parse_key(spec: &str) -> Option<(KeyCode, KeyModifiers)> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = spec;
+    loop {
+        if let Some(after) = rest.strip_prefix("ctrl-").or(rest.strip_prefix("\\C-")) {
+            modifiers |= KeyModifiers::CONTROL;
+            rest = after;
+        } else if let Some(after) = rest
+            .strip_prefix("alt-")
+            .or(rest.strip_prefix("\\M-"))
+            .or(rest.strip_prefix("\\e"))
+        {
+            modifiers |= KeyModifiers::ALT;
+            rest = after;
+        } else {
+            break;
+        }
+    }
+    if let Some((_, code)) = KEY_NAMES.iter().find(|(name, _)| *name == rest) {
+        return Some((*code, modifiers));
+    }
+    let mut chars = rest.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if !modifiers.is_empty() => {
+            Some((KeyCode::Char(c.to_ascii_lowercase()), modifiers))
+        }
+        _ => None,
+    }
+}
+
+fn key_name(code: KeyCode, modifiers: KeyModifiers) -> Option<String> {
+    let name = match code {
+        KeyCode::Char(c) => c.to_string(),
+        code => KEY_NAMES
+            .iter()
+            .find(|(_, named)| *named == code)?
+            .0
+            .to_string(),
+    };
+    let mut prefix = String::new();
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        prefix.push_str("ctrl-");
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        prefix.push_str("alt-");
+    }
+    Some(prefix + name.as_str())
+}
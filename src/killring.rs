@@ -0,0 +1,84 @@
+const MAX_ENTRIES: usize = 30;
+
+pub enum KillDirection {
+    Forward,
+    Backward,
+}
+
+/// Readline-style kill ring. Consecutive kills are merged into a single
+/// entry, and a yank can be cycled through older entries with yank-pop.
+pub struct KillRing {
+    entries: Vec<String>,
+    last_command_killed: bool,
+    this_command_killed: bool,
+    yank_index: usize,
+    /// Byte range of the text inserted by the previous yank, if the
+    /// previous command was a yank.
+    last_yank: Option<(usize, usize)>,
+    this_yank: Option<(usize, usize)>,
+}
+
+impl KillRing {
+    pub fn new() -> Self {
+        KillRing {
+            entries: vec![],
+            last_command_killed: false,
+            this_command_killed: false,
+            yank_index: 0,
+            last_yank: None,
+            this_yank: None,
+        }
+    }
+
+    /// Must be called before every editing command so kills and yanks only
+    /// chain when they directly follow each other.
+    pub fn start_command(&mut self) {
+        self.last_command_killed = std::mem::take(&mut self.this_command_killed);
+        self.last_yank = self.this_yank.take();
+    }
+
+    pub fn kill(&mut self, text: &str, direction: KillDirection) {
+        if text.is_empty() {
+            return;
+        }
+        self.this_command_killed = true;
+        match self.entries.last_mut() {
+            Some(last) if self.last_command_killed => match direction {
+                KillDirection::Forward => last.push_str(text),
+                KillDirection::Backward => last.insert_str(0, text),
+            },
+            _ => self.push(text.to_string()),
+        }
+    }
+
+    pub fn push(&mut self, text: String) {
+        self.entries.push(text);
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+    }
+
+    pub fn latest(&self) -> Option<&String> {
+        self.entries.last()
+    }
+
+    pub fn yank(&mut self, at: usize) -> Option<&String> {
+        self.yank_index = self.entries.len().checked_sub(1)?;
+        let text = &self.entries[self.yank_index];
+        self.this_yank = Some((at, at + text.len()));
+        Some(text)
+    }
+
+    /// Returns the range inserted by the previous yank together with the
+    /// next older entry that should replace it.
+    pub fn yank_pop(&mut self) -> Option<((usize, usize), &String)> {
+        let (start, end) = self.last_yank?;
+        self.yank_index = self
+            .yank_index
+            .checked_sub(1)
+            .unwrap_or(self.entries.len() - 1);
+        let text = &self.entries[self.yank_index];
+        self.this_yank = Some((start, start + text.len()));
+        Some(((start, end), text))
+    }
+}
@@ -0,0 +1,58 @@
+//! An interactive shell with completion, history search and a themeable
+//! prompt. The `a-shell` binary runs it; other programs can embed it with
+//! `ShellBuilder`.
+
+mod about;
+mod alias;
+mod audit;
+mod autocomplete;
+mod bookmarks;
+mod clipboard;
+mod clock;
+mod command_index;
+mod completer;
+mod completion_context;
+pub mod config;
+pub mod config_check;
+mod crypt;
+mod devenv;
+mod direnv;
+mod editor;
+pub mod error;
+mod events;
+mod frecency;
+mod fuzzy;
+mod git;
+mod highlight;
+mod history;
+mod hooks;
+mod integration;
+mod jobs;
+mod json;
+mod keymap;
+mod killring;
+mod ls_colors;
+mod notify;
+mod output;
+mod parser;
+pub mod paths;
+mod picker;
+mod preview;
+mod prompt;
+mod record;
+mod redirect;
+mod render;
+mod script;
+mod shell;
+mod stats;
+mod suggestion;
+mod sysinfo;
+#[cfg(feature = "test-support")]
+pub mod test_support;
+mod theme;
+mod title;
+mod watch;
+
+pub use crossterm::event::Event;
+pub use error::ShellError;
+pub use shell::{Shell, ShellBuilder};
@@ -0,0 +1,38 @@
+use std::env;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// Written by `sh` between what the profiles print and the environment they
+/// leave behind.
+const MARKER: &[u8] = b"\0a-shell-environment\0";
+
+/// Runs `/etc/profile` and `~/.profile` with `sh`, as they are written for
+/// it, and takes on the environment they set up.
+pub fn load_profile() -> io::Result<()> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(
+            "[ -r /etc/profile ] && . /etc/profile; \
+             [ -r \"$HOME/.profile\" ] && . \"$HOME/.profile\"; \
+             printf '\\0a-shell-environment\\0'; exec env -0",
+        )
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+    let at = output
+        .stdout
+        .windows(MARKER.len())
+        .rposition(|window| window == MARKER)
+        .ok_or_else(|| io::Error::other("sh exited early"))?;
+    io::stdout().write_all(&output.stdout[..at])?;
+    let vars = String::from_utf8_lossy(&output.stdout[at + MARKER.len()..]).into_owned();
+    for var in vars.split('\0') {
+        // `sh` sets these for itself.
+        match var.split_once('=') {
+            Some(("PWD" | "OLDPWD" | "SHLVL" | "_", _)) | None => {}
+            Some((name, value)) if !name.is_empty() => env::set_var(name, value),
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
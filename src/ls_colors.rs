@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+
+/// Used for the file types `LS_COLORS` does not mention, or when it is
+/// unset: GNU ls's defaults for the common ones.
+const DEFAULTS: &[(&str, &str)] = &[
+    ("di", "01;34"),
+    ("ln", "01;36"),
+    ("ex", "01;32"),
+    ("pi", "40;33"),
+    ("so", "01;35"),
+    ("bd", "40;33;01"),
+    ("cd", "40;33;01"),
+    ("or", "40;31;01"),
+];
+
+/// File colors in the format of GNU ls's `LS_COLORS`.
+pub struct LsColors {
+    /// Two-letter file type codes such as `di`, mapped to SGR parameters.
+    types: HashMap<String, String>,
+    /// `*.ext` style suffixes, lowercased, with their SGR parameters.
+    suffixes: Vec<(String, String)>,
+}
+
+impl LsColors {
+    pub fn from_env() -> Self {
+        let mut types = DEFAULTS
+            .iter()
+            .map(|(code, style)| (code.to_string(), style.to_string()))
+            .collect::<HashMap<_, _>>();
+        let mut suffixes = vec![];
+        for item in env::var("LS_COLORS").unwrap_or_default().split(':') {
+            let Some((key, style)) = item.split_once('=') else {
+                continue;
+            };
+            match key.strip_prefix('*') {
+                Some(suffix) => suffixes.push((suffix.to_lowercase(), style.to_string())),
+                None => {
+                    types.insert(key.to_string(), style.to_string());
+                }
+            }
+        }
+        LsColors { types, suffixes }
+    }
+
+    /// The SGR parameters to draw the file at `path` with, if any.
+    pub fn style(&self, path: &Path) -> Option<&str> {
+        let metadata = fs::symlink_metadata(path).ok()?;
+        if metadata.file_type().is_symlink() {
+            return match fs::metadata(path) {
+                Err(_) => self.code("or").or_else(|| self.code("ln")),
+                Ok(target) if self.code("ln") == Some("target") => self.style_of(path, &target),
+                Ok(_) => self.code("ln"),
+            };
+        }
+        self.style_of(path, &metadata)
+    }
+
+    fn style_of(&self, path: &Path, metadata: &Metadata) -> Option<&str> {
+        let file_type = metadata.file_type();
+        let code = if file_type.is_dir() {
+            "di"
+        } else if file_type.is_fifo() {
+            "pi"
+        } else if file_type.is_socket() {
+            "so"
+        } else if file_type.is_block_device() {
+            "bd"
+        } else if file_type.is_char_device() {
+            "cd"
+        } else if metadata.permissions().mode() & 0o111 != 0 {
+            "ex"
+        } else {
+            let name = path.file_name()?.to_string_lossy().to_lowercase();
+            return self
+                .suffixes
+                .iter()
+                .find(|(suffix, _)| name.ends_with(suffix.as_str()))
+                .map(|(_, style)| style.as_str())
+                .or_else(|| self.code("fi"));
+        };
+        self.code(code)
+    }
+
+    fn code(&self, code: &str) -> Option<&str> {
+        self.types
+            .get(code)
+            .map(String::as_str)
+            .filter(|style| !style.is_empty() && *style != "0" && *style != "00")
+    }
+}
@@ -1,15 +1,83 @@
-use shell::Shell;
-mod about;
-mod autocomplete;
-mod history;
-mod parser;
-mod shell;
-mod suggestion;
-extern crate toml;
+mod cli;
+mod login;
+
+use std::{env, process};
+
+use a_shell::{
+    config::Config,
+    config_check,
+    paths::{self, File},
+    ShellBuilder,
+};
+use cli::Invocation;
+
 fn main() {
-    let shell = Shell::new();
-    match shell {
-        Ok(mut app) => app.init(),
-        Err(e) => println!("Cannot init {:?}", e),
+    let options = match cli::parse(env::args()) {
+        Ok(Invocation::Run(options)) => options,
+        Ok(Invocation::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Ok(Invocation::Version) => {
+            println!("a-shell {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(e) => {
+            eprint!("a-shell: {}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    // Before anything looks at `$PATH` and the like.
+    if options.login {
+        if let Err(e) = login::load_profile() {
+            eprintln!("Cannot load profile: {}", e);
+        }
     }
+    paths::migrate();
+    let config_path = match options.config {
+        Some(path) if !path.is_file() => {
+            eprintln!("a-shell: {}: no such config file", path.display());
+            process::exit(2);
+        }
+        Some(path) => path,
+        None => Config::default_path(),
+    };
+    if options.check_config {
+        let problems = config_check::check(&config_path, &paths::path(File::Rc));
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        process::exit(if problems.is_empty() { 0 } else { 1 });
+    }
+    let mut app = match ShellBuilder::new().config_path(config_path).build() {
+        Ok(app) => app,
+        Err(e) => {
+            println!("Cannot init {:?}", e);
+            process::exit(1);
+        }
+    };
+    if options.login {
+        app.source_startup_file(File::Profile);
+    }
+    if options.interactive && !options.norc {
+        app.source_rc();
+    }
+    let exit_code = match (options.command, options.script) {
+        (Some(command), _) => app.run_script(&command),
+        (None, Some(script)) => app.source(&script).unwrap_or_else(|e| {
+            eprintln!("a-shell: {}: {}", script.display(), e);
+            127
+        }),
+        (None, None) => {
+            app.init();
+            app.exit_code()
+        }
+    };
+    if options.login {
+        app.logout();
+    }
+    // `process::exit` skips destructors, and the shell's restores the
+    // terminal.
+    drop(app);
+    process::exit(exit_code);
 }
@@ -0,0 +1,82 @@
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use crate::{clock, config::Config};
+
+/// Desktop notifications for commands the user may have stopped watching,
+/// set from `[notify]` in the config file:
+///
+/// ```toml
+/// [notify]
+/// enabled = true            # off by default
+/// after_ms = 10000          # for command lines running this long, the default
+/// command = "notify-send"   # gets the summary and body as arguments, the default
+/// ```
+///
+/// A command line in the foreground notifies when it ran at least
+/// `after_ms`. A background job notifies when it finishes while the
+/// terminal is not focused, which the shell learns from focus reports
+/// while it shows the prompt.
+pub struct Notifier {
+    enabled: bool,
+    after: Duration,
+    command: String,
+    /// Whether the terminal last said it has focus.
+    focused: bool,
+}
+
+impl Notifier {
+    pub fn from_config(config: &Config) -> Self {
+        let after_ms = config
+            .get_int("notify", "after_ms")
+            .and_then(|ms| u64::try_from(ms).ok())
+            .unwrap_or(10_000);
+        Notifier {
+            enabled: config.get_bool("notify", "enabled").unwrap_or(false),
+            after: Duration::from_millis(after_ms),
+            command: config
+                .get_str("notify", "command")
+                .unwrap_or("notify-send")
+                .to_string(),
+            focused: true,
+        }
+    }
+
+    /// Whether the terminal should report focus changes.
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+
+    /// Notifies that `line` finished with `exit_code` after `duration`, if
+    /// the user would want to know.
+    pub fn finished(&self, line: &str, exit_code: i32, duration: Duration, background: bool) {
+        let wanted = match background {
+            true => !self.focused,
+            false => duration >= self.after,
+        };
+        if !self.enabled || !wanted || line.trim().is_empty() {
+            return;
+        }
+        let summary = match exit_code {
+            0 => "Command finished".to_string(),
+            code => format!("Command failed with exit code {}", code),
+        };
+        let body = format!(
+            "{}\ntook {}",
+            line.lines().next().unwrap_or_default(),
+            clock::format_duration(duration.as_millis() as u64)
+        );
+        match Command::new(&self.command).arg(summary).arg(body).spawn() {
+            Ok(mut child) => {
+                // Reaped on the side so the prompt does not wait for it.
+                thread::spawn(move || child.wait());
+            }
+            Err(e) => eprintln!("Cannot notify with {}: {}", self.command, e),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a command run for its output may take before it is killed.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Runs `command` and returns what it printed, or `None` if it could not
+/// start or took longer than `COMMAND_TIMEOUT`.
+pub fn run_for_output(command: &mut Command) -> Option<String> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    // Read on another thread so a full pipe cannot stall the child.
+    let mut stdout = child.stdout.take()?;
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stdout.read_to_end(&mut output);
+        output
+    });
+    let started = Instant::now();
+    loop {
+        if child.try_wait().ok()?.is_some() {
+            break;
+        }
+        if started.elapsed() > COMMAND_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let output = reader.join().ok()?;
+    Some(String::from_utf8_lossy(&output).into_owned())
+}
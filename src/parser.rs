@@ -2,11 +2,62 @@ use std::env;
 
 use toml::Table;
 
+use crate::{config::home_dir, history::Entry};
+
 #[derive(Debug)]
 pub struct ParsedCommand {
     pub command: String,
     pub args: Vec<String>,
     pub paths: Vec<String>,
+    pub redirects: Vec<Redirect>,
+}
+
+/// Where a command reads or writes instead of the terminal or a pipe. The
+/// path is empty when the operator had no word after it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Redirect {
+    /// `< file`
+    Input(String),
+    /// `> file`, or `>> file` to append.
+    Output { path: String, append: bool },
+    /// `2> file`, or `2>> file` to append.
+    Error { path: String, append: bool },
+    /// `2>&1` or `>&2`: descriptor `fd` writes where `target` does. The
+    /// target is `None` when no digit followed `>&`.
+    Duplicate { fd: u8, target: Option<u8> },
+}
+
+/// How the first character of a word was written, which decides whether
+/// a `~` or `$` there is expanded.
+#[derive(Clone, Copy, PartialEq)]
+enum Lead {
+    Bare,
+    /// In double quotes, where `$` still expands but `~` does not.
+    DoubleQuoted,
+    /// In single quotes or after a backslash.
+    Literal,
+}
+
+/// A word of a command line, or an operator outside quotes.
+enum Token {
+    Word(String, Lead),
+    /// `<`, or `>` and `>>` after the file descriptor, 1 or 2.
+    Input,
+    Output {
+        fd: u8,
+        append: bool,
+    },
+    /// `>&` after the file descriptor, and the digit after it.
+    Duplicate {
+        fd: u8,
+        target: Option<u8>,
+    },
+    /// A lone `&`, at this byte offset into the line.
+    Background(usize),
+    /// A lone `|`, at this byte offset into the line.
+    Pipe(usize),
+    /// `&&`, at the byte offset of its first `&`.
+    And(usize),
 }
 
 pub struct CommandParser {
@@ -20,23 +71,40 @@ impl CommandParser {
     }
 
     pub fn parse(&self, command: &str) -> ParsedCommand {
-        let args = self.split_command_line(&command);
-        let mut iterable = args.iter();
-        let command = iterable.next().map_or("", |v| &v).to_string();
+        let mut words = vec![];
+        let mut redirects = vec![];
+        let mut tokens = self.split_command_line(command).into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            let mut target = || match tokens.next_if(|t| matches!(t, Token::Word(..))) {
+                Some(Token::Word(word, lead)) => self.expand(word, lead),
+                _ => String::new(),
+            };
+            match token {
+                Token::Word(word, lead) => words.push((word, lead)),
+                Token::Input => redirects.push(Redirect::Input(target())),
+                Token::Output { fd: 2, append } => redirects.push(Redirect::Error {
+                    path: target(),
+                    append,
+                }),
+                Token::Output { append, .. } => redirects.push(Redirect::Output {
+                    path: target(),
+                    append,
+                }),
+                Token::Duplicate { fd, target } => {
+                    redirects.push(Redirect::Duplicate { fd, target })
+                }
+                // The line was split at these before it got here.
+                Token::Background(_) | Token::Pipe(_) | Token::And(_) => {}
+            }
+        }
+        let mut iterable = words.into_iter();
+        let command = iterable.next().unwrap_or((String::new(), Lead::Bare)).0;
         let mut args = iterable
-            .take(args.len() - 1)
-            .map(|f| f.clone())
+            .map(|(word, lead)| self.expand(word, lead))
             .collect::<Vec<_>>();
-        args.iter_mut().for_each(|f| {
-            if f.starts_with("~") {
-                *f = self.parse_path(f).join("/");
-            }
-            if f.starts_with("$") {
-                *f = self.replace_env_vars(f);
-            }
-        });
         let path = args.last().map_or("", |f| f).to_owned();
-        let paths = self.parse_path(&path);
+        // Already expanded, so a `~` left in it was quoted.
+        let paths = self.parse_path(&path, false);
         let meta = self.metadata.get(
             command
                 .split_whitespace()
@@ -56,18 +124,207 @@ impl CommandParser {
             command,
             args,
             paths,
+            redirects,
+        }
+    }
+
+    /// The commands of `line` split at each `|` outside quotes, and each
+    /// of those at `&&`, in the order they are run.
+    pub fn split_pipeline<'a>(&self, line: &'a str) -> Vec<Vec<&'a str>> {
+        let mut pipeline = vec![vec![]];
+        let mut start = 0;
+        for token in self.split_command_line(line) {
+            let (at, len) = match token {
+                Token::Pipe(at) => (at, 1),
+                Token::And(at) => (at, 2),
+                _ => continue,
+            };
+            pipeline.last_mut().unwrap().push(&line[start..at]);
+            if len == 1 {
+                pipeline.push(vec![]);
+            }
+            start = at + len;
         }
+        pipeline.last_mut().unwrap().push(&line[start..]);
+        pipeline
     }
 
-    fn split_command_line(&self, input: &str) -> Vec<String> {
+    /// `line` split at each `&` outside quotes, with whether each part runs
+    /// in the background: every part before an `&` does, and the part after
+    /// the last one does not. An `&` with no command right before it is an
+    /// error.
+    pub fn split_jobs<'a>(&self, line: &'a str) -> Result<Vec<(&'a str, bool)>, String> {
+        let mut jobs = vec![];
+        let mut start = 0;
+        let mut after_operator = true;
+        for token in self.split_command_line(line) {
+            match token {
+                Token::Background(_) if after_operator => {
+                    return Err("syntax error near `&`".to_string());
+                }
+                Token::Background(at) => {
+                    jobs.push((&line[start..at], true));
+                    start = at + 1;
+                }
+                Token::Pipe(_) | Token::And(_) => {}
+                Token::Word(..) => {
+                    after_operator = false;
+                    continue;
+                }
+                _ => continue,
+            }
+            after_operator = true;
+        }
+        if jobs.is_empty() || !line[start..].trim().is_empty() {
+            jobs.push((&line[start..], false));
+        }
+        Ok(jobs)
+    }
+
+    /// Expands a leading `~` or `$NAME` in `word`, unless `lead` says it
+    /// was quoted or escaped.
+    fn expand(&self, mut word: String, lead: Lead) -> String {
+        if word.starts_with("~") && lead == Lead::Bare {
+            word = self.parse_path(&word, true).join("/");
+        }
+        if word.starts_with("$") && lead != Lead::Literal {
+            word = self.replace_env_vars(&word);
+        }
+        word
+    }
+
+    /// Expands `!!`, `!N`, `!-N`, `!prefix` and `!$` against the history,
+    /// newest entry first. Nothing inside single quotes or after a backslash
+    /// is expanded. Returns `Ok(None)` when the line has no expansions.
+    pub fn expand_history(&self, line: &str, entries: &[Entry]) -> Result<Option<String>, String> {
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut expanded = String::new();
+        let mut changed = false;
+        let mut quote_type: Option<char> = None;
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            i += 1;
+            match c {
+                '\\' if quote_type != Some('\'') => {
+                    expanded.push(c);
+                    if let Some(&next) = chars.get(i) {
+                        expanded.push(next);
+                        i += 1;
+                    }
+                    continue;
+                }
+                '"' | '\'' if quote_type == Some(c) => quote_type = None,
+                '"' | '\'' if quote_type.is_none() => quote_type = Some(c),
+                '!' if quote_type != Some('\'') => {
+                    let spec = history_spec(&chars[i..]);
+                    if !spec.is_empty() {
+                        let word = self.resolve_history_spec(&spec, entries)?;
+                        expanded.push_str(&word);
+                        i += spec.chars().count();
+                        changed = true;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            expanded.push(c);
+        }
+
+        Ok(changed.then_some(expanded))
+    }
+
+    fn resolve_history_spec(&self, spec: &str, entries: &[Entry]) -> Result<String, String> {
+        let not_found = || format!("!{}: event not found", spec);
+        let entry = match spec {
+            "!" | "$" => entries.first(),
+            _ => match spec.parse::<isize>() {
+                Ok(n) if n < 0 => entries.get(n.unsigned_abs() - 1),
+                Ok(n) => entries
+                    .len()
+                    .checked_sub(n as usize)
+                    .filter(|_| n > 0)
+                    .and_then(|index| entries.get(index)),
+                Err(_) => entries.iter().find(|e| e.command.starts_with(spec)),
+            },
+        }
+        .ok_or_else(not_found)?;
+
+        if spec == "$" {
+            return self
+                .split_words(&entry.command)
+                .last()
+                .map(|word| word.to_string())
+                .ok_or_else(not_found);
+        }
+        Ok(entry.command.clone())
+    }
+
+    /// Splits on whitespace outside quotes, keeping the quotes themselves.
+    fn split_words<'a>(&self, input: &'a str) -> Vec<&'a str> {
+        let mut words = vec![];
+        let mut start = None;
+        let mut quote_type: Option<char> = None;
+        for (i, c) in input.char_indices() {
+            match c {
+                '"' | '\'' if quote_type == Some(c) => quote_type = None,
+                '"' | '\'' if quote_type.is_none() => quote_type = Some(c),
+                c if c.is_whitespace() && quote_type.is_none() => {
+                    if let Some(s) = start.take() {
+                        words.push(&input[s..i]);
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            start.get_or_insert(i);
+        }
+        if let Some(s) = start {
+            words.push(&input[s..]);
+        }
+        words
+    }
+
+    /// Splits `input` into words, unquoting them, and the redirection and
+    /// pipe, `&&` and background operators between them. A `1` or `2` right
+    /// before `>` is the file descriptor unless it was quoted, and `||`
+    /// stays in the word.
+    fn split_command_line(&self, input: &str) -> Vec<Token> {
         let mut args = Vec::new();
         let mut current = String::new();
+        let mut quoted = false;
         let mut in_quotes = false;
         let mut quote_type: Option<char> = None;
+        // How the first character of the current word was written.
+        let mut first = Lead::Bare;
+        let mut chars = input.char_indices().peekable();
 
-        for c in input.chars() {
+        while let Some((i, c)) = chars.next() {
+            let lead = match quote_type {
+                _ if c == '\\' => Lead::Literal,
+                Some('"') => Lead::DoubleQuoted,
+                Some(_) => Lead::Literal,
+                None => Lead::Bare,
+            };
+            let starts_word = current.is_empty();
             match c {
+                // Inside double quotes only the characters that are special
+                // there can be escaped; elsewhere any character can.
+                '\\' if quote_type != Some('\'') => match chars.next().map(|(_, next)| next) {
+                    Some(next)
+                        if quote_type.is_none() || matches!(next, '"' | '\\' | '$' | '`') =>
+                    {
+                        current.push(next)
+                    }
+                    Some(next) => {
+                        current.push(c);
+                        current.push(next);
+                    }
+                    None => current.push(c),
+                },
                 '"' | '\'' => {
+                    quoted = true;
                     if in_quotes && quote_type == Some(c) {
                         in_quotes = false;
                         quote_type = None;
@@ -80,45 +337,322 @@ impl CommandParser {
                 }
                 ' ' if !in_quotes => {
                     if !current.is_empty() {
-                        args.push(current.clone());
-                        current.clear();
+                        args.push(Token::Word(std::mem::take(&mut current), first));
+                    }
+                    quoted = false;
+                }
+                '<' if !in_quotes => {
+                    if !current.is_empty() {
+                        args.push(Token::Word(std::mem::take(&mut current), first));
+                    }
+                    quoted = false;
+                    args.push(Token::Input);
+                }
+                '>' if !in_quotes => {
+                    let fd = match current.as_str() {
+                        "1" | "2" if !quoted => std::mem::take(&mut current).parse().unwrap(),
+                        _ => 1,
+                    };
+                    if !current.is_empty() {
+                        args.push(Token::Word(std::mem::take(&mut current), first));
+                    }
+                    quoted = false;
+                    let append = chars.next_if(|&(_, c)| c == '>').is_some();
+                    if !append && chars.next_if(|&(_, c)| c == '&').is_some() {
+                        let target = chars
+                            .next_if(|&(_, c)| c.is_ascii_digit())
+                            .map(|(_, c)| c as u8 - b'0');
+                        args.push(Token::Duplicate { fd, target });
+                    } else {
+                        args.push(Token::Output { fd, append });
+                    }
+                }
+                '&' if !in_quotes => {
+                    if !current.is_empty() {
+                        args.push(Token::Word(std::mem::take(&mut current), first));
+                    }
+                    quoted = false;
+                    if chars.next_if(|&(_, c)| c == '&').is_some() {
+                        args.push(Token::And(i));
+                    } else {
+                        args.push(Token::Background(i));
+                    }
+                }
+                '|' if !in_quotes => {
+                    if chars.next_if(|&(_, c)| c == '|').is_some() {
+                        current.push_str("||");
+                    } else {
+                        if !current.is_empty() {
+                            args.push(Token::Word(std::mem::take(&mut current), first));
+                        }
+                        quoted = false;
+                        args.push(Token::Pipe(i));
                     }
                 }
                 _ => {
                     current.push(c);
                 }
             }
+            if starts_word && !current.is_empty() {
+                first = lead;
+            }
         }
 
         if !current.is_empty() {
-            args.push(current);
+            args.push(Token::Word(current, first));
         }
 
         args
     }
 
-    fn parse_path(&self, input: &str) -> Vec<String> {
+    /// Splits `input` into path components, with `home` first putting the
+    /// home directory in for `~`.
+    fn parse_path(&self, input: &str, home: bool) -> Vec<String> {
         let mut input = input.to_string();
-        let userpath = &format!(
-            "/home/{}/",
-            env::var("USER").unwrap_or_else(|_| "Unknown".to_string())
-        );
+        let userpath = &format!("{}/", home_dir().display());
 
         let home_indicators = ["~/", "~"];
 
-        for indicator in home_indicators {
-            input = input.replace(indicator, userpath);
+        if home {
+            for indicator in home_indicators {
+                input = input.replace(indicator, userpath);
+            }
         }
 
         if !input.starts_with("/") {
             input = format!("./{}", input);
         }
 
-        return input.split("/").map(|f| f.to_string()).collect::<Vec<_>>();
+        input.split("/").map(|f| f.to_string()).collect::<Vec<_>>()
     }
 
     fn replace_env_vars(&self, input: &str) -> String {
-        let val = env::var(input.replace("$", "")).unwrap_or_default();
-        return val;
+        env::var(input.replace("$", "")).unwrap_or_default()
+    }
+}
+
+/// The designator following a `!`: `!`, `$`, an optionally negative number,
+/// or a command prefix. Empty when the `!` is literal, as before a space or
+/// `=`.
+fn history_spec(rest: &[char]) -> String {
+    match rest.first() {
+        Some('!') | Some('$') => rest[0].to_string(),
+        Some(c)
+            if c.is_ascii_digit()
+                || (*c == '-' && rest.get(1).is_some_and(char::is_ascii_digit)) =>
+        {
+            let digits = rest[1..].iter().take_while(|c| c.is_ascii_digit()).count();
+            rest[..=digits].iter().collect()
+        }
+        Some(c) if !c.is_whitespace() && !matches!(c, '=' | '(' | '"' | '\'') => rest
+            .iter()
+            .take_while(|c| !c.is_whitespace() && !matches!(c, '"' | '\'' | ';' | '|' | '&'))
+            .collect(),
+        _ => String::new(),
+    }
+}
+
+/// What leaves a line unfinished, so that the next line continues it.
+#[derive(Clone, Copy)]
+pub enum Continuation {
+    /// An unclosed `'` or `"`.
+    Quote(char),
+    /// A backslash at the very end.
+    Backslash,
+    /// A trailing `|`.
+    Pipe,
+    /// A trailing `&&`.
+    And,
+}
+
+impl Continuation {
+    /// What is left open, as zsh names it in its secondary prompt.
+    pub fn name(self) -> &'static str {
+        match self {
+            Continuation::Quote('\'') => "quote",
+            Continuation::Quote(_) => "dquote",
+            Continuation::Backslash => "",
+            Continuation::Pipe => "pipe",
+            Continuation::And => "cmdand",
+        }
+    }
+
+    /// `line` as it goes before the next line: a quote keeps the line
+    /// break, an escaped line break goes away and an operator is followed
+    /// by a space.
+    pub fn join(self, line: &str) -> String {
+        match self {
+            Continuation::Quote(_) => format!("{}\n", line),
+            Continuation::Backslash => line[..line.len() - 1].to_string(),
+            Continuation::Pipe | Continuation::And => format!("{} ", line.trim_end()),
+        }
+    }
+}
+
+/// Why `line` is not a complete command yet, if it is not.
+pub fn continuation(line: &str) -> Option<Continuation> {
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', Some('\'')) => {}
+            // The escaped character is skipped either way.
+            ('\\', _) if chars.next().is_none() => return Some(Continuation::Backslash),
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            _ => {}
+        }
+    }
+    if let Some(open) = quote {
+        return Some(Continuation::Quote(open));
+    }
+    let line = line.trim_end();
+    if line.ends_with("&&") {
+        Some(Continuation::And)
+    } else if line.ends_with('|') {
+        Some(Continuation::Pipe)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_trailing_ampersand_runs_the_line_in_the_background() {
+        let parser = CommandParser::new();
+        assert_eq!(parser.split_jobs("sleep 1 &"), Ok(vec![("sleep 1 ", true)]));
+        assert_eq!(parser.split_jobs("sleep 1& "), Ok(vec![("sleep 1", true)]));
+        assert_eq!(parser.split_jobs("a && b &"), Ok(vec![("a && b ", true)]));
+    }
+
+    #[test]
+    fn quoted_escaped_and_doubled_ampersands_are_not_background() {
+        let parser = CommandParser::new();
+        for line in ["echo '&'", "echo \"&\"", "echo \\&", "echo a &&", ""] {
+            assert_eq!(parser.split_jobs(line), Ok(vec![(line, false)]), "{}", line);
+        }
+    }
+
+    #[test]
+    fn an_ampersand_mid_line_backgrounds_what_comes_before_it() {
+        let parser = CommandParser::new();
+        assert_eq!(
+            parser.split_jobs("echo one & echo two"),
+            Ok(vec![("echo one ", true), (" echo two", false)])
+        );
+        assert_eq!(
+            parser.split_jobs("a & b | c &"),
+            Ok(vec![("a ", true), (" b | c ", true)])
+        );
+        for line in ["& echo", "a & & b", "a && & b"] {
+            assert!(parser.split_jobs(line).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn pipes_and_chains_are_split_outside_quotes_only() {
+        let parser = CommandParser::new();
+        assert_eq!(
+            parser.split_pipeline("ls | grep a && wc -l|sort"),
+            [vec!["ls "], vec![" grep a ", " wc -l"], vec!["sort"]]
+        );
+        assert_eq!(
+            parser.split_pipeline("echo x > \"a | b.txt\" '&&' \\| c"),
+            [vec!["echo x > \"a | b.txt\" '&&' \\| c"]]
+        );
+        assert_eq!(parser.split_pipeline("a || b"), [vec!["a || b"]]);
+    }
+
+    #[test]
+    fn descriptors_are_duplicated_with_greater_ampersand() {
+        let parser = CommandParser::new();
+        let parsed = parser.parse("cmd > out 2>&1 1>&2 >&");
+        assert_eq!(parsed.args, Vec::<String>::new());
+        assert_eq!(
+            parsed.redirects,
+            [
+                Redirect::Output {
+                    path: "out".to_string(),
+                    append: false
+                },
+                Redirect::Duplicate {
+                    fd: 2,
+                    target: Some(1)
+                },
+                Redirect::Duplicate {
+                    fd: 1,
+                    target: Some(2)
+                },
+                Redirect::Duplicate {
+                    fd: 1,
+                    target: None
+                },
+            ]
+        );
+        assert_eq!(parser.parse("echo '1'>&2").args, ["1"]);
+    }
+
+    #[test]
+    fn quoted_or_escaped_tildes_and_dollars_stay_literal() {
+        let parser = CommandParser::new();
+        let parsed = parser.parse(r#"echo '~' \~ "~/a" '$HOME' \$HOME"#);
+        assert_eq!(parsed.args, ["~", "~", "~/a", "$HOME", "$HOME"]);
+        assert_eq!(parser.parse("cd '~'").paths, [".", "~"]);
+    }
+
+    #[test]
+    fn bare_and_double_quoted_words_are_expanded() {
+        let parser = CommandParser::new();
+        let parsed = parser.parse(r#"echo ~/a "$HOME""#);
+        let home = home_dir().display().to_string();
+        assert_eq!(parsed.args[0], format!("{}/a", home));
+        assert_eq!(parsed.args[1], env::var("HOME").unwrap_or_default());
+    }
+
+    #[test]
+    fn quotes_join_into_one_word_and_keep_what_is_inside() {
+        let parser = CommandParser::new();
+        let parsed = parser.parse(r#"echo a'b c'"d" 'it'\''s' "it's" 'a\b' "a\"b\\c\q" a\ b"#);
+        assert_eq!(
+            parsed.args,
+            ["ab cd", "it's", "it's", r"a\b", r#"a"b\c\q"#, "a b"]
+        );
+    }
+
+    #[test]
+    fn history_designators_end_where_the_word_does() {
+        let spec = |rest: &str| history_spec(&rest.chars().collect::<Vec<_>>());
+        assert_eq!(spec("! x"), "!");
+        assert_eq!(spec("$ x"), "$");
+        assert_eq!(spec("12x"), "12");
+        assert_eq!(spec("-3 x"), "-3");
+        assert_eq!(spec("git|wc"), "git");
+        assert_eq!(spec("ec\"ho\""), "ec");
+        for literal in ["", " x", "=1", "(x)", "\"x\"", "'x'"] {
+            assert_eq!(spec(literal), "", "{}", literal);
+        }
+    }
+
+    #[test]
+    fn unfinished_lines_say_what_is_open() {
+        let open = |line| continuation(line).map(Continuation::name);
+        assert_eq!(open("echo 'a"), Some("quote"));
+        assert_eq!(open("echo \"a\\\""), Some("dquote"));
+        assert_eq!(open("echo a\\"), Some(""));
+        assert_eq!(open("ls | "), Some("pipe"));
+        assert_eq!(open("true &&"), Some("cmdand"));
+        for line in ["echo 'a\\'", "echo \"a|\"", "echo a\\\\", "echo '&&'"] {
+            assert_eq!(open(line), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn continued_lines_join_the_way_their_opening_asks() {
+        assert_eq!(Continuation::Backslash.join("echo a\\"), "echo a");
+        assert_eq!(Continuation::Quote('"').join("echo \"a"), "echo \"a\n");
+        assert_eq!(Continuation::Pipe.join("ls |  "), "ls | ");
     }
 }
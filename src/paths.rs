@@ -0,0 +1,137 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::home_dir;
+
+/// The files the shell keeps, each under the XDG base directory it belongs
+/// in:
+///
+/// - `$XDG_CONFIG_HOME/a-shell/`: `config.toml`, `rc`, `profile`,
+///   `logout` and the `plugins` scripts, all written by hand.
+/// - `$XDG_STATE_HOME/a-shell/`: `history`, the visited `directories`, the
+///   `trusted-env` files and the `audit.log`.
+/// - `$XDG_DATA_HOME/a-shell/`: `recordings`.
+///
+/// Earlier versions kept them as dotfiles in the home directory and under
+/// `~/.local/share/a-shell`. `migrate` moves them over, except the first
+/// versions' `~/.ash_history`, which is in another order and is imported
+/// into the history instead.
+#[derive(Clone, Copy)]
+pub enum File {
+    Config,
+    Rc,
+    Profile,
+    Logout,
+    Plugins,
+    History,
+    Directories,
+    Recordings,
+    TrustedEnv,
+    Audit,
+}
+
+const FILES: [File; 10] = [
+    File::Config,
+    File::Rc,
+    File::Profile,
+    File::Logout,
+    File::Plugins,
+    File::History,
+    File::Directories,
+    File::Recordings,
+    File::TrustedEnv,
+    File::Audit,
+];
+
+impl File {
+    /// Where the file belongs.
+    fn xdg_path(self) -> PathBuf {
+        match self {
+            File::Config => base_dir("XDG_CONFIG_HOME", ".config").join("config.toml"),
+            File::Rc => base_dir("XDG_CONFIG_HOME", ".config").join("rc"),
+            File::Profile => base_dir("XDG_CONFIG_HOME", ".config").join("profile"),
+            File::Logout => base_dir("XDG_CONFIG_HOME", ".config").join("logout"),
+            File::Plugins => base_dir("XDG_CONFIG_HOME", ".config").join("plugins"),
+            File::History => base_dir("XDG_STATE_HOME", ".local/state").join("history"),
+            File::Directories => base_dir("XDG_STATE_HOME", ".local/state").join("directories"),
+            File::Recordings => base_dir("XDG_DATA_HOME", ".local/share").join("recordings"),
+            File::TrustedEnv => base_dir("XDG_STATE_HOME", ".local/state").join("trusted-env"),
+            File::Audit => base_dir("XDG_STATE_HOME", ".local/state").join("audit.log"),
+        }
+    }
+
+    /// Where earlier versions kept the file, if they had it.
+    fn legacy_path(self) -> Option<PathBuf> {
+        let home = home_dir();
+        let path = match self {
+            File::Config => home.join(".a-shell.toml"),
+            File::Rc => home.join(".ashellrc"),
+            File::Profile => home.join(".ashell_profile"),
+            File::Logout => home.join(".ashell_logout"),
+            File::History => home.join(".local/share/a-shell/history"),
+            File::Directories => home.join(".local/share/a-shell/directories"),
+            File::Recordings => home.join(".local/share/a-shell/recordings"),
+            File::Plugins | File::TrustedEnv | File::Audit => return None,
+        };
+        Some(path)
+    }
+}
+
+/// Where `file` is: where it belongs, unless it is still where earlier
+/// versions kept it.
+pub fn path(file: File) -> PathBuf {
+    let xdg = file.xdg_path();
+    match file.legacy_path() {
+        Some(legacy) if !xdg.exists() && legacy.exists() => legacy,
+        _ => xdg,
+    }
+}
+
+/// The history file of the first versions, newest command first.
+pub fn ash_history() -> PathBuf {
+    home_dir().join(".ash_history")
+}
+
+/// Moves the files earlier versions kept elsewhere to where they belong,
+/// unless something is there already. Files that cannot be moved stay in
+/// use where they are.
+pub fn migrate() {
+    for file in FILES {
+        let xdg = file.xdg_path();
+        let Some(legacy) = file.legacy_path() else {
+            continue;
+        };
+        if xdg == legacy || xdg.exists() || !legacy.exists() {
+            continue;
+        }
+        match move_file(&legacy, &xdg) {
+            Ok(()) => eprintln!("Moved {} to {}", legacy.display(), xdg.display()),
+            Err(e) => eprintln!(
+                "Cannot move {} to {}: {}",
+                legacy.display(),
+                xdg.display(),
+                e
+            ),
+        }
+    }
+}
+
+/// The shell's directory under the base directory named by `var`, or
+/// under `default` in the home directory. Relative paths in `var` are
+/// ignored, as the spec asks.
+fn base_dir(var: &str, default: &str) -> PathBuf {
+    let base = env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|base| base.is_absolute())
+        .unwrap_or_else(|| home_dir().join(default));
+    base.join("a-shell")
+}
+
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to)
+}
@@ -0,0 +1,103 @@
+use std::collections::HashSet;
+
+use crate::{
+    fuzzy::{fuzzy_match, FuzzyMatch},
+    history::Entry,
+    theme::{Role, Theme},
+};
+
+const MAX_ROWS: usize = 12;
+
+/// Full-width fuzzy finder over the history, drawn below the prompt.
+pub struct HistoryPicker {
+    pub query: String,
+    /// The line being edited when the picker opened, restored on cancel.
+    pub original: String,
+    results: Vec<(String, FuzzyMatch)>,
+    selected: usize,
+    scroll: usize,
+    total: usize,
+}
+
+impl HistoryPicker {
+    pub fn new(query: &str, entries: &[Entry]) -> Self {
+        let mut picker = HistoryPicker {
+            query: query.to_string(),
+            original: query.to_string(),
+            results: vec![],
+            selected: 0,
+            scroll: 0,
+            total: 0,
+        };
+        picker.update(entries);
+        picker
+    }
+
+    /// Re-filters the history for the current query. Duplicates are shown
+    /// once and ties keep the most recent entry first.
+    pub fn update(&mut self, entries: &[Entry]) {
+        let mut seen = HashSet::new();
+        self.results = entries
+            .iter()
+            .map(|entry| &entry.command)
+            .filter(|command| seen.insert(command.as_str()))
+            .filter_map(|command| Some((command.clone(), fuzzy_match(&self.query, command)?)))
+            .collect();
+        self.total = seen.len();
+        self.results.sort_by_key(|(_, m)| -m.score);
+        self.selected = 0;
+        self.scroll = 0;
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.results.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn selected(&self) -> Option<&str> {
+        self.results.get(self.selected).map(|(c, _)| c.as_str())
+    }
+
+    pub fn status(&self) -> String {
+        format!("  {}/{}", self.results.len(), self.total)
+    }
+
+    /// Result rows that fit in `height` rows, scrolled so the selection is
+    /// visible, with matched characters highlighted.
+    pub fn lines(&mut self, height: usize, width: usize, theme: &Theme) -> Vec<String> {
+        let rows = height.saturating_sub(2).clamp(1, MAX_ROWS);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + rows {
+            self.scroll = self.selected + 1 - rows;
+        }
+
+        self.results
+            .iter()
+            .enumerate()
+            .skip(self.scroll)
+            .take(rows)
+            .map(|(i, (command, m))| {
+                let is_selected = i == self.selected;
+                let mut line = if is_selected {
+                    format!("{} ", theme.paint(Role::Pointer, ">"))
+                } else {
+                    "  ".to_string()
+                };
+                for (j, c) in command.chars().take(width.saturating_sub(3)).enumerate() {
+                    match (m.positions.contains(&j), is_selected) {
+                        (true, _) => line.push_str(&theme.paint(Role::Match, &c.to_string())),
+                        (false, true) => line.push_str(&format!("\x1b[1m{}\x1b[0m", c)),
+                        (false, false) => line.push(c),
+                    }
+                }
+                line
+            })
+            .collect()
+    }
+}
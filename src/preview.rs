@@ -0,0 +1,101 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::{
+    clock,
+    theme::{Role, Theme},
+};
+
+/// Text lines shown of a text file.
+const PREVIEW_LINES: usize = 5;
+/// How much of a file is read to find those lines.
+const PREVIEW_BYTES: u64 = 4096;
+
+/// A few lines describing `path` for the completion menu: its type, size
+/// and modification time, then the first lines of a text file or the
+/// number of entries in a directory. Lines are cut to `width` columns.
+pub fn preview(path: &Path, width: usize, theme: &Theme) -> Vec<String> {
+    let Ok(link) = fs::symlink_metadata(path) else {
+        return vec![];
+    };
+    let metadata = fs::metadata(path).unwrap_or_else(|_| link.clone());
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .and_then(|since| clock::local(since.as_secs()))
+        .map(|t| {
+            format!(
+                ", modified {}-{:02}-{:02} {:02}:{:02}",
+                t.year, t.month, t.day, t.hour, t.minute
+            )
+        })
+        .unwrap_or_default();
+    let target = if link.file_type().is_symlink() {
+        fs::read_link(path)
+            .map(|target| format!(" -> {}", target.display()))
+            .unwrap_or_default()
+    } else {
+        String::new()
+    };
+
+    let mut lines = vec![];
+    if metadata.is_dir() {
+        let entries = fs::read_dir(path).map_or(0, |entries| entries.count());
+        lines.push(format!(
+            "directory{}, {} entries{}",
+            target, entries, modified
+        ));
+    } else {
+        lines.push(format!(
+            "file{}, {}{}",
+            target,
+            format_size(metadata.len()),
+            modified
+        ));
+        lines.extend(text_lines(path));
+    }
+    lines
+        .into_iter()
+        .map(|line| {
+            let cut = line.chars().take(width).collect::<String>();
+            theme.paint(Role::Description, &cut)
+        })
+        .collect()
+}
+
+/// The first lines of a file that looks like text, or none.
+fn text_lines(path: &Path) -> Vec<String> {
+    let mut head = vec![];
+    let read = File::open(path).and_then(|file| file.take(PREVIEW_BYTES).read_to_end(&mut head));
+    if read.is_err() || head.contains(&0) {
+        return vec![];
+    }
+    String::from_utf8_lossy(&head)
+        .lines()
+        .take(PREVIEW_LINES)
+        .map(|line| {
+            line.replace('\t', "    ")
+                .chars()
+                .filter(|c| !c.is_control())
+                .collect()
+        })
+        .collect()
+}
+
+/// Formats a byte count like `ls -h`, e.g. `812B` or `4.2K`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, UNITS[unit])
+}
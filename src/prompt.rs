@@ -0,0 +1,549 @@
+use std::env;
+use std::process::Command;
+use std::time::Duration;
+
+use crossterm::terminal;
+
+use crate::{
+    clock,
+    config::{home_dir, Config},
+    devenv, editor,
+    git::{self, GitCache},
+    output::run_for_output,
+    parser::Continuation,
+    script::Scripts,
+    theme::{Role, Theme},
+};
+
+/// Commands quicker than this get no duration shown, unless
+/// `[prompt] duration_threshold_ms` or the segment's `threshold_ms` says
+/// otherwise.
+const DURATION_THRESHOLD_MS: u64 = 5000;
+
+/// What the prompt can show about the previous command.
+pub struct PromptState {
+    pub exit_code: i32,
+    /// Background jobs that are running or stopped.
+    pub jobs: usize,
+    /// How long it ran, if anything ran yet.
+    pub duration: Option<Duration>,
+}
+
+/// The prompt drawn before the input. It comes from the first of these
+/// that is set:
+///
+/// - a PS1-style template in the `PROMPT` environment variable
+/// - the same in `[prompt] format` in the config file
+/// - the output of `[prompt] command`, such as
+///   `starship prompt --status=$? --cmd-duration=$A_SHELL_DURATION_MS
+///   --jobs=$A_SHELL_JOBS --terminal-width=$COLUMNS`; it also gets the exit
+///   status as `A_SHELL_STATUS`, and the rest apply when it prints nothing
+/// - a list of `[[prompt.segments]]` in the config file
+///
+/// and otherwise shows the working directory, with `user@host` over SSH and
+/// a `#` in the theme's warning style for root.
+///
+/// Templates understand these escapes:
+///
+/// - `\u` user, `\h` host name, `\w` working directory with `~` for home,
+///   `\W` its last component or `~` at home
+/// - `\t` time as HH:MM:SS, `\?` exit status of the last command, `\j`
+///   number of background jobs
+/// - `\g` git branch, empty outside a repository; the git segment also
+///   shows commits ahead of and behind upstream and `*` for changes
+/// - `\$` `#` for root and `$` otherwise, `\e` escape, `\\` backslash
+/// - `\n` newline; lines before the last are drawn above the input
+///
+/// Segments are drawn in order, each followed by its separator, and left
+/// out when empty:
+///
+/// ```toml
+/// [[prompt.segments]]
+/// type = "cwd"        # also git, python, node, rust, duration, status,
+///                     # jobs, command, script, text and newline
+/// fg = "blue"         # a color name, 0-255 or #rrggbb; bg likewise
+/// bold = true
+/// format = "[{}]"     # where the value goes, `{}` by default
+/// separator = " > "   # a space by default
+///
+/// [[prompt.segments]]
+/// type = "command"
+/// run = "date +%H:%M"
+///
+/// [[prompt.segments]]
+/// type = "script"
+/// name = "count"      # drawn by the function a script gave `segment`
+/// ```
+///
+/// A line left unfinished by an open quote, a trailing backslash, `|` or
+/// `&&` is continued on the next one after `[prompt] continuation`, `{}> `
+/// by default, where `{}` says what is open: `quote`, `dquote`, `pipe`,
+/// `cmdand` or nothing for a backslash.
+pub struct Prompt {
+    template: Option<String>,
+    /// Prints the whole prompt, like `starship prompt`.
+    command: Option<String>,
+    continuation: String,
+    segments: Vec<Segment>,
+    /// Rendered once per command line by `refresh`, since segments may run
+    /// commands.
+    rendered: String,
+    git: GitCache,
+    duration_threshold_ms: u64,
+    /// Print how long a slow command took once it finishes.
+    show_duration: bool,
+    /// Left after output that does not end in a newline, like zsh's
+    /// `PROMPT_EOL_MARK`; `[prompt] eol_mark`, empty for none.
+    eol_mark: String,
+    directory: DirectoryStyle,
+    theme: Theme,
+    scripts: Scripts,
+}
+
+/// How `\w` and the cwd segment shorten the working directory, set with
+/// `[prompt] abbreviate` and `[prompt] directory_depth`.
+struct DirectoryStyle {
+    /// Cut every component but the last to its first letter, fish style:
+    /// `~/p/a/src`.
+    abbreviate: bool,
+    /// Show only this many trailing components, after `…/`.
+    depth: Option<usize>,
+}
+
+struct Segment {
+    kind: SegmentKind,
+    /// SGR parameters, empty for none.
+    style: String,
+    format: String,
+    separator: String,
+}
+
+enum SegmentKind {
+    Cwd,
+    Git,
+    Python,
+    Node,
+    Rust,
+    Duration { threshold_ms: u64 },
+    Status,
+    Jobs,
+    Command(String),
+    Script(String),
+    Text(String),
+    Newline,
+}
+
+impl Prompt {
+    pub fn from_config(config: &Config, theme: Theme, scripts: &Scripts) -> Self {
+        let template = env::var("PROMPT")
+            .ok()
+            .or_else(|| config.get_str("prompt", "format").map(str::to_string));
+        let duration_threshold_ms = config
+            .get_int("prompt", "duration_threshold_ms")
+            .and_then(|ms| u64::try_from(ms).ok())
+            .unwrap_or(DURATION_THRESHOLD_MS);
+        let segments = config
+            .get("prompt", "segments")
+            .and_then(|segments| segments.as_array())
+            .map(|segments| {
+                segments
+                    .iter()
+                    .filter_map(|segment| {
+                        Segment::from_value(segment, duration_threshold_ms, &theme)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Prompt {
+            template,
+            command: config.get_str("prompt", "command").map(str::to_string),
+            continuation: config
+                .get_str("prompt", "continuation")
+                .unwrap_or("{}> ")
+                .to_string(),
+            segments,
+            rendered: String::new(),
+            git: GitCache::default(),
+            duration_threshold_ms,
+            show_duration: config.get_bool("prompt", "show_duration").unwrap_or(true),
+            eol_mark: config
+                .get_str("prompt", "eol_mark")
+                .unwrap_or("%")
+                .to_string(),
+            directory: DirectoryStyle {
+                abbreviate: config.get_bool("prompt", "abbreviate").unwrap_or(false),
+                depth: config
+                    .get_int("prompt", "directory_depth")
+                    .and_then(|depth| usize::try_from(depth).ok())
+                    .filter(|depth| *depth > 0),
+            },
+            theme,
+            scripts: scripts.clone(),
+        }
+    }
+
+    /// Renders the prompt for the next command line.
+    pub fn refresh(&mut self, state: &PromptState) {
+        let external = self
+            .command
+            .as_ref()
+            .filter(|_| self.template.is_none())
+            .and_then(|command| run_prompt_command(command, state));
+        self.rendered = if let Some(template) = &self.template {
+            expand(template, state, &self.directory)
+        } else if let Some(external) = external {
+            external
+        } else if !self.segments.is_empty() {
+            let git = &mut self.git;
+            self.segments
+                .iter()
+                .filter_map(|segment| segment.render(state, git, &self.directory, &self.scripts))
+                .collect()
+        } else {
+            self.default_prompt(state)
+        };
+    }
+
+    /// The working directory, after `user@host` when logged in over SSH
+    /// and before the number of background jobs if there are any, and a `#`
+    /// in the warning style for root.
+    fn default_prompt(&self, state: &PromptState) -> String {
+        let mut text = String::new();
+        if is_ssh() {
+            text.push_str(&format!("{}@{} ", user(), host()));
+        }
+        text.push_str(&format!("\u{f07c}  {} ", self.directory.current_dir()));
+        if state.jobs > 0 {
+            text.push_str(&format!("{} ", jobs(state.jobs)));
+        }
+        if is_root() {
+            let symbol = self.theme.paint(Role::Warning, "#");
+            format!("{}{} ", self.theme.paint(Role::Prompt, &text), symbol)
+        } else {
+            text.push_str("\u{ea9c} ");
+            self.theme.paint(Role::Prompt, &text)
+        }
+    }
+
+    /// Uses `template` instead of whatever the config set.
+    pub fn set_template(&mut self, template: String) {
+        self.template = Some(template);
+    }
+
+    /// The prompt for a line continuing an unfinished one, like `dquote> `.
+    pub fn continuation(&self, continuation: Continuation) -> String {
+        let text = self.continuation.replace("{}", continuation.name());
+        self.theme.paint(Role::Prompt, &text)
+    }
+
+    /// The prompt text, which may contain SGR color sequences.
+    pub fn text(&self) -> &str {
+        &self.rendered
+    }
+
+    /// A line saying how long a command took, for commands slower than the
+    /// threshold.
+    pub fn duration_notice(&self, duration: Duration) -> Option<String> {
+        let millis = duration.as_millis() as u64;
+        (self.show_duration && millis >= self.duration_threshold_ms).then(|| {
+            let text = format!("took {}", clock::format_duration(millis));
+            self.theme.paint(Role::Notice, &text)
+        })
+    }
+
+    /// What to print before the prompt so it starts on a line of its own,
+    /// for a terminal `width` columns wide. Output that did not end in a
+    /// newline is followed by the marker in reverse video.
+    ///
+    /// This is zsh's trick: the marker and enough spaces to fill the line
+    /// only wrap onto a new line when the cursor was not at its start, and
+    /// the carriage return and erase then leave nothing on the fresh line.
+    pub fn line_start(&self, width: usize) -> String {
+        if self.eol_mark.is_empty() {
+            return String::new();
+        }
+        let mark_width = visible_width(&self.eol_mark).min(width);
+        let style = self.theme.sgr("bold reverse");
+        let mark = if style.is_empty() {
+            self.eol_mark.clone()
+        } else {
+            format!("\x1b[{}m{}\x1b[0m", style, self.eol_mark)
+        };
+        format!("{}{}\r\x1b[K", mark, " ".repeat(width - mark_width))
+    }
+}
+
+impl Segment {
+    fn from_value(value: &toml::Value, duration_threshold_ms: u64, theme: &Theme) -> Option<Self> {
+        let string = |key: &str| value.get(key).and_then(|v| v.as_str());
+        let kind = match string("type")? {
+            "cwd" => SegmentKind::Cwd,
+            "git" => SegmentKind::Git,
+            "python" => SegmentKind::Python,
+            "node" => SegmentKind::Node,
+            "rust" => SegmentKind::Rust,
+            "duration" => SegmentKind::Duration {
+                threshold_ms: value
+                    .get("threshold_ms")
+                    .and_then(|v| v.as_integer())
+                    .and_then(|ms| u64::try_from(ms).ok())
+                    .unwrap_or(duration_threshold_ms),
+            },
+            "status" => SegmentKind::Status,
+            "jobs" => SegmentKind::Jobs,
+            "command" => SegmentKind::Command(string("run")?.to_string()),
+            "script" => SegmentKind::Script(string("name")?.to_string()),
+            "text" => SegmentKind::Text(string("text")?.to_string()),
+            "newline" => SegmentKind::Newline,
+            _ => return None,
+        };
+        let mut style = vec![];
+        if value.get("bold").and_then(|v| v.as_bool()) == Some(true) {
+            style.push("bold");
+        }
+        style.extend(string("fg"));
+        if let Some(bg) = string("bg") {
+            style.extend(["on", bg]);
+        }
+        Some(Segment {
+            kind,
+            style: theme.sgr(&style.join(" ")),
+            format: string("format").unwrap_or("{}").to_string(),
+            separator: string("separator").unwrap_or(" ").to_string(),
+        })
+    }
+
+    fn render(
+        &self,
+        state: &PromptState,
+        git: &mut GitCache,
+        directory: &DirectoryStyle,
+        scripts: &Scripts,
+    ) -> Option<String> {
+        let value = match &self.kind {
+            SegmentKind::Cwd => directory.current_dir(),
+            SegmentKind::Git => git.status()?.summary(),
+            SegmentKind::Python => devenv::python()?,
+            SegmentKind::Node => devenv::node()?,
+            SegmentKind::Rust => devenv::rust()?,
+            SegmentKind::Duration { threshold_ms } => {
+                let millis = state.duration?.as_millis() as u64;
+                if millis < *threshold_ms {
+                    return None;
+                }
+                clock::format_duration(millis)
+            }
+            SegmentKind::Status if state.exit_code == 0 => return None,
+            SegmentKind::Status => state.exit_code.to_string(),
+            SegmentKind::Jobs if state.jobs == 0 => return None,
+            SegmentKind::Jobs => jobs(state.jobs),
+            SegmentKind::Command(run) => {
+                let output = run_for_output(Command::new("sh").arg("-c").arg(run))?;
+                output.trim_end().to_string()
+            }
+            SegmentKind::Script(name) => scripts.segment(name, state.exit_code, state.jobs)?,
+            SegmentKind::Text(text) => text.clone(),
+            SegmentKind::Newline => return Some("\n".to_string()),
+        };
+        if value.is_empty() {
+            return None;
+        }
+        let text = self.format.replace("{}", &value);
+        Some(if self.style.is_empty() {
+            format!("{}{}", text, self.separator)
+        } else {
+            format!("\x1b[{}m{}\x1b[0m{}", self.style, text, self.separator)
+        })
+    }
+}
+
+/// The characters that may follow a backslash in a template.
+const ESCAPES: &str = "uhwWt?jg$en\\";
+
+/// What is wrong with `template`, if anything.
+pub fn check_template(template: &str) -> Result<(), String> {
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            continue;
+        }
+        match chars.next() {
+            Some(escape) if ESCAPES.contains(escape) => {}
+            Some(escape) => return Err(format!("unknown escape \\{}", escape)),
+            None => return Err("trailing backslash".to_string()),
+        }
+    }
+    Ok(())
+}
+
+fn expand(template: &str, state: &PromptState, directory: &DirectoryStyle) -> String {
+    let mut prompt = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            prompt.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('u') => prompt.push_str(&user()),
+            Some('h') => prompt.push_str(&host()),
+            Some('w') => prompt.push_str(&directory.current_dir()),
+            Some('W') => prompt.push_str(&current_dir_name()),
+            Some('t') => {
+                if let Some(t) = clock::local(clock::now()) {
+                    prompt.push_str(&format!("{:02}:{:02}:{:02}", t.hour, t.minute, t.second));
+                }
+            }
+            Some('?') => prompt.push_str(&state.exit_code.to_string()),
+            Some('j') => prompt.push_str(&state.jobs.to_string()),
+            Some('g') => prompt.push_str(&git::branch().unwrap_or_default()),
+            Some('$') => prompt.push(if is_root() { '#' } else { '$' }),
+            Some('e') => prompt.push('\x1b'),
+            Some('n') => prompt.push('\n'),
+            Some('\\') => prompt.push('\\'),
+            Some(other) => {
+                prompt.push('\\');
+                prompt.push(other);
+            }
+            None => prompt.push('\\'),
+        }
+    }
+    prompt
+}
+
+/// Columns `prompt` takes on screen, leaving out escape sequences.
+pub fn visible_width(prompt: &str) -> usize {
+    let mut visible = String::new();
+    let mut chars = prompt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' && chars.peek() == Some(&'[') {
+            chars.next();
+            // Skip to the final byte of the control sequence.
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+            continue;
+        }
+        if c == '\x1b' && chars.peek() == Some(&']') {
+            // Skip the operating system command, which ends in BEL or ST.
+            while let Some(c) = chars.next() {
+                if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                    break;
+                }
+            }
+            continue;
+        }
+        visible.push(c);
+    }
+    editor::display_width(&visible) as usize
+}
+
+/// The output of a prompt command, without the newline it ends with. The
+/// command sees the last exit status as `$?` and in `A_SHELL_STATUS`, how
+/// long the last command ran in `A_SHELL_DURATION_MS`, the number of jobs in
+/// `A_SHELL_JOBS` and the terminal width in `COLUMNS`.
+fn run_prompt_command(command: &str, state: &PromptState) -> Option<String> {
+    let status = state.exit_code.clamp(0, 255);
+    let duration = state.duration.map_or(0, |d| d.as_millis());
+    let (columns, _) = terminal::size().unwrap_or((80, 24));
+    let output = run_for_output(
+        Command::new("sh")
+            .arg("-c")
+            .arg(format!("(exit {}); {}", status, command))
+            .env("A_SHELL_STATUS", status.to_string())
+            .env("A_SHELL_DURATION_MS", duration.to_string())
+            .env("A_SHELL_JOBS", state.jobs.to_string())
+            .env("COLUMNS", columns.to_string()),
+    )?;
+    let prompt = output.trim_end_matches('\n');
+    (!prompt.is_empty()).then(|| prompt.to_string())
+}
+
+/// Like `[2⚙]` for two background jobs.
+fn jobs(count: usize) -> String {
+    format!("[{}\u{2699}]", count)
+}
+
+/// Whether this shell runs in an SSH session.
+fn is_ssh() -> bool {
+    ["SSH_CONNECTION", "SSH_CLIENT", "SSH_TTY"]
+        .iter()
+        .any(|name| env::var_os(name).is_some_and(|value| !value.is_empty()))
+}
+
+fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() == 0 }
+}
+
+pub fn user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("LOGNAME"))
+        .unwrap_or_default()
+}
+
+pub fn host() -> String {
+    // Like bash's `\h`, only up to the first dot.
+    let host = hostname();
+    host.split('.').next().unwrap_or_default().to_string()
+}
+
+/// The full host name, as the system has it.
+pub fn hostname() -> String {
+    let mut name = [0u8; 256];
+    // SAFETY: the buffer is writable for its whole length, which is passed.
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } != 0 {
+        return String::new();
+    }
+    let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..end]).into_owned()
+}
+
+impl DirectoryStyle {
+    /// The working directory with `~` for home, shortened as configured.
+    fn current_dir(&self) -> String {
+        let path = home_relative_dir();
+        let mut parts = path.split('/').map(String::from).collect::<Vec<_>>();
+        let mut prefix = "";
+        if let Some(depth) = self.depth.filter(|depth| parts.len() > depth + 1) {
+            parts.drain(..parts.len() - depth);
+            prefix = "…/";
+        }
+        if self.abbreviate {
+            let last = parts.len() - 1;
+            for part in &mut parts[..last] {
+                *part = abbreviate(part);
+            }
+        }
+        format!("{}{}", prefix, parts.join("/"))
+    }
+}
+
+/// The working directory with `~` for home.
+pub fn home_relative_dir() -> String {
+    let cwd = env::current_dir().unwrap_or_default();
+    match cwd.strip_prefix(home_dir()) {
+        Ok(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Ok(rest) => format!("~/{}", rest.display()),
+        Err(_) => cwd.display().to_string(),
+    }
+}
+
+/// The first letter of a directory name, or two for a dotted one.
+fn abbreviate(part: &str) -> String {
+    let take = if part.starts_with('.') { 2 } else { 1 };
+    part.chars().take(take).collect()
+}
+
+/// The last component of the working directory, or `~` for home.
+fn current_dir_name() -> String {
+    let cwd = env::current_dir().unwrap_or_default();
+    if cwd == home_dir() {
+        return "~".to_string();
+    }
+    cwd.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| cwd.display().to_string())
+}
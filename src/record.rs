@@ -0,0 +1,238 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossterm::terminal::{self, disable_raw_mode, enable_raw_mode};
+
+use crate::{clock, json};
+
+/// Set in a recorded session to the file it is being recorded to, so
+/// `record stop` knows to end it.
+pub const RECORDING_VAR: &str = "A_SHELL_RECORDING";
+/// Replay never waits longer than this between two events.
+const MAX_IDLE: f64 = 2.0;
+
+/// Records a nested shell session on a pseudo-terminal into an asciicast v2
+/// file, returning when that session exits.
+pub fn record(path: &Path) -> io::Result<()> {
+    let (cols, rows) = terminal::size().unwrap_or((80, 24));
+    let mut cast = File::create(path)?;
+    writeln!(
+        cast,
+        "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {}, \"env\": {{\"SHELL\": \"a-shell\", \"TERM\": {}}}}}",
+        cols,
+        rows,
+        clock::now(),
+        json::quote(&env::var("TERM").unwrap_or_default())
+    )?;
+
+    let mut command = Command::new(env::current_exe()?);
+    command.env(RECORDING_VAR, path);
+    let (mut master, mut child) = spawn_on_pty(command, cols, rows)?;
+    let started = Instant::now();
+    let mut size = (cols, rows);
+    // Output can split a UTF-8 sequence between reads.
+    let mut pending = Vec::new();
+    let mut buffer = [0u8; 8192];
+    let mut stdout = io::stdout();
+
+    enable_raw_mode()?;
+    let result = (|| -> io::Result<()> {
+        loop {
+            let mut fds = [
+                libc::pollfd {
+                    fd: libc::STDIN_FILENO,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: master.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            // SAFETY: `fds` is a valid array for the duration of the call.
+            if unsafe { libc::poll(fds.as_mut_ptr(), 2, 200) } < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(error);
+            }
+
+            if let Ok(new_size) = terminal::size() {
+                if new_size != size {
+                    size = new_size;
+                    set_window_size(&master, size.0, size.1);
+                    write_event(&mut cast, started, "r", &format!("{}x{}", size.0, size.1))?;
+                }
+            }
+            if fds[0].revents & libc::POLLIN != 0 {
+                let read = io::stdin().read(&mut buffer)?;
+                if read == 0 {
+                    return Ok(());
+                }
+                master.write_all(&buffer[..read])?;
+            }
+            if fds[1].revents & (libc::POLLIN | libc::POLLHUP) != 0 {
+                // Reading fails with EIO once the session has exited.
+                let read = match master.read(&mut buffer) {
+                    Ok(0) | Err(_) => return Ok(()),
+                    Ok(read) => read,
+                };
+                stdout.write_all(&buffer[..read])?;
+                stdout.flush()?;
+                pending.extend_from_slice(&buffer[..read]);
+                let text = take_utf8(&mut pending);
+                if !text.is_empty() {
+                    write_event(&mut cast, started, "o", &text)?;
+                }
+            }
+        }
+    })();
+    disable_raw_mode()?;
+    let _ = child.wait();
+    result
+}
+
+/// Plays an asciicast file back, `speed` times faster than recorded.
+pub fn replay(path: &Path, speed: f64) -> io::Result<()> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not an asciicast v2 file");
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header = lines.next().ok_or_else(invalid)??;
+    if !header.contains("\"version\": 2") && !header.contains("\"version\":2") {
+        return Err(invalid());
+    }
+
+    let mut stdout = io::stdout();
+    let mut last = 0.0;
+    for line in lines {
+        let line = line?;
+        let Some((time, kind, data)) = parse_event(&line) else {
+            continue;
+        };
+        let wait = ((time - last) / speed).clamp(0.0, MAX_IDLE);
+        thread::sleep(Duration::from_secs_f64(wait));
+        last = time;
+        if kind == "o" {
+            stdout.write_all(data.as_bytes())?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Where `record start` saves by default: a timestamped file under
+/// `dir`, which is created if needed.
+pub fn default_path(dir: &Path) -> io::Result<std::path::PathBuf> {
+    fs::create_dir_all(dir)?;
+    let name = match clock::local(clock::now()) {
+        Some(t) => format!(
+            "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}.cast",
+            t.year, t.month, t.day, t.hour, t.minute, t.second
+        ),
+        None => format!("{}.cast", clock::now()),
+    };
+    Ok(dir.join(name))
+}
+
+/// Runs `command` as a session leader on a new pseudo-terminal of the
+/// given size, returning the master side and the child.
+pub(crate) fn spawn_on_pty(
+    mut command: Command,
+    cols: u16,
+    rows: u16,
+) -> io::Result<(File, std::process::Child)> {
+    let (mut master, mut slave) = (0, 0);
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: the out-pointers are valid and the returned descriptors are
+    // owned by the `File`s created right after.
+    if unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            &size,
+        )
+    } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    let master = unsafe { File::from_raw_fd(master) };
+    let slave = unsafe { File::from_raw_fd(slave) };
+    // Keep the session's children from inheriting the master side.
+    unsafe { libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+
+    command
+        .stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave);
+    // SAFETY: only async-signal-safe calls between fork and exec.
+    unsafe {
+        command.pre_exec(|| {
+            // Become a session leader with the pty as controlling terminal.
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn()?;
+    Ok((master, child))
+}
+
+fn set_window_size(master: &File, cols: u16, rows: u16) {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: `size` outlives the call.
+    unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+}
+
+fn write_event(cast: &mut File, started: Instant, kind: &str, data: &str) -> io::Result<()> {
+    writeln!(
+        cast,
+        "[{:.6}, \"{}\", {}]",
+        started.elapsed().as_secs_f64(),
+        kind,
+        json::quote(data)
+    )
+}
+
+/// Takes the longest valid UTF-8 prefix out of `bytes`, replacing invalid
+/// sequences but leaving an incomplete one at the end for the next read.
+fn take_utf8(bytes: &mut Vec<u8>) -> String {
+    let end = match std::str::from_utf8(bytes) {
+        Ok(_) => bytes.len(),
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => bytes.len(),
+    };
+    let text = String::from_utf8_lossy(&bytes[..end]).into_owned();
+    bytes.drain(..end);
+    text
+}
+
+/// Splits an event line such as `[1.5, "o", "text"]`.
+fn parse_event(line: &str) -> Option<(f64, String, String)> {
+    let rest = line.trim().strip_prefix('[')?;
+    let (time, rest) = rest.split_once(',')?;
+    let (kind, rest) = json::parse_string(rest.trim_start())?;
+    let rest = rest.trim_start().strip_prefix(',')?;
+    let (data, _) = json::parse_string(rest.trim_start())?;
+    Some((time.trim().parse().ok()?, kind, data))
+}
@@ -0,0 +1,163 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::{AsFd, AsRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::{error::ShellError, parser::Redirect};
+
+/// The files a command's redirections opened. Each operator opens or
+/// creates its file, as in other shells, and the last one for a stream
+/// wins. `2>&1` and `>&2` take the other stream's file as it is at that
+/// point, so their order against the others matters.
+#[derive(Default)]
+pub struct Redirections {
+    pub stdin: Option<File>,
+    pub stdout: Option<File>,
+    pub stderr: Option<File>,
+    /// `2>&1` with standard output left as it is, which may be a pipe only
+    /// the command's own descriptors lead to.
+    pub stderr_to_stdout: bool,
+}
+
+impl Redirections {
+    pub fn open(redirects: &[Redirect]) -> Result<Self, ShellError> {
+        let mut redirections = Redirections::default();
+        for redirect in redirects {
+            match redirect {
+                Redirect::Input(path) => redirections.stdin = Some(open(path, None)?),
+                Redirect::Output { path, append } => {
+                    // An earlier `2>&1` keeps what standard output was.
+                    if std::mem::take(&mut redirections.stderr_to_stdout) {
+                        redirections.stderr = Some(copy(io::stdout().as_fd())?);
+                    }
+                    redirections.stdout = Some(open(path, Some(*append))?)
+                }
+                Redirect::Error { path, append } => {
+                    redirections.stderr_to_stdout = false;
+                    redirections.stderr = Some(open(path, Some(*append))?)
+                }
+                Redirect::Duplicate { fd, target } if Some(*fd) == *target => {}
+                Redirect::Duplicate {
+                    fd: 2,
+                    target: Some(1),
+                } => match &redirections.stdout {
+                    Some(file) => redirections.stderr = Some(copy(file.as_fd())?),
+                    None => {
+                        redirections.stderr = None;
+                        redirections.stderr_to_stdout = true;
+                    }
+                },
+                Redirect::Duplicate {
+                    fd: 1,
+                    target: Some(2),
+                } => {
+                    let stderr = match &redirections.stderr {
+                        Some(file) => copy(file.as_fd())?,
+                        None => copy(io::stderr().as_fd())?,
+                    };
+                    redirections.stdout = Some(stderr);
+                }
+                Redirect::Duplicate { .. } => {
+                    return Err(ShellError::Parse(
+                        "only 1 and 2 can be duplicated, as in 2>&1 or >&2".to_string(),
+                    ))
+                }
+            }
+        }
+        Ok(redirections)
+    }
+
+    /// Points the shell's own standard streams at the files, for a builtin,
+    /// until the returned guard is dropped.
+    pub fn apply(&self) -> io::Result<Applied> {
+        let mut applied = Applied { saved: vec![] };
+        let streams = [
+            (&self.stdin, libc::STDIN_FILENO),
+            (&self.stdout, libc::STDOUT_FILENO),
+            (&self.stderr, libc::STDERR_FILENO),
+        ];
+        for (file, fd) in streams {
+            let Some(file) = file else {
+                continue;
+            };
+            // SAFETY: both descriptors are open; the copy of `fd` is owned by
+            // `applied` and closed when it is put back.
+            let saved = unsafe { libc::dup(fd) };
+            if saved < 0 || unsafe { libc::dup2(file.as_raw_fd(), fd) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            applied.saved.push((fd, saved));
+        }
+        if self.stderr_to_stdout {
+            // SAFETY: as above.
+            let saved = unsafe { libc::dup(libc::STDERR_FILENO) };
+            if saved < 0 || unsafe { libc::dup2(libc::STDOUT_FILENO, libc::STDERR_FILENO) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            applied.saved.push((libc::STDERR_FILENO, saved));
+        }
+        Ok(applied)
+    }
+}
+
+/// Standard streams pointed elsewhere by `Redirections::apply`, put back on
+/// drop.
+pub struct Applied {
+    /// Each stream with a copy of what it was.
+    saved: Vec<(RawFd, RawFd)>,
+}
+
+impl Drop for Applied {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+        for (fd, saved) in self.saved.drain(..) {
+            // SAFETY: `saved` is a descriptor this guard owns.
+            unsafe {
+                libc::dup2(saved, fd);
+                libc::close(saved);
+            }
+        }
+    }
+}
+
+/// Points the standard error of `command` at its standard output once both
+/// are set up, for `2>&1` when that is a pipe.
+pub fn stderr_to_stdout(command: &mut Command) {
+    // SAFETY: only an async-signal-safe call between fork and exec, which
+    // std makes after putting the standard streams in place.
+    unsafe {
+        command.pre_exec(|| {
+            if libc::dup2(libc::STDOUT_FILENO, libc::STDERR_FILENO) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// A file of its own for what `fd` refers to.
+fn copy(fd: std::os::fd::BorrowedFd) -> Result<File, ShellError> {
+    Ok(File::from(fd.try_clone_to_owned()?))
+}
+
+/// Opens `path` for reading, or for writing when `append` says how.
+fn open(path: &str, append: Option<bool>) -> Result<File, ShellError> {
+    if path.is_empty() {
+        return Err(ShellError::Parse(
+            "expected a file name after the redirection".to_string(),
+        ));
+    }
+    let opened = match append {
+        None => File::open(path),
+        Some(append) => OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path),
+    };
+    opened.map_err(|e| ShellError::Redirect(PathBuf::from(path), e))
+}
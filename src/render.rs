@@ -0,0 +1,315 @@
+use std::io::{self, Write};
+use std::ops::Range;
+
+use crossterm::{
+    cursor::{self, MoveDown, MoveToColumn, MoveUp},
+    queue, terminal,
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    editor::display_width,
+    prompt::visible_width,
+    theme::{Role, Theme},
+};
+
+pub struct Frame<'a> {
+    /// Prompt text, possibly containing escape sequences. Lines before the
+    /// last are drawn above the input.
+    pub prompt: &'a str,
+    /// Width of the last line of the prompt.
+    pub prompt_width: usize,
+    pub input: &'a str,
+    pub hint: &'a str,
+    /// SGR parameters for byte ranges of `input`.
+    pub highlights: &'a [(Range<usize>, String)],
+    /// Byte offset of the cursor within `input`.
+    pub cursor: usize,
+    /// Lines shown below the input, such as the completion menu.
+    pub menu: &'a [String],
+}
+
+/// What lies under a terminal cell, as reported by `Renderer::hit_test`.
+pub enum Hit {
+    /// Grapheme index within the input.
+    Input(usize),
+    Menu {
+        row: usize,
+        column: usize,
+    },
+}
+
+#[derive(Clone, PartialEq)]
+struct Cell {
+    text: String,
+    /// SGR parameters, empty for none.
+    style: String,
+}
+
+/// Draws the prompt, the input line, and anything below it.
+///
+/// The last frame is remembered so that the next one only rewrites what
+/// changed: typing at the end of a line emits just the new characters, and
+/// moving the cursor emits just a cursor movement. Rows are tracked so
+/// wrapped input and multi-line prompts are redrawn from the right place.
+pub struct Renderer {
+    width: usize,
+    height: usize,
+    /// Row of the terminal cursor relative to the first row of the prompt.
+    row: usize,
+    /// Rows taken by the prompt lines above the input line.
+    header_rows: usize,
+    /// Cells between the start of the prompt and the cursor, kept so the
+    /// layout can be recomputed after a resize.
+    cursor_offset: usize,
+    prompt: String,
+    cells: Vec<Cell>,
+    line_end_row: usize,
+    menu: Vec<String>,
+    /// Set when the screen no longer matches the last frame.
+    dirty: bool,
+    /// Absolute screen row of the first prompt row, when known.
+    origin: Option<usize>,
+    prompt_width: usize,
+    input_len: usize,
+    /// SGR parameters for the hint, empty for none.
+    hint_style: String,
+}
+
+impl Renderer {
+    pub fn new(theme: &Theme) -> Self {
+        let (width, height) = terminal::size().unwrap_or((80, 24));
+        Renderer {
+            width: width.max(1) as usize,
+            height: height.max(1) as usize,
+            row: 0,
+            header_rows: 0,
+            cursor_offset: 0,
+            prompt: String::new(),
+            cells: vec![],
+            line_end_row: 0,
+            menu: vec![],
+            dirty: true,
+            origin: None,
+            prompt_width: 0,
+            input_len: 0,
+            hint_style: theme.style(Role::Hint).to_string(),
+        }
+    }
+
+    /// Uses the styles of `theme` from the next frame on.
+    pub fn set_theme(&mut self, theme: &Theme) {
+        self.hint_style = theme.style(Role::Hint).to_string();
+        self.dirty = true;
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Most terminals reflow the current line when resized, so the cursor's
+    /// row within the prompt is recomputed for the new width.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.width = width.max(1) as usize;
+        self.height = height.max(1) as usize;
+        self.header_rows = self.header_rows(&self.prompt);
+        self.row = self.header_rows + self.cursor_offset / self.width;
+        self.dirty = true;
+    }
+
+    /// Anchors the frame to an absolute screen row so mouse events can be
+    /// mapped back onto it.
+    pub fn set_origin(&mut self, row: u16) {
+        self.origin = Some(row as usize);
+    }
+
+    pub fn hit_test(&self, column: u16, row: u16) -> Option<Hit> {
+        let row = (row as usize)
+            .checked_sub(self.origin?)?
+            .checked_sub(self.header_rows)?;
+        if row <= self.line_end_row {
+            let offset = row * self.width + column as usize;
+            return Some(Hit::Input(
+                offset.saturating_sub(self.prompt_width).min(self.input_len),
+            ));
+        }
+        let menu_row = row - self.line_end_row - 1;
+        (menu_row < self.menu.len()).then_some(Hit::Menu {
+            row: menu_row,
+            column: column as usize,
+        })
+    }
+
+    pub fn render(&mut self, out: &mut impl Write, frame: &Frame) -> io::Result<()> {
+        let cells = frame
+            .input
+            .grapheme_indices(true)
+            .map(|(i, g)| {
+                let style = frame
+                    .highlights
+                    .iter()
+                    .find(|(range, _)| range.contains(&i))
+                    .map_or("", |(_, style)| style);
+                (g, style)
+            })
+            .chain(
+                frame
+                    .hint
+                    .graphemes(true)
+                    .map(|g| (g, self.hint_style.as_str())),
+            )
+            .map(|(text, style)| Cell {
+                text: text.to_string(),
+                style: style.to_string(),
+            })
+            .collect::<Vec<_>>();
+        let line_width = frame.prompt_width + cells.len();
+        let line_end_row = line_width / self.width;
+        let menu = self.visible_menu(frame.menu, line_end_row);
+        let cursor_offset =
+            frame.prompt_width + display_width(&frame.input[..frame.cursor]) as usize;
+
+        let full = self.dirty || frame.prompt != self.prompt;
+        let first = if full {
+            0
+        } else {
+            self.cells
+                .iter()
+                .zip(&cells)
+                .position(|(old, new)| old != new)
+                .unwrap_or(self.cells.len().min(cells.len()))
+        };
+        if !full && first == cells.len() && first == self.cells.len() && menu == self.menu {
+            self.move_to(out, cursor_offset)?;
+            self.cursor_offset = cursor_offset;
+            return out.flush();
+        }
+
+        queue!(out, cursor::Hide)?;
+        if full {
+            if self.row > 0 {
+                queue!(out, MoveUp(self.row as u16))?;
+            }
+            // Raw mode needs the carriage return a newline would imply.
+            write!(out, "\r\x1b[J{}", frame.prompt.replace('\n', "\r\n"))?;
+            self.header_rows = self.header_rows(frame.prompt);
+            self.row = self.header_rows;
+        } else {
+            self.move_to(out, frame.prompt_width + first)?;
+        }
+
+        let mut style = "";
+        for cell in &cells[first..] {
+            if cell.style != style {
+                style = &cell.style;
+                match style {
+                    "" => write!(out, "\x1b[0m")?,
+                    _ => write!(out, "\x1b[0;{}m", style)?,
+                }
+            }
+            write!(out, "{}", cell.text)?;
+        }
+        if !style.is_empty() {
+            write!(out, "\x1b[0m")?;
+        }
+        // A line that exactly fills the last column leaves the cursor in a
+        // pending-wrap state; force the wrap so row math stays simple.
+        if line_width > 0 && line_width.is_multiple_of(self.width) {
+            write!(out, "\r\n")?;
+        }
+        self.row = self.header_rows + line_end_row;
+
+        if !full && menu == self.menu && line_end_row == self.line_end_row {
+            write!(out, "\x1b[K")?;
+        } else {
+            write!(out, "\x1b[J")?;
+            for line in &menu {
+                write!(out, "\r\n{}", line)?;
+                self.row += 1;
+            }
+        }
+
+        self.move_to(out, cursor_offset)?;
+        queue!(out, cursor::Show)?;
+        out.flush()?;
+
+        self.cursor_offset = cursor_offset;
+        self.prompt = frame.prompt.to_string();
+        self.cells = cells;
+        self.line_end_row = line_end_row;
+        self.menu = menu;
+        self.dirty = false;
+        self.prompt_width = frame.prompt_width;
+        self.input_len = frame.input.graphemes(true).count();
+        // Output reaching the bottom of the screen scrolls the frame up.
+        if let Some(origin) = self.origin {
+            let last_row = self.header_rows + self.line_end_row + self.menu.len();
+            self.origin = Some(origin.min(self.height.saturating_sub(last_row + 1)));
+        }
+        Ok(())
+    }
+
+    /// Moves below everything drawn so far and starts a fresh line, leaving
+    /// the last frame in the scrollback.
+    pub fn finish(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let last_row = self.header_rows + self.line_end_row + self.menu.len();
+        if last_row > self.row {
+            queue!(out, MoveDown((last_row - self.row) as u16))?;
+        }
+        write!(out, "\r\n")?;
+        out.flush()?;
+        self.origin = self
+            .origin
+            .map(|origin| (origin + last_row + 1).min(self.height - 1));
+        self.row = 0;
+        self.header_rows = 0;
+        self.cursor_offset = 0;
+        self.line_end_row = 0;
+        self.menu.clear();
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Clips the menu to the rows left below the input, replacing the last
+    /// visible row with a count of what was cut off.
+    fn visible_menu(&self, menu: &[String], line_end_row: usize) -> Vec<String> {
+        let available = self
+            .height
+            .saturating_sub(self.header_rows + line_end_row + 1);
+        if menu.len() <= available {
+            return menu.to_vec();
+        }
+        let mut visible = menu[..available.saturating_sub(1)].to_vec();
+        if available > 0 {
+            visible.push(format!("... {} more rows", menu.len() - visible.len()));
+        }
+        visible
+    }
+
+    /// Rows the prompt lines before the last take, each wrapping on its own.
+    fn header_rows(&self, prompt: &str) -> usize {
+        let mut lines = prompt.split('\n').collect::<Vec<_>>();
+        lines.pop();
+        lines
+            .iter()
+            .map(|line| visible_width(line).div_ceil(self.width).max(1))
+            .sum()
+    }
+
+    /// Moves to `offset` cells from the start of the input line's prompt.
+    fn move_to(&mut self, out: &mut impl Write, offset: usize) -> io::Result<()> {
+        let row = self.header_rows + offset / self.width;
+        if row < self.row {
+            queue!(out, MoveUp((self.row - row) as u16))?;
+        } else if row > self.row {
+            queue!(out, MoveDown((row - self.row) as u16))?;
+        }
+        queue!(out, MoveToColumn((offset % self.width) as u16))?;
+        self.row = row;
+        Ok(())
+    }
+}
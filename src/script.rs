@@ -0,0 +1,439 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, AST};
+
+use crate::{
+    completer::expand_directory,
+    config::Config,
+    hooks::{Hook, HookEvent},
+    output::run_for_output,
+    paths::{self, File},
+};
+
+/// Operations a call from the shell may run before it is cut short, so a
+/// runaway loop cannot hang the prompt.
+const MAX_OPERATIONS: u64 = 100_000;
+/// How deeply script functions may call each other, and expressions and
+/// blocks may nest.
+const MAX_DEPTH: usize = 64;
+
+/// [Rhai](https://rhai.rs) scripts, which hook into the same points as
+/// `[hooks]`, complete arguments and draw prompt segments without
+/// recompiling the shell or starting a process each time. Every `*.rhai`
+/// file in `$XDG_CONFIG_HOME/a-shell/plugins/` is loaded, by name, then
+/// the files `[scripts] files` lists:
+///
+/// ```toml
+/// [scripts]
+/// files = ["~/.config/a-shell/make.rhai"]
+/// ```
+///
+/// A script hands the shell functions, usually closures so they can share
+/// the script's variables:
+///
+/// ```text
+/// // Counts command lines; hooks get what hook commands get as $1, $2...
+/// let count = 0;
+/// hook("preexec", |line| count += 1);
+///
+/// // Completes `make` with the Makefile's targets, given the words before
+/// // the cursor and the word being completed.
+/// complete("make", |words, word| {
+///     let targets = [];
+///     for line in read("Makefile").split("\n") {
+///         if line.contains(":") && !line.starts_with("\t") {
+///             targets.push(line.split(":")[0]);
+///         }
+///     }
+///     targets
+/// });
+///
+/// // Drawn by a `type = "script"` prompt segment with `name = "count"`,
+/// // given the last exit status and the number of jobs.
+/// segment("count", |status, jobs| count.to_string());
+/// ```
+///
+/// A hook function takes as many parameters as the hook has arguments.
+/// Besides Rhai's own functions, scripts can call `env`, `set_env`, `cwd`,
+/// `run` (a shell command's output, `()` if it failed), `read` (a file,
+/// `()` if unreadable) and `exists`.
+///
+/// Errors in hooks are printed; completions and segments that fail offer
+/// nothing.
+#[derive(Clone, Default)]
+pub struct Scripts {
+    /// Shared with completion workers.
+    state: Arc<Mutex<State>>,
+}
+
+impl Scripts {
+    pub fn from_config(config: &Config) -> Self {
+        let mut state = State::default();
+        for path in files(config) {
+            let loaded = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|source| state.load(&source).map_err(|e| e.to_string()));
+            if let Err(e) = loaded {
+                eprintln!("Cannot load script {}: {}", path.display(), e);
+            }
+        }
+        Scripts {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Runs the functions scripts hooked to the point `event` happened at.
+    pub fn run_hooks(&self, event: &HookEvent) {
+        let hook = event.hook();
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let functions = state.registered(|registered| {
+            registered
+                .hooks
+                .iter()
+                .filter(|(h, _)| *h == hook)
+                .map(|(_, function)| function.clone())
+                .collect::<Vec<_>>()
+        });
+        let arguments = event
+            .arguments()
+            .into_iter()
+            .map(Dynamic::from)
+            .collect::<Vec<_>>();
+        for function in functions {
+            if let Err(e) = state.call(&function, arguments.clone()) {
+                eprintln!("Script error in {} hook: {}", hook.name(), e);
+            }
+        }
+    }
+
+    /// Candidates from the functions scripts gave `complete` for the
+    /// command in `words`, the words before the cursor.
+    pub fn complete(&self, words: &[String], word: &str) -> Vec<String> {
+        let Some(command) = words.first() else {
+            return vec![];
+        };
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let functions = state.registered(|registered| {
+            registered
+                .completions
+                .iter()
+                .filter(|(name, _)| name == command)
+                .map(|(_, function)| function.clone())
+                .collect::<Vec<_>>()
+        });
+        let words = words.iter().cloned().map(Dynamic::from).collect::<Vec<_>>();
+        let arguments = vec![Dynamic::from_array(words), Dynamic::from(word.to_string())];
+        let mut candidates = vec![];
+        for function in functions {
+            let Ok(result) = state.call(&function, arguments.clone()) else {
+                continue;
+            };
+            if result.is_array() {
+                let items = result.into_array().unwrap_or_default();
+                candidates.extend(items.iter().map(Dynamic::to_string));
+            } else if result.is_string() {
+                let text = result.to_string();
+                candidates.extend(text.lines().map(str::trim).map(str::to_string));
+            }
+        }
+        candidates.retain(|candidate| !candidate.is_empty());
+        candidates
+    }
+
+    /// The text of the segment a script named `name`, if it has any.
+    pub fn segment(&self, name: &str, exit_code: i32, jobs: usize) -> Option<String> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let function = state.registered(|registered| {
+            registered
+                .segments
+                .iter()
+                .rev()
+                .find(|(segment, _)| segment == name)
+                .map(|(_, function)| function.clone())
+        })?;
+        let arguments = vec![Dynamic::from(exit_code as i64), Dynamic::from(jobs as i64)];
+        let result = state.call(&function, arguments).ok()?;
+        let text = if result.is_unit() {
+            String::new()
+        } else {
+            result.to_string()
+        };
+        (!text.is_empty()).then_some(text)
+    }
+}
+
+/// The plugins, by name, then the files `[scripts] files` lists.
+pub fn files(config: &Config) -> Vec<PathBuf> {
+    let mut plugins = fs::read_dir(paths::path(File::Plugins))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "rhai")
+        })
+        .collect::<Vec<_>>();
+    plugins.sort();
+    plugins.extend(
+        config
+            .get_str_list("scripts", "files")
+            .unwrap_or_default()
+            .into_iter()
+            .map(expand_directory),
+    );
+    plugins
+}
+
+/// Where `source` fails to parse, if it does.
+pub fn check(source: &str) -> Result<(), ScriptError> {
+    State::default()
+        .engine
+        .compile(source)
+        .map(|_| ())
+        .map_err(|e| ScriptError {
+            line: e.1.line().unwrap_or_default(),
+            message: e.0.to_string(),
+        })
+}
+
+/// Something wrong in a script, and the line it is on.
+#[derive(Debug)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl From<Box<EvalAltResult>> for ScriptError {
+    fn from(mut e: Box<EvalAltResult>) -> Self {
+        let line = e.take_position().line().unwrap_or_default();
+        // What a function like `hook` failed with is the whole message.
+        let message = match *e {
+            EvalAltResult::ErrorRuntime(value, _) => value.to_string(),
+            e => e.to_string(),
+        };
+        ScriptError { line, message }
+    }
+}
+
+/// A function a script handed the shell, and the script it is in.
+#[derive(Clone)]
+struct Function {
+    script: usize,
+    pointer: FnPtr,
+}
+
+/// What the scripts handed the shell through `hook`, `complete` and
+/// `segment`.
+#[derive(Default)]
+struct Registered {
+    /// The script being loaded, which the functions handed over are in.
+    loading: usize,
+    hooks: Vec<(Hook, Function)>,
+    completions: Vec<(String, Function)>,
+    segments: Vec<(String, Function)>,
+}
+
+struct State {
+    engine: Engine,
+    scripts: Vec<AST>,
+    /// Shared with the engine's `hook`, `complete` and `segment`.
+    registered: Arc<Mutex<Registered>>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_DEPTH)
+            .set_max_expr_depths(MAX_DEPTH, MAX_DEPTH)
+            .set_strict_variables(true);
+        let registered = Arc::<Mutex<Registered>>::default();
+
+        let shared = registered.clone();
+        engine.register_fn(
+            "hook",
+            move |name: &str, pointer: FnPtr| -> Result<(), Box<EvalAltResult>> {
+                let Some(hook) = Hook::from_name(name) else {
+                    return Err(format!("unknown hook {}", name).into());
+                };
+                let mut registered = shared.lock().unwrap_or_else(|e| e.into_inner());
+                let function = registered.function(pointer);
+                registered.hooks.push((hook, function));
+                Ok(())
+            },
+        );
+        let shared = registered.clone();
+        engine.register_fn("complete", move |command: &str, pointer: FnPtr| {
+            let mut registered = shared.lock().unwrap_or_else(|e| e.into_inner());
+            let function = registered.function(pointer);
+            registered.completions.push((command.to_string(), function));
+        });
+        let shared = registered.clone();
+        engine.register_fn("segment", move |name: &str, pointer: FnPtr| {
+            let mut registered = shared.lock().unwrap_or_else(|e| e.into_inner());
+            let function = registered.function(pointer);
+            registered.segments.push((name.to_string(), function));
+        });
+
+        engine
+            .register_fn("env", |name: &str| {
+                env::var(name).map_or(Dynamic::UNIT, Dynamic::from)
+            })
+            .register_fn("set_env", |name: &str, value: &str| {
+                env::set_var(name, value)
+            })
+            .register_fn("cwd", || {
+                env::current_dir().map_or(Dynamic::UNIT, |cwd| {
+                    Dynamic::from(cwd.display().to_string())
+                })
+            })
+            .register_fn("run", |command: &str| {
+                run_for_output(Command::new("sh").arg("-c").arg(command))
+                    .map_or(Dynamic::UNIT, Dynamic::from)
+            })
+            .register_fn("read", |path: &str| {
+                fs::read_to_string(path).map_or(Dynamic::UNIT, Dynamic::from)
+            })
+            .register_fn("exists", |path: &str| Path::new(path).exists());
+
+        State {
+            engine,
+            scripts: vec![],
+            registered,
+        }
+    }
+}
+
+impl Registered {
+    fn function(&self, pointer: FnPtr) -> Function {
+        Function {
+            script: self.loading,
+            pointer,
+        }
+    }
+}
+
+impl State {
+    /// Compiles and runs `source`, which hands the shell its functions.
+    fn load(&mut self, source: &str) -> Result<(), ScriptError> {
+        let ast = self.engine.compile(source).map_err(|e| ScriptError {
+            line: e.1.line().unwrap_or_default(),
+            message: e.0.to_string(),
+        })?;
+        self.registered(|registered| registered.loading = self.scripts.len());
+        self.scripts.push(ast);
+        self.engine.run_ast(&self.scripts[self.scripts.len() - 1])?;
+        Ok(())
+    }
+
+    fn registered<T>(&self, f: impl FnOnce(&mut Registered) -> T) -> T {
+        f(&mut self.registered.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn call(&self, function: &Function, arguments: Vec<Dynamic>) -> Result<Dynamic, ScriptError> {
+        let script = &self.scripts[function.script];
+        Ok(function.pointer.call(&self.engine, script, arguments)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(source: &str) -> Result<Scripts, ScriptError> {
+        let mut state = State::default();
+        state.load(source)?;
+        Ok(Scripts {
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    fn load_error(source: &str) -> String {
+        load(source).err().expect("the script loads").to_string()
+    }
+
+    #[test]
+    fn completions_hooks_and_segments_are_registered() {
+        let source = r#"
+            let seen = "";
+            hook("preexec", |line| seen = line);
+            complete("make", |words, word| [word + "all", "clean"]);
+            segment("seen", |status, jobs| seen + status);
+        "#;
+        let scripts = load(source).unwrap();
+        scripts.run_hooks(&HookEvent::Preexec { line: "make" });
+        assert_eq!(scripts.segment("seen", 1, 0).as_deref(), Some("make1"));
+        assert_eq!(scripts.segment("other", 0, 0), None);
+        let words = ["make".to_string()];
+        assert_eq!(scripts.complete(&words, "x"), ["xall", "clean"]);
+        assert!(scripts.complete(&["ls".to_string()], "").is_empty());
+    }
+
+    #[test]
+    fn functions_run_against_the_script_that_defined_them() {
+        let mut state = State::default();
+        state
+            .load(r#"fn targets() { ["all"] } complete("make", |words, word| targets());"#)
+            .unwrap();
+        state
+            .load(r#"fn targets() { ["other"] } complete("ninja", |words, word| targets());"#)
+            .unwrap();
+        let scripts = Scripts {
+            state: Arc::new(Mutex::new(state)),
+        };
+        assert_eq!(scripts.complete(&["make".to_string()], ""), ["all"]);
+        assert_eq!(scripts.complete(&["ninja".to_string()], ""), ["other"]);
+    }
+
+    #[test]
+    fn errors_name_their_line() {
+        assert!(load_error("let a = 1;\nlet b = c;").starts_with("line 2: "));
+        assert!(load_error("let a = 1;\n\"open").starts_with("line 2: "));
+        assert!(check("let a = 1;\nlet b = ;").is_err_and(|e| e.line == 2));
+        assert!(check("let a = 1;").is_ok());
+    }
+
+    #[test]
+    fn runaway_loops_are_cut_short() {
+        assert!(load_error("while true {}").starts_with("line 1: "));
+        assert!(load_error("fn f() { f() } f();").starts_with("line 1: "));
+        let source = format!("let a = {}1{};", "(".repeat(1000), ")".repeat(1000));
+        assert!(check(&source).is_err());
+    }
+
+    #[test]
+    fn unknown_hooks_are_rejected() {
+        assert_eq!(
+            load_error(r#"hook("later", |line| ());"#),
+            "line 1: unknown hook later"
+        );
+    }
+
+    #[test]
+    fn scripts_read_files_and_the_environment() {
+        let path = env::temp_dir().join(format!("a-shell-script-{}", std::process::id()));
+        fs::write(&path, "a\nb\n").unwrap();
+        let source = format!(
+            r#"segment("lines", |status, jobs| `${{read("{0}").split("\n").len()}}${{exists("{0}")}}`);
+               segment("missing", |status, jobs| read("{0}.none"));"#,
+            path.display()
+        );
+        let scripts = load(&source).unwrap();
+        assert_eq!(scripts.segment("lines", 0, 0).as_deref(), Some("3true"));
+        assert_eq!(scripts.segment("missing", 0, 0), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,320 +1,1370 @@
 use crossterm::{
-    cursor::{self, MoveLeft, MoveRight, MoveTo},
-    event::{self, Event, KeyCode, KeyModifiers},
+    cursor,
+    event::{
+        DisableFocusChange, DisableMouseCapture, EnableFocusChange, EnableMouseCapture, Event,
+        KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEventKind,
+    },
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode},
+    terminal::{self, disable_raw_mode, enable_raw_mode},
 };
-use std::io::{self, BufRead, BufReader, Stdout, Write};
+use std::ffi::OsString;
+use std::io::{self, stdout, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
-use std::{env, error::Error};
-use std::{fs::File, io::stdout};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, fs};
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
-    about::print_about, autocomplete::AutoComplete, history::History, parser::CommandParser,
+    about::About,
+    alias::Aliases,
+    audit::Audit,
+    autocomplete::{self, AutoComplete, Kind, Suggestion},
+    bookmarks::Bookmarks,
+    clipboard::Clipboard,
+    clock,
+    completer::{expand_directory, Sources},
+    completion_context::CompletionContext,
+    config::Config,
+    config_check,
+    direnv::DirEnv,
+    editor::{self, WordCase},
+    error::ShellError,
+    events::{EventLoop, ShellEvent},
+    frecency::Directories,
+    highlight::Highlighter,
+    history::{ExportFormat, History, HistoryConfig, HistoryFilter, HistorySearch},
+    hooks::{Hook, HookEvent, Hooks},
+    integration::Integration,
+    jobs::{JobState, Jobs},
+    keymap::{Action, Keymap},
+    killring::{KillDirection, KillRing},
+    notify::Notifier,
+    parser::{self, CommandParser, Continuation},
+    paths::{self, File},
+    picker::HistoryPicker,
+    preview,
+    prompt::{self, Prompt, PromptState},
+    record,
+    redirect::{self, Redirections},
+    render::{Frame, Hit, Renderer},
+    script::Scripts,
+    stats,
     suggestion::get_command_suggestion,
+    theme::{Role, Theme},
+    title::Title,
+    watch,
 };
 
+/// Commands handled by the shell itself rather than run from `$PATH`.
+pub const BUILTINS: &[&str] = &[
+    ".", "about", "alias", "allow", "bg", "bind", "cd", "config", "deny", "exit", "export", "fg",
+    "history", "hook", "jobs", "private", "profile", "pwd", "record", "reload", "replay", "source",
+    "unalias",
+];
+
 pub struct Shell {
     input: String,
-    temp_input: String,
+    cursor: usize,
+    /// The earlier lines of a command continued over several lines, joined
+    /// as they will be run, and what the last of them left open.
+    continued: Option<(String, Continuation)>,
+    /// The unfinished line and its cursor, saved while browsing the history
+    /// and restored when stepping back down past the newest entry. It is
+    /// never added to the history itself.
+    draft: String,
+    draft_cursor: usize,
     history: History,
-    stdout: Stdout,
-    autocompleter: AutoComplete,
+    kill_ring: KillRing,
+    clipboard: Clipboard,
+    /// Where the line editor draws.
+    stdout: Box<dyn Write>,
+    /// Whether input comes from the terminal, which is then put in raw
+    /// mode while a line is edited.
+    terminal: bool,
+    renderer: Renderer,
+    events: EventLoop,
+    /// Shared with the worker thread each completion runs on.
+    autocompleter: Arc<Mutex<AutoComplete>>,
+    /// The text before the cursor a completion worker is running for, and
+    /// the flag that cancels it.
+    pending_completion: Option<(String, Arc<AtomicBool>)>,
+    about: About,
+    aliases: Aliases,
+    bookmarks: Bookmarks,
+    directories: Directories,
+    direnv: DirEnv,
+    prompt: Prompt,
+    hooks: Hooks,
+    scripts: Scripts,
+    title: Title,
+    integration: Integration,
+    notifier: Notifier,
+    audit: Audit,
+    theme: Theme,
+    highlighter: Highlighter,
+    keymap: Keymap,
+    /// Exit status of the last command line, for the prompt.
+    last_exit_code: i32,
+    /// How long the last command line ran, for the prompt.
+    last_duration: Option<Duration>,
+    jobs: Jobs,
+    /// The exit code of the job `fg` waited for, which becomes the line's.
+    job_status: Option<i32>,
     parser: CommandParser,
-    prompt_length: u16,
     suggestions: Vec<String>,
-    suggestion_index: u8,
+    completions: Vec<Suggestion>,
+    /// While Tab cycles through `completions`, the text before the cursor
+    /// as it was before the first candidate was inserted, and the current
+    /// candidate.
+    completion_cycle: Option<(String, usize)>,
+    /// Position while walking the history with Up/Down; -1 is the line
+    /// being edited.
+    history_index: isize,
+    search: Option<HistorySearch>,
+    picker: Option<HistoryPicker>,
+    mouse: bool,
+    should_exit: bool,
+    /// Why the line could not be drawn, which ends the session: the
+    /// terminal is gone or unusable.
+    render_error: Option<io::Error>,
+    /// Where the config was read from, to read it again on `reload`.
+    config_path: PathBuf,
+    /// Whether the rc file was sourced, to source it again on `reload`.
+    rc_sourced: bool,
+    /// Set when the config file changed while a line was being edited.
+    reload_pending: bool,
+    /// The profile laid over the config, chosen with `profile use`.
+    profile: Option<String>,
+    /// Each variable set from `[env]`, with its value from before.
+    config_env: Vec<(String, Option<OsString>)>,
+    /// Set with `ShellBuilder`, taking precedence over the config.
+    prompt_template: Option<String>,
+    history_file: Option<PathBuf>,
 }
 
-impl Drop for Shell {
-    fn drop(&mut self) {
-        disable_raw_mode().unwrap();
-    }
+/// Sets up a `Shell`, for the `a-shell` binary or a program embedding it:
+///
+/// ```no_run
+/// let mut shell = a_shell::ShellBuilder::new()
+///     .prompt("\\w $ ")
+///     .history_file("/tmp/history")
+///     .build()?;
+/// shell.init();
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// The input and output handles are the line editor's. Commands and
+/// builtins still use the process's standard streams.
+#[derive(Default)]
+pub struct ShellBuilder {
+    config_path: Option<PathBuf>,
+    prompt: Option<String>,
+    history_file: Option<PathBuf>,
+    input: Option<Box<dyn Iterator<Item = Event> + Send>>,
+    output: Option<Box<dyn Write>>,
 }
 
-impl Shell {
-    pub fn new() -> io::Result<Self> {
-        let history = History::new(format!(
-            "/home/{}/.ash_history",
-            env::var("USER").unwrap_or_else(|_| "Unknown".to_string())
-        ))?;
-        Ok(Shell {
-            autocompleter: AutoComplete::new(),
-            stdout: stdout(),
+impl ShellBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the config from `path` instead of the default file.
+    pub fn config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    /// Uses `template` for the prompt, with the escapes `[prompt] format`
+    /// takes, whatever the config says.
+    pub fn prompt(mut self, template: impl Into<String>) -> Self {
+        self.prompt = Some(template.into());
+        self
+    }
+
+    /// Keeps the history in the file at `path`, whatever the config says.
+    pub fn history_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.history_file = Some(path.into());
+        self
+    }
+
+    /// Takes key presses and other events from `input` instead of the
+    /// terminal. The shell exits when it runs out.
+    pub fn input(mut self, input: impl Iterator<Item = Event> + Send + 'static) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    /// Draws the prompt and the line being edited on `output` instead of
+    /// standard output.
+    pub fn output(mut self, output: impl Write + 'static) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    pub fn build(self) -> io::Result<Shell> {
+        let config_path = self.config_path.unwrap_or_else(Config::default_path);
+        let profile = env::var("A_SHELL_PROFILE")
+            .ok()
+            .filter(|profile| !profile.is_empty());
+        let config = load_config(&config_path, profile.as_deref());
+        let theme = Theme::from_config(&config);
+        let history_path = self
+            .history_file
+            .clone()
+            .unwrap_or_else(|| history_path(&config));
+        let history_config = HistoryConfig::from_config(&config);
+        let sync = history_config.sync;
+        let mut history = History::new(&history_path, history_config)?;
+        if self.history_file.is_none() {
+            import_ash_history(&mut history);
+        }
+        let terminal = self.input.is_none();
+        let events = EventLoop::new(self.input);
+        if let Some(load) = history.older_loader() {
+            let sender = events.sender();
+            thread::spawn(move || sender.send(ShellEvent::HistoryLoaded(load())));
+        }
+        if sync {
+            let sender = events.sender();
+            let watched = watch::watch_file(&history_path, move || {
+                sender.send(ShellEvent::HistoryChanged).is_ok()
+            });
+            if let Err(e) = watched {
+                eprintln!("Cannot watch history file: {}", e);
+            }
+        }
+        if config.get_bool("config", "auto_reload").unwrap_or(false) {
+            let sender = events.sender();
+            let watched = watch::watch_file(&config_path, move || {
+                sender.send(ShellEvent::ConfigChanged).is_ok()
+            });
+            if let Err(e) = watched {
+                eprintln!("Cannot watch config file: {}", e);
+            }
+        }
+        let scripts = Scripts::from_config(&config);
+        let mut prompt = Prompt::from_config(&config, theme.clone(), &scripts);
+        if let Some(template) = &self.prompt {
+            prompt.set_template(template.clone());
+        }
+        let mut shell = Shell {
+            autocompleter: Arc::new(Mutex::new(AutoComplete::from_config(&config, &scripts))),
+            pending_completion: None,
+            about: About::from_config(&config),
+            aliases: Aliases::from_config(&config),
+            bookmarks: Bookmarks::from_config(&config),
+            prompt,
+            hooks: Hooks::from_config(&config),
+            scripts,
+            title: Title::from_config(&config),
+            integration: Integration::from_config(&config),
+            notifier: Notifier::from_config(&config),
+            audit: Audit::from_config(&config),
+            highlighter: Highlighter::new(),
+            keymap: Keymap::from_config(&config),
+            last_exit_code: 0,
+            last_duration: None,
+            jobs: Jobs::default(),
+            job_status: None,
+            directories: Directories::load(paths::path(File::Directories)),
+            direnv: DirEnv::load(paths::path(File::TrustedEnv)),
+            stdout: self.output.unwrap_or_else(|| Box::new(stdout())),
+            terminal,
+            renderer: Renderer::new(&theme),
+            theme,
+            events,
             input: "".to_string(),
-            temp_input: "".to_string(),
+            cursor: 0,
+            continued: None,
+            draft: "".to_string(),
+            draft_cursor: 0,
             history,
-            prompt_length: 0,
+            kill_ring: KillRing::new(),
+            clipboard: Clipboard::from_config(&config),
             suggestions: vec![],
-            suggestion_index: 0,
+            completions: vec![],
+            completion_cycle: None,
+            history_index: -1,
+            search: None,
+            picker: None,
             parser: CommandParser::new(),
-        })
+            mouse: config.get_bool("editor", "mouse").unwrap_or(false),
+            should_exit: false,
+            render_error: None,
+            config_path,
+            rc_sourced: false,
+            reload_pending: false,
+            profile,
+            config_env: vec![],
+            prompt_template: self.prompt,
+            history_file: self.history_file,
+        };
+        shell.set_env(&config);
+        Ok(shell)
     }
+}
 
+impl Drop for Shell {
+    fn drop(&mut self) {
+        if self.mouse {
+            let _ = execute!(self.stdout, DisableMouseCapture);
+        }
+        if self.notifier.enabled() {
+            let _ = execute!(self.stdout, DisableFocusChange);
+        }
+        let _ = self.raw_mode(false);
+    }
+}
+
+impl Shell {
+    /// Reads and runs commands interactively until the shell exits.
     pub fn init(&mut self) {
+        let _ = self.title.save(&mut self.stdout);
+        if self.terminal {
+            if let Err(e) = self.jobs.take_terminal() {
+                eprintln!("Cannot take the terminal for job control: {}", e);
+            }
+        }
         loop {
             self.input.clear();
             if let Err(e) = self.collect_input() {
-                eprintln!("Error collecting input: {}", e);
+                let error = format!("Error collecting input: {}", e);
+                eprintln!("{}", self.theme.paint(Role::Error, &error));
+                if self.should_exit {
+                    break;
+                }
                 continue;
             }
 
-            if self.input.trim() == "exit" {
+            if self.should_exit {
                 break;
             }
 
-            if let Err(e) = self.process_input() {
-                eprintln!("Error processing input: {}", e);
+            let line = self.input.clone();
+            if !line.trim().is_empty() {
+                self.emit(HookEvent::Preexec { line: &line });
             }
+            let started = Instant::now();
+            let exit_code = self.process_input().unwrap_or_else(|e| {
+                self.report(&e);
+                e.exit_code()
+            });
+            self.emit(HookEvent::JobFinished {
+                line: &line,
+                exit_code,
+                duration: started.elapsed(),
+                background: false,
+            });
             self.reset_states();
+            if self.should_exit {
+                break;
+            }
         }
+        self.emit(HookEvent::Exit {
+            exit_code: self.last_exit_code,
+        });
     }
 
-    fn collect_input(&mut self) -> Result<(), Box<dyn Error>> {
-        enable_raw_mode()?;
-        let mut index: i8 = -1;
+    /// Tells the shell's own features, then the user's hook commands, that
+    /// the point `event` stands for was reached.
+    fn emit(&mut self, event: HookEvent) {
+        match &event {
+            HookEvent::Precmd => {
+                self.direnv.update();
+                let _ = self.title.idle(&mut self.stdout);
+                let _ = self.integration.directory(&mut self.stdout);
+            }
+            HookEvent::Preexec { line } => {
+                let _ = self.title.running(&mut self.stdout, line);
+                let _ = self.integration.command_started(&mut self.stdout);
+            }
+            HookEvent::Chpwd { new, .. } => {
+                if let Err(e) = self.directories.visit(new) {
+                    eprintln!("Cannot save visited directories: {}", e);
+                }
+            }
+            HookEvent::JobFinished {
+                line,
+                exit_code,
+                duration,
+                background: true,
+            } => {
+                self.notifier.finished(line, *exit_code, *duration, true);
+            }
+            HookEvent::JobFinished {
+                line,
+                exit_code,
+                duration,
+                background: false,
+            } => {
+                self.notifier.finished(line, *exit_code, *duration, false);
+                self.audit.record(line, *exit_code, *duration);
+                let _ = self
+                    .integration
+                    .command_finished(&mut self.stdout, *exit_code);
+                self.history.record_result(*duration, *exit_code);
+                if let Some(notice) = self.prompt.duration_notice(*duration) {
+                    println!("{}", notice);
+                }
+                self.last_exit_code = *exit_code;
+                self.last_duration = Some(*duration);
+                if let Err(e) = self.history.save() {
+                    eprintln!("Cannot save history: {}", e);
+                }
+            }
+            HookEvent::Exit { .. } => {
+                let _ = self.title.restore(&mut self.stdout);
+            }
+            HookEvent::Logout => {}
+        }
+        self.hooks.run(&event);
+        self.scripts.run_hooks(&event);
+    }
+
+    /// Prints `error` in the theme's error style, with what to do about it
+    /// below in the hint style.
+    fn report(&self, error: &ShellError) {
+        eprintln!("{}", self.theme.paint(Role::Error, &error.to_string()));
+        if let Some(hint) = error.hint() {
+            eprintln!(
+                "{}",
+                self.theme.paint(Role::Hint, &format!("hint: {}", hint))
+            );
+        }
+    }
+
+    /// Puts the terminal in raw mode or takes it out, unless the input
+    /// comes from elsewhere.
+    fn raw_mode(&self, on: bool) -> io::Result<()> {
+        match (self.terminal, on) {
+            (false, _) => Ok(()),
+            (true, true) => enable_raw_mode(),
+            (true, false) => disable_raw_mode(),
+        }
+    }
+
+    /// The exit code of the last command run.
+    pub fn exit_code(&self) -> i32 {
+        self.last_exit_code
+    }
+
+    fn collect_input(&mut self) -> Result<(), ShellError> {
+        if std::mem::take(&mut self.reload_pending) {
+            self.reload();
+        }
+        self.report_jobs();
+        self.emit(HookEvent::Precmd);
+        self.prompt.refresh(&PromptState {
+            exit_code: self.last_exit_code,
+            duration: self.last_duration,
+            jobs: self.jobs.len(),
+        });
+        self.raw_mode(true)?;
+        let (width, _) = terminal::size()?;
+        write!(
+            self.stdout,
+            "{}",
+            self.prompt.line_start(width.max(1) as usize)
+        )?;
+        self.stdout.flush()?;
+        if self.mouse {
+            execute!(self.stdout, EnableMouseCapture)?;
+            // Safe to query here: the event reader is idle between prompts.
+            if let Ok((_, row)) = cursor::position() {
+                self.renderer.set_origin(row);
+            }
+        }
+        if self.notifier.enabled() {
+            execute!(self.stdout, EnableFocusChange)?;
+        }
+        self.history_index = -1;
         self.print_prompt();
 
         loop {
-            if let Ok(true) = event::poll(std::time::Duration::from_millis(500)) {
-                if let Event::Key(key_event) = event::read()? {
-                    if key_event.modifiers.contains(KeyModifiers::CONTROL)
-                        && key_event.code == KeyCode::Char('c')
-                    {
-                        self.reset_states();
-                        index = -1;
-                        print!("\n");
-                        self.print_prompt();
+            if let Some(e) = self.render_error.take() {
+                self.should_exit = true;
+                return Err(e.into());
+            }
+            let event = match self.events.next()? {
+                ShellEvent::Terminal(event) => event,
+                ShellEvent::HistoryChanged => {
+                    self.sync_history();
+                    continue;
+                }
+                ShellEvent::InputClosed => {
+                    self.should_exit = true;
+                    self.renderer.finish(&mut self.stdout)?;
+                    self.raw_mode(false)?;
+                    return Ok(());
+                }
+                ShellEvent::ConfigChanged => {
+                    self.reload_pending = true;
+                    continue;
+                }
+                ShellEvent::HistoryLoaded(older) => {
+                    self.history.add_older(older);
+                    continue;
+                }
+                ShellEvent::Completed {
+                    line,
+                    completed,
+                    completions,
+                } => {
+                    self.finish_completion(line, completed, completions);
+                    continue;
+                }
+            };
+            let key_event = match event {
+                Event::Key(key_event) => key_event,
+                Event::Resize(width, height) => {
+                    self.renderer.resize(width, height);
+                    self.print_prompt();
+                    continue;
+                }
+                Event::Mouse(mouse) if mouse.kind == MouseEventKind::Down(MouseButton::Left) => {
+                    self.handle_click(mouse.column, mouse.row);
+                    continue;
+                }
+                Event::FocusGained => {
+                    self.notifier.set_focused(true);
+                    continue;
+                }
+                Event::FocusLost => {
+                    self.notifier.set_focused(false);
+                    continue;
+                }
+                _ => continue,
+            };
+            self.kill_ring.start_command();
+            if !matches!(key_event.code, KeyCode::Tab | KeyCode::BackTab) {
+                self.completions.clear();
+                self.completion_cycle = None;
+                self.cancel_completion();
+            }
+            if key_event.modifiers.contains(KeyModifiers::CONTROL)
+                && key_event.code == KeyCode::Char('c')
+            {
+                self.print_prompt();
+                self.renderer.finish(&mut self.stdout)?;
+                self.reset_states();
+                self.history_index = -1;
+                self.print_prompt();
+                continue;
+            }
+            if self.search.is_some() {
+                if self.handle_search_key(key_event) {
+                    return self.accept_line();
+                }
+                continue;
+            }
+            if self.picker.is_some() {
+                self.handle_picker_key(key_event);
+                continue;
+            }
+            if key_event.modifiers.contains(KeyModifiers::CONTROL)
+                && key_event.code == KeyCode::Char('d')
+                && self.input.is_empty()
+                && self.continued.is_none()
+            {
+                self.should_exit = true;
+                self.renderer.finish(&mut self.stdout)?;
+                self.raw_mode(false)?;
+                return Ok(());
+            }
+            if let Some(action) = self.keymap.get(&key_event) {
+                if self.run_action(action)? {
+                    return self.accept_line();
+                }
+                continue;
+            }
+            if key_event
+                .modifiers
+                .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+            {
+                continue;
+            }
+            match key_event.code {
+                KeyCode::Char(c) => self.handle_char_input(c)?,
+                KeyCode::Backspace => self.handle_backspace()?,
+                KeyCode::Enter => {
+                    if self.continue_line()? {
                         continue;
                     }
-                    match key_event.code {
-                        KeyCode::Char(c) => self.handle_char_input(c)?,
-                        KeyCode::Backspace => self.handle_backspace()?,
-                        KeyCode::Enter => {
-                            disable_raw_mode()?;
-                            self.handle_enter();
-                            return Ok(());
-                        }
-                        KeyCode::Up => {
-                            if self.suggestions.len() > 0 {
-                                if self.suggestion_index < self.suggestions.len() as u8 {
-                                    self.suggestion_index += 1;
-                                    self.print_prompt();
-                                }
-                                continue;
-                            }
-
-                            if self.history.count() > 0 && index < (self.history.count() - 1) as i8
-                            {
-                                if index == -1 {
-                                    self.temp_input = self.input.clone();
-                                }
-
-                                index += 1;
-                                if self.history.count() >= 10
-                                    && index as usize == self.history.count() - 2
-                                {
-                                    self.history.fetch_more();
-                                }
-                                self.handle_arrow(index as usize)?;
-                            }
-                        }
-                        KeyCode::Down => {
-                            if self.suggestions.len() > 0 && self.suggestion_index > 0 {
-                                self.suggestion_index -= 1;
-                                self.print_prompt();
-                                continue;
-                            }
-                            if index < 0 {
-                                continue;
-                            }
-                            if index > 0 {
-                                index -= 1;
-                                self.handle_arrow(index as usize)?;
-                            } else {
-                                index = -1;
-                                self.input = self.temp_input.clone();
+                    return self.accept_line();
+                }
+                KeyCode::Up => self.history_up()?,
+                KeyCode::Down => self.history_down()?,
+                KeyCode::Tab if !self.completions.is_empty() => self.cycle_completion(1),
+                KeyCode::BackTab if !self.completions.is_empty() => self.cycle_completion(-1),
+                KeyCode::Tab if !self.input.is_empty() => self.autocomplete(),
+                KeyCode::Left => {
+                    if self.cursor == 0 {
+                        continue;
+                    }
+                    self.cursor = editor::prev_grapheme(&self.input, self.cursor);
+                    self.print_prompt();
+                }
+                KeyCode::Right => {
+                    if self.cursor == self.input.len() {
+                        if let Some(suggestion) = self.suggestions.first() {
+                            if suggestion.starts_with(&self.input) {
+                                self.input = suggestion.clone();
+                                self.cursor = self.input.len();
                                 self.print_prompt();
                             }
                         }
-                        KeyCode::Tab => {
-                            if !self.input.is_empty() {
-                                self.autocomplete()?
-                            };
-                        }
-                        KeyCode::Left => {
-                            let (x, _) = cursor::position().unwrap();
-                            if x <= self.prompt_length {
-                                continue;
-                            }
-                            execute!(self.stdout, MoveLeft(1)).unwrap();
-                        }
-                        KeyCode::Right => {
-                            let (x, _) = cursor::position().unwrap();
-                            if x > self.prompt_length - 1 + self.input.len() as u16 {
-                                if !self.suggestions.is_empty() {
-                                    self.input = format!(
-                                        "{}{}",
-                                        self.input,
-                                        self.suggestions
-                                            .get(self.suggestion_index as usize)
-                                            .map_or("", |x| x)
-                                            .replacen(&self.input, "", 1)
-                                    );
-                                    self.print_prompt();
-                                    continue;
-                                } else {
-                                    continue;
-                                }
-                            }
-
-                            execute!(self.stdout, MoveRight(1)).unwrap();
-                        }
-                        _ => {}
+                        continue;
                     }
+
+                    self.cursor = editor::next_grapheme(&self.input, self.cursor);
+                    self.print_prompt();
                 }
+                _ => {}
             }
         }
     }
 
-    fn autocomplete(&mut self) -> Result<(), Box<dyn Error>> {
-        disable_raw_mode()?;
-        match self
-            .autocompleter
-            .autocomplete(self.input.as_str(), &self.parser)
-        {
-            Ok(new_command) => {
-                self.input = new_command;
+    /// Moves on to a continuation line when the input so far is not a
+    /// complete command. Returns whether it did.
+    fn continue_line(&mut self) -> io::Result<bool> {
+        let earlier = self.continued.as_ref().map_or("", |(text, _)| text);
+        let line = format!("{}{}", earlier, self.input);
+        let Some(continuation) = parser::continuation(&line) else {
+            return Ok(false);
+        };
+        self.suggestions.clear();
+        self.print_prompt();
+        self.renderer.finish(&mut self.stdout)?;
+        self.continued = Some((continuation.join(&line), continuation));
+        self.input.clear();
+        self.cursor = 0;
+        self.history_index = -1;
+        self.print_prompt();
+        Ok(true)
+    }
+
+    fn accept_line(&mut self) -> Result<(), ShellError> {
+        if self.mouse {
+            execute!(self.stdout, DisableMouseCapture)?;
+        }
+        if self.notifier.enabled() {
+            execute!(self.stdout, DisableFocusChange)?;
+        }
+        self.raw_mode(false)?;
+        self.handle_enter();
+        Ok(())
+    }
+
+    /// Steps to the next older entry starting with what was typed before
+    /// history navigation began, skipping entries equal to the current line.
+    fn history_up(&mut self) -> Result<(), ShellError> {
+        if self.history_index == -1 {
+            self.draft = self.input.clone();
+            self.draft_cursor = self.cursor;
+        }
+        let found = self
+            .history
+            .entries
+            .iter()
+            .enumerate()
+            .skip((self.history_index + 1) as usize)
+            .find(|(_, entry)| {
+                entry.command.starts_with(&self.draft) && entry.command != self.input
+            })
+            .map(|(i, _)| i);
+        if let Some(index) = found {
+            self.history_index = index as isize;
+            self.handle_arrow(index)?;
+        }
+        Ok(())
+    }
+
+    fn history_down(&mut self) -> Result<(), ShellError> {
+        if self.history_index < 0 {
+            return Ok(());
+        }
+        let found = self.history.entries[..self.history_index as usize]
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, entry)| {
+                entry.command.starts_with(&self.draft) && entry.command != self.input
+            })
+            .map(|(i, _)| i);
+        match found {
+            Some(index) => {
+                self.history_index = index as isize;
+                self.handle_arrow(index)?;
+            }
+            None => {
+                self.history_index = -1;
+                self.input = self.draft.clone();
+                self.cursor = self.draft_cursor;
+                if !self.input.is_empty() {
+                    self.suggestions = get_command_suggestion(&self.history.entries, &self.input);
+                }
                 self.print_prompt();
             }
-            Err(_) => todo!(),
         }
-        enable_raw_mode()?;
         Ok(())
     }
 
+    /// Handles a key while reverse-searching. Returns true when the found
+    /// line should be run.
+    fn handle_search_key(&mut self, key_event: KeyEvent) -> bool {
+        let Some(search) = self.search.as_mut() else {
+            return false;
+        };
+        let entries = &self.history.entries;
+        let control = key_event.modifiers.contains(KeyModifiers::CONTROL);
+        match key_event.code {
+            KeyCode::Char('r') if control => search.next(entries),
+            KeyCode::Char(c) if !control && !key_event.modifiers.contains(KeyModifiers::ALT) => {
+                search.push(c, entries)
+            }
+            KeyCode::Backspace => search.pop(entries),
+            // Abort, leaving the line as it was before searching.
+            KeyCode::Char('g') if control => self.search = None,
+            KeyCode::Enter => {
+                self.end_search();
+                return true;
+            }
+            // Anything else leaves the match on the line for editing.
+            _ => self.end_search(),
+        }
+        self.print_prompt();
+        false
+    }
+
+    fn sync_history(&mut self) {
+        let Ok(added) = self.history.sync() else {
+            return;
+        };
+        if self.history_index >= 0 {
+            self.history_index += added as isize;
+        }
+        if let Some(search) = self.search.as_mut() {
+            search.shift(added);
+        }
+    }
+
+    /// Leaves Up/Down history navigation, restoring the line that was being
+    /// typed before it started.
+    fn abort_history_recall(&mut self) {
+        if self.history_index < 0 {
+            return;
+        }
+        self.history_index = -1;
+        self.input = self.draft.clone();
+        self.cursor = self.draft_cursor;
+        self.print_prompt();
+    }
+
+    fn start_search(&mut self) {
+        self.search = Some(HistorySearch::new());
+        self.print_prompt();
+    }
+
+    fn end_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            if let Some(matched) = search.matched(&self.history.entries) {
+                self.input = matched.to_string();
+                self.history_index = -1;
+            }
+            self.cursor = self.input.len();
+        }
+    }
+
+    /// Handles a key in the fuzzy history picker. Enter puts the selected
+    /// entry on the line for editing; Esc or Ctrl-G restores the old line.
+    fn handle_picker_key(&mut self, key_event: KeyEvent) {
+        let Some(picker) = self.picker.as_mut() else {
+            return;
+        };
+        let entries = &self.history.entries;
+        let control = key_event.modifiers.contains(KeyModifiers::CONTROL);
+        match key_event.code {
+            KeyCode::Up => picker.select_previous(),
+            KeyCode::Char('p') if control => picker.select_previous(),
+            KeyCode::Down => picker.select_next(),
+            KeyCode::Char('n') if control => picker.select_next(),
+            KeyCode::Char('g') if control => self.close_picker(false),
+            KeyCode::Esc => self.close_picker(false),
+            KeyCode::Enter => self.close_picker(true),
+            KeyCode::Char(c) if !control && !key_event.modifiers.contains(KeyModifiers::ALT) => {
+                picker.query.push(c);
+                picker.update(entries);
+            }
+            KeyCode::Backspace => {
+                picker.query.pop();
+                picker.update(entries);
+            }
+            _ => {}
+        }
+        self.print_prompt();
+    }
+
+    fn open_picker(&mut self) {
+        self.picker = Some(HistoryPicker::new(&self.input, &self.history.entries));
+        self.print_prompt();
+    }
+
+    fn close_picker(&mut self, accept: bool) {
+        if let Some(picker) = self.picker.take() {
+            self.input = match picker.selected() {
+                Some(selected) if accept => selected.to_string(),
+                _ => picker.original,
+            };
+            self.cursor = self.input.len();
+            self.history_index = -1;
+            self.suggestions.clear();
+        }
+    }
+
+    /// Starts completing the word before the cursor on a worker thread, so
+    /// slow directories or commands do not freeze the line editor. The
+    /// result arrives as `ShellEvent::Completed`.
+    fn autocomplete(&mut self) {
+        self.cancel_completion();
+        let line = self.input[..self.cursor].to_string();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let sources = Sources {
+            aliases: self.aliases.clone(),
+            bookmarks: self.bookmarks.clone(),
+            directories: self.directories.ranked(),
+            history: self.history.entries.clone(),
+            jobs: self
+                .jobs
+                .iter()
+                .map(|(_, job)| (job.id, job.line.clone()))
+                .collect(),
+            cancelled: cancelled.clone(),
+        };
+        let autocompleter = self.autocompleter.clone();
+        let sender = self.events.sender();
+        let worker_line = line.clone();
+        thread::spawn(move || {
+            // A completer that panicked left nothing half-changed that
+            // matters, so later completions go on.
+            let mut autocompleter = autocompleter.lock().unwrap_or_else(|e| e.into_inner());
+            let (completed, completions) = autocompleter.autocomplete(&worker_line, &sources);
+            if !sources.cancelled() {
+                let _ = sender.send(ShellEvent::Completed {
+                    line: worker_line,
+                    completed,
+                    completions,
+                });
+            }
+        });
+        self.pending_completion = Some((line, cancelled));
+    }
+
+    /// Applies a worker's result unless the line changed meanwhile.
+    fn finish_completion(&mut self, line: String, completed: String, completions: Vec<Suggestion>) {
+        let current = self
+            .pending_completion
+            .as_ref()
+            .is_some_and(|(pending, _)| *pending == line);
+        if !current || self.input[..self.cursor] != line {
+            return;
+        }
+        self.pending_completion = None;
+        self.replace_before_cursor(&completed);
+        self.completions = completions;
+        self.print_prompt();
+    }
+
+    fn cancel_completion(&mut self) {
+        if let Some((_, cancelled)) = self.pending_completion.take() {
+            cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Puts a listed candidate at the cursor, completing `base`, the text
+    /// that was before the cursor when the candidates were listed.
+    fn insert_completion(&mut self, base: &str, index: usize) {
+        let completed = autocomplete::apply(base, &self.completions[index]);
+        self.replace_before_cursor(&completed);
+    }
+
+    /// Puts `completed` in place of the text before the cursor. A directory
+    /// completed in front of a `/` shares it rather than doubling it.
+    fn replace_before_cursor(&mut self, completed: &str) {
+        let mut after = &self.input[self.cursor..];
+        if completed.ends_with('/') {
+            after = after.strip_prefix('/').unwrap_or(after);
+        }
+        self.input = format!("{}{}", completed, after);
+        self.cursor = completed.len();
+    }
+
+    /// Puts the next (or with a negative `step`, previous) listed candidate
+    /// on the line in place of the one inserted before.
+    fn cycle_completion(&mut self, step: isize) {
+        let len = self.completions.len();
+        let (base, index) = match self.completion_cycle.take() {
+            Some((base, index)) => (base, (index as isize + step).rem_euclid(len as isize)),
+            None if step < 0 => (self.input[..self.cursor].to_string(), len as isize - 1),
+            None => (self.input[..self.cursor].to_string(), 0),
+        };
+        let index = index as usize;
+        self.insert_completion(&base, index);
+        self.completion_cycle = Some((base, index));
+        self.print_prompt();
+    }
+
     fn print_prompt(&mut self) {
-        let cwd = env::current_dir()
-            .unwrap_or_default()
-            .into_os_string()
-            .into_string()
-            .unwrap_or("".to_string());
-        let wdir = cwd.split("/").last().unwrap_or_default();
-        let prompt = format!("{}{}  ", "  ", wdir);
-        self.prompt_length = prompt.graphemes(true).count() as u16;
-        execute!(self.stdout, cursor::Hide).unwrap();
-        print!("\r\x1b[2K\x1b[34m{}\x1b[0m{}", prompt, self.input);
-        if self.input.len() > 0 {
-            print!(
-                "\x1b[2m{}\x1b[0m",
-                self.suggestions
-                    .get(self.suggestion_index as usize)
-                    .map_or("", |x| x)
-                    .replacen(&self.input, "", 1)
+        if let Some(search) = &self.search {
+            let label = format!(
+                "({}reverse-i-search)'{}': ",
+                if search.failed { "failed " } else { "" },
+                search.query
             );
+            let matched = search.matched(&self.history.entries).unwrap_or("");
+            let frame = Frame {
+                prompt: &label,
+                prompt_width: editor::display_width(&label) as usize,
+                input: matched,
+                hint: "",
+                highlights: &[],
+                cursor: matched.find(&search.query).unwrap_or(0),
+                menu: &[],
+            };
+            if let Err(e) = self.renderer.render(&mut self.stdout, &frame) {
+                self.render_error = Some(e);
+            }
+            return;
+        }
+        if let Some(picker) = &mut self.picker {
+            let menu = picker.lines(self.renderer.height(), self.renderer.width(), &self.theme);
+            let status = picker.status();
+            let pointer = format!("{} ", self.theme.paint(Role::Pointer, ">"));
+            let frame = Frame {
+                prompt: &pointer,
+                prompt_width: 2,
+                input: &picker.query,
+                hint: &status,
+                highlights: &[],
+                cursor: picker.query.len(),
+                menu: &menu,
+            };
+            if let Err(e) = self.renderer.render(&mut self.stdout, &frame) {
+                self.render_error = Some(e);
+            }
+            return;
+        }
+        let hint = if self.input.is_empty() {
+            ""
+        } else {
+            self.suggestions
+                .first()
+                .and_then(|x| x.strip_prefix(self.input.as_str()))
+                .unwrap_or("")
+        };
+        // Earlier lines of a continued command decide what the quotes and
+        // words of this one are.
+        let earlier = self
+            .continued
+            .as_ref()
+            .map_or("", |(text, _)| text.as_str());
+        let highlights = self
+            .highlighter
+            .highlight(
+                &format!("{}{}", earlier, self.input),
+                earlier.len() + self.cursor,
+                &self.aliases,
+                &self.theme,
+            )
+            .into_iter()
+            .filter(|(range, _)| range.end > earlier.len())
+            .map(|(range, style)| {
+                let start = range.start.saturating_sub(earlier.len());
+                (start..range.end - earlier.len(), style)
+            })
+            .collect::<Vec<_>>();
+        let selected = self.completion_cycle.as_ref().map(|(_, index)| *index);
+        let mut menu = autocomplete::format_grid(
+            &self.completions,
+            self.renderer.width(),
+            selected,
+            &self.theme,
+        );
+        if let Some((base, index)) = &self.completion_cycle {
+            menu.extend(self.completion_preview(base, *index));
+        }
+        let prompt = if let Some((_, continuation)) = self.continued {
+            self.prompt.continuation(continuation)
+        } else if self.history.private {
+            format!(
+                "{}{}",
+                self.theme.paint(Role::Private, "[private] "),
+                self.prompt.text()
+            )
+        } else {
+            self.prompt.text().to_string()
+        };
+        let prompt = self.integration.mark_prompt(&prompt);
+        let frame = Frame {
+            prompt: &prompt,
+            prompt_width: prompt::visible_width(prompt.rsplit('\n').next().unwrap_or_default()),
+            input: &self.input,
+            hint,
+            highlights: &highlights,
+            cursor: self.cursor,
+            menu: &menu,
+        };
+        if let Err(e) = self.renderer.render(&mut self.stdout, &frame) {
+            self.render_error = Some(e);
         }
-        let (_, y) = cursor::position().unwrap();
-        execute!(
-            self.stdout,
-            MoveTo(self.prompt_length + self.input.len() as u16, y)
-        )
-        .unwrap();
-        execute!(self.stdout, cursor::Show).unwrap();
-        io::stdout().flush().unwrap();
     }
 
-    fn handle_char_input(&mut self, c: char) -> Result<(), Box<dyn Error>> {
-        let (x, y) = cursor::position().unwrap();
-        self.input.insert((x - self.prompt_length) as usize, c);
-        if self.input.len() > 0 {
-            self.suggestions = get_command_suggestion(&self.history.commands, &self.input)
+    /// Preview lines for the file candidate the menu selection rests on.
+    fn completion_preview(&self, base: &str, index: usize) -> Vec<String> {
+        let suggestion = &self.completions[index];
+        if !matches!(suggestion.kind, Kind::File | Kind::Directory) {
+            return vec![];
+        }
+        let context = CompletionContext::new(base);
+        let path = expand_directory(context.directory()).join(&suggestion.file_name);
+        preview::preview(&path, self.renderer.width(), &self.theme)
+    }
+
+    fn handle_char_input(&mut self, c: char) -> Result<(), ShellError> {
+        self.input.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+        if !self.input.is_empty() {
+            self.suggestions = get_command_suggestion(&self.history.entries, &self.input)
         }
         self.print_prompt();
-        execute!(self.stdout, MoveTo(x + 1, y)).unwrap();
         Ok(())
     }
 
-    fn handle_backspace(&mut self) -> Result<(), Box<dyn Error>> {
-        if self.input.len() == 0 {
-            return Ok(());
+    /// Runs the editing command bound to a key. Returns whether it
+    /// accepted the line.
+    fn run_action(&mut self, action: Action) -> io::Result<bool> {
+        let case = match action {
+            Action::AcceptLine => return Ok(!self.continue_line()?),
+            Action::BeginningOfLine => {
+                self.cursor = 0;
+                self.print_prompt();
+                return Ok(false);
+            }
+            Action::EndOfLine => {
+                self.cursor = self.input.len();
+                self.print_prompt();
+                return Ok(false);
+            }
+            Action::KillLine => {
+                self.kill_text(self.cursor, self.input.len(), KillDirection::Forward);
+                return Ok(false);
+            }
+            Action::UnixLineDiscard => {
+                self.kill_text(0, self.cursor, KillDirection::Backward);
+                return Ok(false);
+            }
+            Action::UnixWordRubout => {
+                let start = editor::prev_unix_word_start(&self.input, self.cursor);
+                self.kill_text(start, self.cursor, KillDirection::Backward);
+                return Ok(false);
+            }
+            Action::BackwardKillWord => {
+                let start = editor::prev_word_start(&self.input, self.cursor);
+                self.kill_text(start, self.cursor, KillDirection::Backward);
+                return Ok(false);
+            }
+            Action::KillWord => {
+                if let Some((_, end)) = editor::next_word(&self.input, self.cursor) {
+                    self.kill_text(self.cursor, end, KillDirection::Forward);
+                }
+                return Ok(false);
+            }
+            Action::Yank => {
+                self.yank();
+                return Ok(false);
+            }
+            Action::YankPop => {
+                self.yank_pop();
+                return Ok(false);
+            }
+            Action::ReverseSearchHistory => {
+                self.start_search();
+                return Ok(false);
+            }
+            Action::HistoryPicker => {
+                self.open_picker();
+                return Ok(false);
+            }
+            Action::Abort => {
+                self.abort_history_recall();
+                return Ok(false);
+            }
+            Action::UpcaseWord => WordCase::Upper,
+            Action::DowncaseWord => WordCase::Lower,
+            Action::CapitalizeWord => WordCase::Capitalize,
+        };
+        self.cursor = editor::change_word_case(&mut self.input, self.cursor, case);
+        self.print_prompt();
+        Ok(false)
+    }
+
+    fn kill_text(&mut self, start: usize, end: usize, direction: KillDirection) {
+        let killed = self.input.drain(start..end).collect::<String>();
+        self.cursor = start;
+        self.kill_ring.kill(&killed, direction);
+        if let Some(text) = self.kill_ring.latest() {
+            self.clipboard.copy(text);
         }
-        let (x, y) = cursor::position().unwrap();
-        let pos = (x - self.prompt_length) as usize;
-        if pos > 0 {
-            self.input.remove(pos - 1);
-            if self.input.len() > 0 {
-                self.suggestions = get_command_suggestion(&self.history.commands, &self.input)
+        self.print_prompt();
+    }
+
+    fn yank(&mut self) {
+        if let Some(text) = self.clipboard.paste() {
+            if !text.is_empty() && self.kill_ring.latest() != Some(&text) {
+                self.kill_ring.push(text);
             }
+        }
+        if let Some(text) = self.kill_ring.yank(self.cursor) {
+            self.input.insert_str(self.cursor, text);
+            self.cursor += text.len();
+            self.print_prompt();
+        }
+    }
+
+    fn yank_pop(&mut self) {
+        if let Some(((start, end), text)) = self.kill_ring.yank_pop() {
+            self.input.replace_range(start..end, text);
+            self.cursor = start + text.len();
             self.print_prompt();
-            execute!(self.stdout, MoveTo(if x > 0 { x - 1 } else { x }, y)).unwrap();
         }
+    }
+
+    fn handle_backspace(&mut self) -> Result<(), ShellError> {
+        if self.cursor == 0 {
+            return Ok(());
+        }
+        let prev = editor::prev_grapheme(&self.input, self.cursor);
+        self.input.replace_range(prev..self.cursor, "");
+        self.cursor = prev;
+        if !self.input.is_empty() {
+            self.suggestions = get_command_suggestion(&self.history.entries, &self.input)
+        }
+        self.print_prompt();
         Ok(())
     }
 
+    fn handle_click(&mut self, column: u16, row: u16) {
+        match self.renderer.hit_test(column, row) {
+            Some(Hit::Input(index)) => {
+                self.cursor = self
+                    .input
+                    .grapheme_indices(true)
+                    .nth(index)
+                    .map_or(self.input.len(), |(i, _)| i);
+            }
+            Some(Hit::Menu { row, column }) => {
+                let width = self.renderer.width();
+                let Some(index) =
+                    autocomplete::grid_entry_at(&self.completions, width, row, column)
+                else {
+                    return;
+                };
+                // Replace the candidate Tab put on the line, if any.
+                let base = match self.completion_cycle.take() {
+                    Some((base, _)) => base,
+                    None => self.input[..self.cursor].to_string(),
+                };
+                self.insert_completion(&base, index);
+                self.completions.clear();
+            }
+            None => return,
+        }
+        self.print_prompt();
+    }
+
     fn handle_enter(&mut self) {
-        println!();
-        if !self.input.trim().is_empty() {
+        // Leave the accepted line in the scrollback without the hint.
+        self.suggestions.clear();
+        self.print_prompt();
+        let _ = self.renderer.finish(&mut self.stdout);
+        if let Some((earlier, _)) = self.continued.take() {
+            self.input = format!("{}{}", earlier, self.input);
+        }
+        match self
+            .parser
+            .expand_history(&self.input, &self.history.entries)
+        {
+            Ok(Some(expanded)) => {
+                println!("{}", expanded);
+                self.input = expanded;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                self.report(&ShellError::Parse(e));
+                self.input.clear();
+            }
+        }
+        // History entries are single lines.
+        if !self.input.trim().is_empty() && !self.input.contains('\n') {
             self.history.add_command(&self.input);
         }
     }
 
-    fn handle_arrow(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
+    fn handle_arrow(&mut self, index: usize) -> Result<(), ShellError> {
         if index < self.history.count() {
             self.input = self
                 .history
                 .get_command(index)
                 .map_or("", |f| f)
                 .to_string();
+            self.cursor = self.input.len();
+            self.suggestions.clear();
             self.print_prompt();
         }
         Ok(())
     }
 
-    fn process_input(&mut self) -> Result<(), Box<dyn Error>> {
-        let input = self.input.clone();
-        let mut commands = input.split(" | ").peekable();
+    /// Runs the accepted line and returns the exit code of the last command.
+    /// Each part of the line before an `&` is started as a background job
+    /// and the next part runs right after it.
+    fn process_input(&mut self) -> Result<i32, ShellError> {
+        let jobs = self
+            .parser
+            .split_jobs(&self.input)
+            .map_err(ShellError::Parse)?
+            .into_iter()
+            .map(|(line, background)| (line.to_string(), background))
+            .collect::<Vec<_>>();
+        let mut exit_code = 0;
+        let mut jobs = jobs.into_iter().peekable();
+        while let Some((line, background)) = jobs.next() {
+            exit_code = match self.run_list(line, background) {
+                Err(e) if jobs.peek().is_some() => {
+                    self.report(&e);
+                    e.exit_code()
+                }
+                result => result?,
+            };
+        }
+        Ok(exit_code)
+    }
+
+    /// Runs one `&`-separated part of the line. In the background its last
+    /// pipeline is left running as a job; commands before an `&&` still run
+    /// first.
+    fn run_list(&mut self, input: String, background: bool) -> Result<i32, ShellError> {
+        let mut exit_code = 0;
+        let mut commands = self.parser.split_pipeline(&input).into_iter().peekable();
         let mut previous_command: Option<Child> = None;
+        // The processes started since the last one waited for, which form
+        // the next job.
+        let mut pipeline: Vec<u32> = vec![];
 
-        while let Some(command_group) = commands.next() {
-            let mut split_commands = command_group.split(" && ").peekable();
+        'line: while let Some(command_group) = commands.next() {
+            let mut split_commands = command_group.into_iter().peekable();
 
             while let Some(command) = split_commands.next() {
-                // Execute the current command
-                let mut current_command = self.execute_command(
+                let last = split_commands.peek().is_none();
+                let current_command = match self.execute_command(
                     command.trim(),
                     previous_command.take(),
-                    commands.peek().is_some(),
-                )?;
-
-                // If there are more commands after &&, check the success of the previous one
-                if split_commands.peek().is_some() {
-                    if let Some(ref mut child) = current_command {
-                        let status = child.wait()?;
-                        if !status.success() {
-                            // If the current command fails, stop processing this group
-                            break;
+                    last && commands.peek().is_some(),
+                    pipeline.first().copied(),
+                    !(background && last),
+                ) {
+                    Ok(child) => child,
+                    Err(e) => {
+                        // What already started still has to end before the
+                        // shell gets the terminal back
+                        if !pipeline.is_empty() {
+                            let id = self.jobs.add(&input, &pipeline);
+                            if !background {
+                                self.wait_for_job(id, false)?;
+                            }
                         }
+                        return Err(e);
                     }
+                };
+                if let Some(code) = self.job_status.take() {
+                    exit_code = code;
+                }
+                if let Some(child) = &current_command {
+                    pipeline.push(child.id());
                 }
 
-                // Update previous_command for the next iteration
-                previous_command = current_command;
+                // Only the last command of an && chain feeds the next pipe;
+                // the others must succeed for the chain to go on
+                if last {
+                    previous_command = current_command;
+                } else if !pipeline.is_empty() {
+                    let id = self.jobs.add(command, &std::mem::take(&mut pipeline));
+                    exit_code = self.wait_for_job(id, false)?;
+                    if exit_code != 0 {
+                        break 'line;
+                    }
+                }
             }
         }
 
-        // Wait for the last command in the pipeline to finish
-        if let Some(mut final_command) = previous_command {
-            final_command.wait()?;
+        if pipeline.is_empty() {
+            return Ok(exit_code);
+        }
+        let id = self.jobs.add(&input, &pipeline);
+        if background {
+            println!("[{}] {}", id, pipeline.last().copied().unwrap_or_default());
+            return Ok(0);
         }
+        self.wait_for_job(id, false)
+    }
 
-        Ok(())
+    /// Waits for job `id` in the foreground, continuing it first if
+    /// `resume`, and returns its exit code, or 128 plus SIGTSTP if it was
+    /// stopped.
+    fn wait_for_job(&mut self, id: usize, resume: bool) -> Result<i32, ShellError> {
+        match self.jobs.foreground(id, resume)? {
+            JobState::Done(code) => {
+                // Ctrl-C leaves the cursor after the `^C` it echoed.
+                if code == 128 + libc::SIGINT && self.terminal {
+                    println!();
+                }
+                Ok(code)
+            }
+            _ => {
+                if let Some(job) = self.jobs.get(id) {
+                    println!();
+                    println!("{}", job.describe('+'));
+                }
+                Ok(128 + libc::SIGTSTP)
+            }
+        }
+    }
+
+    /// Reports the background jobs that ended since the last prompt.
+    fn report_jobs(&mut self) {
+        for job in self.jobs.reap() {
+            println!("{}", job.describe(' '));
+            let JobState::Done(exit_code) = job.state() else {
+                continue;
+            };
+            self.emit(HookEvent::JobFinished {
+                line: &job.line,
+                exit_code,
+                duration: job.elapsed(),
+                background: true,
+            });
+        }
     }
 
     fn reset_states(&mut self) {
-        self.suggestion_index = 0;
+        self.continued = None;
+        self.search = None;
+        self.picker = None;
         self.input.clear();
+        self.cursor = 0;
         self.suggestions.clear();
     }
 
@@ -323,40 +1373,224 @@ impl Shell {
         command_line: &str,
         previous_command: Option<Child>,
         has_more_commands: bool,
-    ) -> Result<Option<Child>, Box<dyn Error>> {
+        group: Option<u32>,
+        foreground: bool,
+    ) -> Result<Option<Child>, ShellError> {
         if command_line.is_empty() {
             return Ok(None);
         }
+        let command_line = self.aliases.expand(command_line);
         let parsed_command = self.parser.parse(&command_line);
         let command = parsed_command.command.as_str();
+        let redirections = Redirections::open(&parsed_command.redirects)?;
+        let _redirected = match BUILTINS.contains(&command) {
+            true => Some(redirections.apply()?),
+            false => None,
+        };
 
         match command {
             "cd" => {
-                self.change_directory(&parsed_command.paths)?;
+                let old = env::current_dir()?;
+                self.change_directory(&parsed_command.paths, parsed_command.args.last())?;
+                let new = env::current_dir()?;
+                if new != old {
+                    self.emit(HookEvent::Chpwd {
+                        old: &old,
+                        new: &new,
+                    });
+                }
                 Ok(None)
             }
             "exit" | "exit;" => {
-                std::process::exit(0);
+                self.should_exit = true;
+                Ok(None)
             }
             "about" => {
-                print_about();
+                let json = match parsed_command.args.as_slice() {
+                    [] => false,
+                    [flag] if flag == "--json" => true,
+                    _ => return Err(ShellError::builtin("about", "usage: about [--json]")),
+                };
+                self.about.print(json);
                 Ok(None)
             }
             "pwd" => {
                 self.print_pwd();
                 Ok(None)
             }
+            "history" => {
+                self.history_builtin(&parsed_command.args)?;
+                Ok(None)
+            }
+            "record" => {
+                self.record_builtin(&parsed_command.args)?;
+                Ok(None)
+            }
+            "replay" => {
+                let mut speed = 1.0;
+                let mut file = None;
+                let mut rest = parsed_command.args.iter();
+                while let Some(arg) = rest.next() {
+                    if arg == "--speed" {
+                        speed = rest
+                            .next()
+                            .and_then(|s| s.parse::<f64>().ok())
+                            .filter(|s| *s > 0.0)
+                            .ok_or_else(|| {
+                                ShellError::builtin("replay", "--speed needs a positive number")
+                            })?;
+                    } else {
+                        file = Some(arg);
+                    }
+                }
+                record::replay(
+                    Path::new(
+                        file.ok_or_else(|| ShellError::builtin("replay", "file name required"))?,
+                    ),
+                    speed,
+                )?;
+                Ok(None)
+            }
+            "alias" => {
+                self.alias_builtin(&parsed_command.args)?;
+                Ok(None)
+            }
+            "export" => {
+                export_builtin(&parsed_command.args)?;
+                Ok(None)
+            }
+            "source" | "." => {
+                let file = parsed_command.args.first().ok_or_else(|| {
+                    let name = if command == "." { "." } else { "source" };
+                    ShellError::builtin(name, "file name required")
+                })?;
+                self.source(&expand_directory(file))?;
+                Ok(None)
+            }
+            "allow" => {
+                let file = self.direnv.allow()?;
+                println!("Allowed {}", file.display());
+                Ok(None)
+            }
+            "deny" => {
+                let file = self.direnv.deny()?;
+                println!("Denied {}", file.display());
+                Ok(None)
+            }
+            "config" => {
+                if parsed_command.args.first().map(String::as_str) != Some("check") {
+                    return Err(ShellError::builtin("config", "usage: config check"));
+                }
+                let problems = config_check::check(&self.config_path, &paths::path(File::Rc));
+                for problem in &problems {
+                    println!("{}", problem);
+                }
+                if problems.is_empty() {
+                    println!("No problems found");
+                }
+                Ok(None)
+            }
+            "profile" => {
+                self.profile_builtin(&parsed_command.args)?;
+                Ok(None)
+            }
+            "reload" => {
+                self.reload();
+                println!("Reloaded {}", self.config_path.display());
+                Ok(None)
+            }
+            "bind" => {
+                self.bind_builtin(&parsed_command.args)?;
+                Ok(None)
+            }
+            "hook" => {
+                self.hook_builtin(&parsed_command.args)?;
+                Ok(None)
+            }
+            "jobs" => {
+                if !parsed_command.args.is_empty() {
+                    return Err(ShellError::builtin("jobs", "usage: jobs"));
+                }
+                for (marker, job) in self.jobs.iter() {
+                    println!("{}", job.describe(marker));
+                }
+                Ok(None)
+            }
+            "fg" => {
+                let id = self.job_from_args("fg", &parsed_command.args)?;
+                if let Some(job) = self.jobs.get(id) {
+                    println!("{}", job.line);
+                }
+                self.job_status = Some(self.wait_for_job(id, true)?);
+                Ok(None)
+            }
+            "bg" => {
+                let id = self.job_from_args("bg", &parsed_command.args)?;
+                let job = self.jobs.background(id)?;
+                println!("[{}] {} &", job.id, job.line);
+                Ok(None)
+            }
+            "unalias" => {
+                for name in &parsed_command.args {
+                    if !self.aliases.remove(name) {
+                        return Err(ShellError::builtin(
+                            "unalias",
+                            format!("{}: not found", name),
+                        ));
+                    }
+                }
+                Ok(None)
+            }
+            "private" => {
+                self.history.private = match parsed_command.args.first().map(String::as_str) {
+                    Some("on") => true,
+                    Some("off") => false,
+                    _ => !self.history.private,
+                };
+                if self.history.private {
+                    println!("Private mode on: new commands will not be saved to history");
+                } else {
+                    println!("Private mode off");
+                }
+                Ok(None)
+            }
             _ => {
-                let stdin = self.get_stdin(previous_command);
-                let stdout = self.get_stdout(has_more_commands);
+                let stdin = match redirections.stdin {
+                    Some(file) => Stdio::from(file),
+                    None => self.get_stdin(previous_command),
+                };
+                let stdout = match redirections.stdout {
+                    Some(file) => Stdio::from(file),
+                    None => self.get_stdout(has_more_commands),
+                };
+                let stderr = redirections.stderr.map_or_else(Stdio::inherit, Stdio::from);
 
                 let resolved_command = self.resolve_path(command)?;
+                let mut args = parsed_command.args;
+                // Jobs are the shell's to name, so `kill %N` gets their PIDs.
+                if command == "kill" {
+                    args = self.expand_job_specs(args)?;
+                }
 
-                let child = Command::new(resolved_command)
-                    .args(parsed_command.args)
+                let mut command = Command::new(&resolved_command);
+                command
+                    .args(args)
                     .stdin(stdin)
                     .stdout(stdout)
-                    .spawn()?;
+                    .stderr(stderr);
+                self.jobs.prepare(&mut command, group, foreground);
+                if redirections.stderr_to_stdout {
+                    redirect::stderr_to_stdout(&mut command);
+                }
+                let child = command.spawn().map_err(|e| match e.kind() {
+                    io::ErrorKind::NotFound => {
+                        ShellError::CommandNotFound(resolved_command.clone())
+                    }
+                    io::ErrorKind::PermissionDenied => {
+                        ShellError::PermissionDenied(PathBuf::from(&resolved_command))
+                    }
+                    _ => ShellError::from(e),
+                })?;
 
                 Ok(Some(child))
             }
@@ -368,14 +1602,477 @@ impl Shell {
         println!("{}", cwd.to_string_lossy());
     }
 
-    fn change_directory(&self, args: &[String]) -> Result<(), Box<dyn Error>> {
-        let path = args.join("/");
+    /// `alias` lists the aliases, `alias name` shows one and
+    /// `alias name=value` defines one for this session.
+    fn alias_builtin(&mut self, args: &[String]) -> Result<(), ShellError> {
+        if args.is_empty() {
+            for (name, value) in self.aliases.iter() {
+                println!("alias {}='{}'", name, value);
+            }
+        }
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, value)) if !name.is_empty() => self.aliases.set(name, value),
+                _ => match self.aliases.get(arg) {
+                    Some(value) => println!("alias {}='{}'", arg, value),
+                    None => {
+                        return Err(ShellError::builtin("alias", format!("{}: not found", arg)))
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// `bind` lists the key bindings and `bind key action` binds a key for
+    /// this session.
+    fn bind_builtin(&mut self, args: &[String]) -> Result<(), ShellError> {
+        match args {
+            [] => {
+                for (key, action) in self.keymap.describe() {
+                    println!("{} {}", key, action);
+                }
+                Ok(())
+            }
+            [key, action] => Ok(self
+                .keymap
+                .bind(key, action)
+                .map_err(|e| ShellError::builtin("bind", e))?),
+            _ => Err(ShellError::builtin("bind", "usage: bind [key action]")),
+        }
+    }
+
+    /// `hook` lists the hook commands, `hook NAME COMMAND` adds one and
+    /// `hook -c NAME` drops those of a hook. Changes last until `reload`.
+    fn hook_builtin(&mut self, args: &[String]) -> Result<(), ShellError> {
+        let hook = |name: &str| {
+            Hook::from_name(name)
+                .ok_or_else(|| ShellError::builtin("hook", format!("{}: no such hook", name)))
+        };
+        match args {
+            [] => {
+                for (name, command) in self.hooks.describe() {
+                    println!("{} {}", name, command);
+                }
+                Ok(())
+            }
+            [flag, name] if flag == "-c" => {
+                self.hooks.clear(hook(name)?);
+                Ok(())
+            }
+            [name, command @ ..] if !command.is_empty() => {
+                self.hooks.add(hook(name)?, &command.join(" "));
+                Ok(())
+            }
+            _ => Err(ShellError::builtin(
+                "hook",
+                "usage: hook [NAME COMMAND | -c NAME]",
+            )),
+        }
+    }
+
+    /// The job `fg` or `bg` was given, like `%2`, or the current one.
+    /// Replaces each `%JOB` in `args` with the PIDs of the job's processes.
+    fn expand_job_specs(&self, args: Vec<String>) -> Result<Vec<String>, ShellError> {
+        let mut expanded = vec![];
+        for arg in args {
+            if !arg.starts_with('%') {
+                expanded.push(arg);
+                continue;
+            }
+            let pids = self
+                .jobs
+                .pids(&arg)
+                .map_err(|e| ShellError::builtin("kill", e))?;
+            expanded.extend(pids.iter().map(|pid| pid.to_string()));
+        }
+        Ok(expanded)
+    }
+
+    fn job_from_args(&self, name: &'static str, args: &[String]) -> Result<usize, ShellError> {
+        match args {
+            [] | [_] => self
+                .jobs
+                .find(args.first().map(String::as_str))
+                .map_err(|e| ShellError::builtin(name, e)),
+            _ => Err(ShellError::builtin(name, format!("usage: {} [%JOB]", name))),
+        }
+    }
+
+    /// Sources one of the startup files, such as the rc file, if there is
+    /// one.
+    pub fn source_startup_file(&mut self, file: File) {
+        let file = paths::path(file);
+        if !file.exists() {
+            return;
+        }
+        if let Err(e) = self.source(&file) {
+            let error = format!("Cannot source {}: {}", file.display(), e);
+            eprintln!("{}", self.theme.paint(Role::Error, &error));
+        }
+    }
+
+    /// Sources the rc file, and again on `reload`.
+    pub fn source_rc(&mut self) {
+        self.rc_sourced = true;
+        self.source_startup_file(File::Rc);
+    }
+
+    /// Reads the config file again and applies it to the session, then
+    /// sources the rc file again if it was sourced at startup. Aliases and
+    /// key bindings made at the prompt are dropped, as is anything the
+    /// config no longer sets. History settings other than the file apply
+    /// from the next start.
+    ///
+    /// With `auto_reload = true` in `[config]`, this happens before the
+    /// next prompt whenever the file is written.
+    fn reload(&mut self) {
+        let config = load_config(&self.config_path, self.profile.as_deref());
+        self.set_env(&config);
+        let history_path = self
+            .history_file
+            .clone()
+            .unwrap_or_else(|| history_path(&config));
+        if history_path != self.history.path() {
+            let history_config = HistoryConfig::keeping_cipher(&config, self.history.cipher());
+            if let Err(e) = self.switch_history(history_path, history_config) {
+                eprintln!("Cannot open history: {}", e);
+            }
+        }
+        self.theme = Theme::from_config(&config);
+        self.scripts = Scripts::from_config(&config);
+        self.prompt = Prompt::from_config(&config, self.theme.clone(), &self.scripts);
+        if let Some(template) = &self.prompt_template {
+            self.prompt.set_template(template.clone());
+        }
+        self.renderer.set_theme(&self.theme);
+        self.about = About::from_config(&config);
+        self.aliases = Aliases::from_config(&config);
+        self.bookmarks = Bookmarks::from_config(&config);
+        self.hooks = Hooks::from_config(&config);
+        self.title = Title::from_config(&config);
+        self.integration = Integration::from_config(&config);
+        self.notifier = Notifier::from_config(&config);
+        self.audit = Audit::from_config(&config);
+        self.keymap = Keymap::from_config(&config);
+        self.clipboard = Clipboard::from_config(&config);
+        // A completion still running keeps the old one rather than making
+        // the reload wait for it.
+        self.autocompleter = Arc::new(Mutex::new(AutoComplete::from_config(
+            &config,
+            &self.scripts,
+        )));
+        let mouse = config.get_bool("editor", "mouse").unwrap_or(false);
+        if self.mouse && !mouse {
+            let _ = execute!(self.stdout, DisableMouseCapture);
+        }
+        self.mouse = mouse;
+        if self.rc_sourced {
+            self.source_startup_file(File::Rc);
+        }
+    }
+
+    /// Sets the variables in `[env]`, after putting back those set from
+    /// the config before.
+    fn set_env(&mut self, config: &Config) {
+        for (name, value) in self.config_env.drain(..).rev() {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+        for (name, value) in config.section("env").into_iter().flatten() {
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            self.config_env.push((name.clone(), env::var_os(name)));
+            env::set_var(name, value);
+        }
+    }
+
+    /// Saves the history and goes on with the one in the file at `path`.
+    fn switch_history(&mut self, path: PathBuf, config: HistoryConfig) -> io::Result<()> {
+        self.history.save()?;
+        let mut history = History::new(path, config)?;
+        if let Some(load) = history.older_loader() {
+            let older = load();
+            history.add_older(older);
+        }
+        history.private |= self.history.private;
+        self.history = history;
+        Ok(())
+    }
+
+    /// `profile` lists the profiles, `profile use name` lays one over the
+    /// config and `profile off` goes back to the config alone.
+    fn profile_builtin(&mut self, args: &[String]) -> Result<(), ShellError> {
+        match args {
+            [] => {
+                let config = Config::load(&self.config_path);
+                for name in config.profiles() {
+                    let mark = if self.profile.as_deref() == Some(name) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    println!("{} {}", mark, name);
+                }
+            }
+            [command, name] if command == "use" => {
+                Config::load(&self.config_path)
+                    .with_profile(name)
+                    .map_err(|e| ShellError::builtin("profile", e))?;
+                env::set_var("A_SHELL_PROFILE", name);
+                self.profile = Some(name.clone());
+                self.reload();
+                println!("Using profile {}", name);
+            }
+            [command] if command == "off" => {
+                env::remove_var("A_SHELL_PROFILE");
+                self.profile = None;
+                self.reload();
+                println!("Using no profile");
+            }
+            _ => {
+                return Err(ShellError::builtin(
+                    "profile",
+                    "usage: profile [use name | off]",
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends a login session: runs the logout hooks and the logout file.
+    pub fn logout(&mut self) {
+        self.emit(HookEvent::Logout);
+        self.source_startup_file(File::Logout);
+    }
+
+    /// Runs the lines of the file at `path`, as `run_script` does, and
+    /// returns the exit code of the last command.
+    pub fn source(&mut self, path: &Path) -> Result<i32, ShellError> {
+        let content = fs::read_to_string(path)?;
+        Ok(self.run_script(&content))
+    }
+
+    /// Runs the lines of `script` as if they were typed, skipping blank
+    /// lines and `#` comments, and returns the exit code of the last
+    /// command. Lines left unfinished go on in the next one, as at the
+    /// prompt.
+    pub fn run_script(&mut self, script: &str) -> i32 {
+        let typed = std::mem::take(&mut self.input);
+        let mut pending: Option<(String, Continuation)> = None;
+        for line in script.lines() {
+            let line = match pending.take() {
+                Some((earlier, open)) => open.join(&earlier) + line,
+                None if line.trim().is_empty() || line.trim_start().starts_with('#') => continue,
+                None => line.to_string(),
+            };
+            if let Some(open) = parser::continuation(&line) {
+                pending = Some((line, open));
+                continue;
+            }
+            self.input = line;
+            let started = Instant::now();
+            self.last_exit_code = self.process_input().unwrap_or_else(|e| {
+                self.report(&e);
+                e.exit_code()
+            });
+            self.audit
+                .record(&self.input, self.last_exit_code, started.elapsed());
+            if self.should_exit {
+                break;
+            }
+        }
+        self.input = typed;
+        self.last_exit_code
+    }
+
+    /// `record start [file]` runs a nested session recorded to an asciicast
+    /// file; `record stop` (or `exit`) inside it ends the recording.
+    fn record_builtin(&mut self, args: &[String]) -> Result<(), ShellError> {
+        let recording = env::var_os(record::RECORDING_VAR);
+        match args.first().map(String::as_str) {
+            Some("start") if recording.is_some() => {
+                Err(ShellError::builtin("record", "already recording"))
+            }
+            Some("start") => {
+                let path = match args.get(1) {
+                    Some(path) => PathBuf::from(path),
+                    None => record::default_path(&paths::path(File::Recordings))?,
+                };
+                println!(
+                    "Recording to {}; run `record stop` to finish",
+                    path.display()
+                );
+                record::record(&path)?;
+                println!("Recording saved to {}", path.display());
+                Ok(())
+            }
+            Some("stop") if recording.is_some() => {
+                self.should_exit = true;
+                Ok(())
+            }
+            Some("stop") => Err(ShellError::builtin("record", "not recording")),
+            _ => Err(ShellError::builtin(
+                "record",
+                "usage: record start [file] | record stop",
+            )),
+        }
+    }
+
+    fn history_builtin(&mut self, args: &[String]) -> Result<(), ShellError> {
+        match args.first().map(String::as_str) {
+            Some("-c") => self.history.clear(),
+            Some("-d") => {
+                let number = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(0);
+                if !self.history.delete(number) {
+                    return Err(ShellError::builtin(
+                        "history",
+                        format!("{}: position out of range", args.get(1).map_or("", |n| n)),
+                    ));
+                }
+            }
+            Some("-w") => self.history.write()?,
+            Some("-r") => self.history.reload()?,
+            Some("export") => {
+                // history export [--format json|csv] [file]
+                let mut format = ExportFormat::Json;
+                let mut file = None;
+                let mut rest = args[1..].iter();
+                while let Some(arg) = rest.next() {
+                    if arg == "--format" {
+                        let name = rest.next().map_or("", |n| n);
+                        format = ExportFormat::parse(name).ok_or_else(|| {
+                            ShellError::builtin(
+                                "history",
+                                format!("export: unknown format {}", name),
+                            )
+                        })?;
+                    } else {
+                        file = Some(arg);
+                    }
+                }
+                let content = format.format(&self.history.entries);
+                match file {
+                    Some(file) => fs::write(file, content)?,
+                    None => print!("{}", content),
+                }
+            }
+            Some("stats") => print!("{}", stats::summarize(&self.history.entries)),
+            Some("import") => {
+                let path = args
+                    .get(1)
+                    .ok_or_else(|| ShellError::builtin("history", "import: file name required"))?;
+                let count = self.history.import(Path::new(path))?;
+                println!("Imported {} commands from {}", count, path);
+            }
+            _ => self.print_history(args)?,
+        }
+        Ok(())
+    }
+
+    /// Lists the history oldest first, numbered like bash. `-t` adds when
+    /// each command was run, `-v` also its duration, exit code and
+    /// directory, and a number limits output to the last N matches.
+    /// `--failed`, `--cwd DIR` and `--since WHEN` filter the entries.
+    fn print_history(&self, args: &[String]) -> Result<(), ShellError> {
+        let mut verbose = false;
+        let mut show_times = false;
+        let total = self.history.count();
+        let mut count = total;
+        let mut filter = HistoryFilter::default();
+        let mut rest = args.iter();
+        while let Some(arg) = rest.next() {
+            match arg.as_str() {
+                "-t" => show_times = true,
+                "-v" => verbose = true,
+                "--failed" => filter.failed = true,
+                "--cwd" => {
+                    let dir = rest.next().ok_or_else(|| {
+                        ShellError::builtin("history", "--cwd: directory required")
+                    })?;
+                    let dir = env::current_dir()?.join(dir);
+                    let dir = dir.canonicalize().unwrap_or(dir);
+                    filter.cwd = Some(dir.to_string_lossy().into_owned());
+                }
+                "--since" => {
+                    let when = rest
+                        .next()
+                        .ok_or_else(|| ShellError::builtin("history", "--since: time required"))?;
+                    filter.since =
+                        Some(clock::parse_since(when, clock::now()).ok_or_else(|| {
+                            ShellError::builtin(
+                                "history",
+                                format!("--since: cannot parse {}", when),
+                            )
+                        })?);
+                }
+                _ => {
+                    count = arg.parse().map_err(|_| {
+                        ShellError::builtin("history", format!("{}: invalid option", arg))
+                    })?
+                }
+            }
+        }
+        let show_times = show_times || verbose;
+        let matching = self
+            .history
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| filter.matches(entry))
+            .take(count)
+            .collect::<Vec<_>>();
+        for (i, entry) in matching.into_iter().rev() {
+            let time = entry.time.map(clock::format_local).unwrap_or_default();
+            if verbose {
+                println!(
+                    "{:5}  {:19}  {:>7}  {:>3}  {}  {}",
+                    total - i,
+                    time,
+                    entry
+                        .duration
+                        .map(clock::format_duration)
+                        .unwrap_or_default(),
+                    entry.exit_code.map(|c| c.to_string()).unwrap_or_default(),
+                    entry.cwd.as_deref().unwrap_or(""),
+                    entry.command
+                );
+            } else if show_times {
+                println!("{:5}  {:19}  {}", total - i, time, entry.command);
+            } else {
+                println!("{:5}  {}", total - i, entry.command);
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes to `paths` joined as a path. When that is not a directory,
+    /// the `typed` argument is looked up in `$CDPATH` and the bookmarks,
+    /// printing where it led like bash does.
+    fn change_directory(&self, paths: &[String], typed: Option<&String>) -> Result<(), ShellError> {
+        let path = paths.join("/");
         let root = Path::new(&path);
-        env::set_current_dir(&root)?;
+        if let Some(typed) = typed.filter(|_| !root.is_dir()) {
+            let found = self
+                .bookmarks
+                .alternatives(typed)
+                .into_iter()
+                .find(|(dir, _)| dir.is_dir());
+            if let Some((dir, _)) = found {
+                env::set_current_dir(&dir)?;
+                println!("{}", dir.display());
+                return Ok(());
+            }
+        }
+        env::set_current_dir(root)?;
         Ok(())
     }
 
-    fn resolve_path(&self, command: &str) -> Result<String, Box<dyn Error>> {
+    fn resolve_path(&self, command: &str) -> Result<String, ShellError> {
         if command.contains('/') {
             Ok(command.to_string())
         } else {
@@ -387,14 +2084,17 @@ impl Shell {
                     return Ok(full_path.to_string_lossy().to_string());
                 }
             }
-            Err(format!("Command not found: {}", command).into())
+            Err(ShellError::CommandNotFound(command.to_string()))
         }
     }
 
+    /// The input of a command piped from `previous_command`. When that
+    /// one's output went to a file, there is nothing to read.
     fn get_stdin(&self, previous_command: Option<Child>) -> Stdio {
-        previous_command
-            .and_then(|mut child| child.stdout.take())
-            .map_or(Stdio::inherit(), Stdio::from)
+        match previous_command {
+            Some(mut child) => child.stdout.take().map_or(Stdio::null(), Stdio::from),
+            None => Stdio::inherit(),
+        }
     }
 
     fn get_stdout(&self, has_more_commands: bool) -> Stdio {
@@ -405,3 +2105,68 @@ impl Shell {
         }
     }
 }
+
+/// Reads the config at `path`, with `profile` laid over it. An unknown
+/// profile is reported and left out.
+fn load_config(path: &Path, profile: Option<&str>) -> Config {
+    let config = Config::load(path);
+    let Some(profile) = profile else {
+        return config;
+    };
+    config.with_profile(profile).unwrap_or_else(|e| {
+        eprintln!("Cannot use profile {}", e);
+        Config::load(path)
+    })
+}
+
+/// Imports `~/.ash_history` from the first versions once, renaming it so
+/// the next session leaves it alone. A history locked by a missing
+/// passphrase keeps it for a later session.
+fn import_ash_history(history: &mut History) {
+    let legacy = paths::ash_history();
+    if !legacy.is_file() || history.private {
+        return;
+    }
+    let imported = history.import_newest_first(&legacy).and_then(|count| {
+        fs::rename(&legacy, legacy.with_extension("imported"))?;
+        Ok(count)
+    });
+    match imported {
+        Ok(count) => eprintln!("Imported {} commands from {}", count, legacy.display()),
+        Err(e) => eprintln!("Cannot import {}: {}", legacy.display(), e),
+    }
+}
+
+/// The history file: `file` in `[history]`, or the default one.
+fn history_path(config: &Config) -> PathBuf {
+    config
+        .get_str("history", "file")
+        .map(expand_directory)
+        .unwrap_or_else(|| paths::path(File::History))
+}
+
+/// `export` lists the environment and `export name=value` sets a variable
+/// for the shell and the commands it runs.
+fn export_builtin(args: &[String]) -> Result<(), ShellError> {
+    if args.is_empty() {
+        let mut vars = env::vars().collect::<Vec<_>>();
+        vars.sort();
+        for (name, value) in vars {
+            println!("export {}='{}'", name, value);
+        }
+    }
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, value)) if !name.is_empty() && !name.contains('\0') => {
+                env::set_var(name, value)
+            }
+            _ => {
+                return Err(ShellError::builtin(
+                    "export",
+                    format!("{}: expected name=value", arg),
+                ))
+            }
+        }
+    }
+    Ok(())
+}
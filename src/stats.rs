@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use crate::{clock, history::Entry};
+
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const BAR_WIDTH: usize = 30;
+
+/// Summary printed by `history stats`: the most used commands with their
+/// failure rates, and when commands are run by hour and by weekday.
+pub fn summarize(entries: &[Entry]) -> String {
+    let mut out = String::new();
+    let failed = |e: &&Entry| e.exit_code.is_some_and(|code| code != 0);
+    let with_status = entries.iter().filter(|e| e.exit_code.is_some()).count();
+    out.push_str(&format!(
+        "Commands: {} ({} failed{})\n",
+        entries.len(),
+        entries.iter().filter(failed).count(),
+        percent(entries.iter().filter(failed).count(), with_status)
+            .map_or(String::new(), |p| format!(", {}", p)),
+    ));
+
+    // (uses, uses with a known exit code, failures) per command name.
+    let mut commands: HashMap<&str, (usize, usize, usize)> = HashMap::new();
+    for entry in entries {
+        let Some(name) = entry.command.split_whitespace().next() else {
+            continue;
+        };
+        let counts = commands.entry(name).or_default();
+        counts.0 += 1;
+        if entry.exit_code.is_some() {
+            counts.1 += 1;
+        }
+        if failed(&entry) {
+            counts.2 += 1;
+        }
+    }
+    let mut commands = commands.into_iter().collect::<Vec<_>>();
+    commands.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(a.0.cmp(b.0)));
+    out.push_str("\nMost used:\n");
+    for (name, (uses, with_status, failures)) in commands.iter().take(10) {
+        let rate =
+            percent(*failures, *with_status).map_or(String::new(), |p| format!("  {} failed", p));
+        out.push_str(&format!("  {:6}  {:<16}{}\n", uses, name, rate));
+    }
+
+    let times = entries
+        .iter()
+        .filter_map(|e| clock::local(e.time?))
+        .collect::<Vec<_>>();
+    if times.is_empty() {
+        return out;
+    }
+    let mut hours = [0; 24];
+    let mut days = [0; 7];
+    for time in &times {
+        hours[time.hour as usize] += 1;
+        days[time.weekday as usize] += 1;
+    }
+
+    out.push_str("\nBy hour:\n");
+    let busiest = *hours.iter().max().unwrap_or(&0);
+    for (hour, &count) in hours.iter().enumerate().filter(|(_, &c)| c > 0) {
+        out.push_str(&format!(
+            "  {:02}:00  {} {}\n",
+            hour,
+            bar(count, busiest),
+            count
+        ));
+    }
+    out.push_str("\nBy day:\n");
+    let busiest = *days.iter().max().unwrap_or(&0);
+    for (day, &count) in days.iter().enumerate() {
+        out.push_str(&format!(
+            "  {}    {} {}\n",
+            DAYS[day],
+            bar(count, busiest),
+            count
+        ));
+    }
+    out
+}
+
+fn percent(part: usize, whole: usize) -> Option<String> {
+    (whole > 0).then(|| format!("{:.1}%", part as f64 * 100.0 / whole as f64))
+}
+
+fn bar(count: usize, max: usize) -> String {
+    "█".repeat((count * BAR_WIDTH).div_ceil(max.max(1)))
+}
@@ -1,8 +1,10 @@
-pub fn get_command_suggestion(commands: &Vec<String>, input: &str) -> Vec<String> {
+use crate::history::Entry;
+
+pub fn get_command_suggestion(entries: &[Entry], input: &str) -> Vec<String> {
     let mut suggestions: Vec<String> = vec![];
-    for command in commands {
-        if command.starts_with(input) {
-            suggestions.push(command.clone());
+    for entry in entries {
+        if entry.command.starts_with(input) {
+            suggestions.push(entry.command.clone());
         }
     }
     suggestions
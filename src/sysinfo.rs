@@ -0,0 +1,340 @@
+use std::env;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::{
+    clock,
+    output::run_for_output,
+    prompt::{hostname, user},
+};
+
+/// What `about` shows about the system. Anything that cannot be found out
+/// on this platform is `None` or empty.
+pub struct SystemInfo {
+    pub user: String,
+    pub host: String,
+    /// The distribution or product name, like `Debian GNU/Linux 12`.
+    pub os: Option<String>,
+    /// Lowercase ids the OS goes by, most specific first, like `ubuntu`
+    /// and `debian`.
+    pub os_ids: Vec<String>,
+    pub kernel: Option<String>,
+    pub uptime: Option<Duration>,
+    pub packages: Vec<(&'static str, usize)>,
+    pub shell: String,
+    pub terminal: Option<String>,
+    pub resolution: Option<String>,
+    pub cpu: Option<String>,
+    pub gpus: Vec<String>,
+    /// Used and total bytes.
+    pub memory: Option<(u64, u64)>,
+    /// Used and total bytes of the file system holding `/`.
+    pub disk: Option<(u64, u64)>,
+}
+
+impl SystemInfo {
+    pub fn collect() -> Self {
+        let (os, os_ids) = os();
+        let display = display();
+        SystemInfo {
+            user: user(),
+            host: hostname(),
+            os,
+            os_ids,
+            kernel: kernel(),
+            uptime: uptime(),
+            packages: packages(),
+            shell: format!("a-shell {}", env!("CARGO_PKG_VERSION")),
+            terminal: env::var("TERM_PROGRAM")
+                .or_else(|_| env::var("TERM"))
+                .ok()
+                .filter(|t| !t.is_empty()),
+            resolution: display.resolution,
+            cpu: cpu(),
+            gpus: display.gpus,
+            memory: memory(),
+            disk: disk(Path::new("/")),
+        }
+    }
+}
+
+/// Formats a byte count in binary units, e.g. `3.2 GiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
+/// Formats an uptime like `3 days, 4 hours, 12 mins`.
+pub fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let parts = [
+        (secs / 86_400, "day"),
+        (secs / 3600 % 24, "hour"),
+        (secs / 60 % 60, "min"),
+    ];
+    let parts = parts
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, unit)| format!("{} {}{}", n, unit, if *n == 1 { "" } else { "s" }))
+        .collect::<Vec<_>>();
+    match parts.is_empty() {
+        true => format!("{} secs", secs),
+        false => parts.join(", "),
+    }
+}
+
+fn sysctl(name: &str) -> Option<String> {
+    run_for_output(Command::new("sysctl").arg("-n").arg(name))
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn os() -> (Option<String>, Vec<String>) {
+    if cfg!(target_os = "macos") {
+        let name = run_for_output(&mut Command::new("sw_vers"))
+            .map(|out| {
+                let field = |key: &str| {
+                    out.lines()
+                        .find_map(|line| line.strip_prefix(key))
+                        .map(str::trim)
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                format!("{} {}", field("ProductName:"), field("ProductVersion:"))
+            })
+            .filter(|name| !name.trim().is_empty());
+        return (name, vec!["macos".to_string()]);
+    }
+    let release = fs::read_to_string("/etc/os-release")
+        .or_else(|_| fs::read_to_string("/usr/lib/os-release"))
+        .unwrap_or_default();
+    let field = |key: &str| {
+        release.lines().find_map(|line| {
+            let value = line.strip_prefix(key)?.strip_prefix('=')?;
+            Some(value.trim_matches(['"', '\'']).to_string())
+        })
+    };
+    let ids = field("ID")
+        .into_iter()
+        .chain(field("ID_LIKE").into_iter().flat_map(|like| {
+            like.split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        }))
+        .map(|id| id.to_lowercase())
+        .chain([env::consts::OS.to_string()])
+        .collect();
+    (field("PRETTY_NAME").or_else(|| field("NAME")), ids)
+}
+
+fn kernel() -> Option<String> {
+    // SAFETY: `utsname` is plain data and outlives the call, which fills
+    // its fields with NUL-terminated strings.
+    let mut name: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut name) } != 0 {
+        return None;
+    }
+    let field = |field: &[libc::c_char]| {
+        // SAFETY: uname NUL-terminated the field within its length.
+        unsafe { CStr::from_ptr(field.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    Some(format!("{} {}", field(&name.sysname), field(&name.release)))
+}
+
+fn uptime() -> Option<Duration> {
+    if let Ok(uptime) = fs::read_to_string("/proc/uptime") {
+        let secs = uptime.split_whitespace().next()?.parse::<f64>().ok()?;
+        return Some(Duration::from_secs_f64(secs));
+    }
+    // BSDs and macOS print `{ sec = 1700000000, usec = 0 } ...`.
+    let boot = sysctl("kern.boottime")?;
+    let secs = boot
+        .split("sec =")
+        .nth(1)?
+        .split(',')
+        .next()?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(Duration::from_secs(clock::now().saturating_sub(secs)))
+}
+
+fn cpu() -> Option<String> {
+    let model = match fs::read_to_string("/proc/cpuinfo") {
+        Ok(info) => info
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| matches!(key.trim(), "model name" | "Hardware" | "cpu model"))
+            .map(|(_, value)| value.trim().to_string()),
+        Err(_) => sysctl("machdep.cpu.brand_string").or_else(|| sysctl("hw.model")),
+    }?;
+    let cores = std::thread::available_parallelism().map_or(0, |n| n.get());
+    Some(match cores {
+        0 => model,
+        cores => format!("{} ({})", model, cores),
+    })
+}
+
+fn memory() -> Option<(u64, u64)> {
+    if let Ok(info) = fs::read_to_string("/proc/meminfo") {
+        let field = |key: &str| {
+            info.lines()
+                .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+                .and_then(|value| value.split_whitespace().next()?.parse::<u64>().ok())
+                .map(|kb| kb * 1024)
+        };
+        let total = field("MemTotal")?;
+        let available = field("MemAvailable").or_else(|| field("MemFree"))?;
+        return Some((total.saturating_sub(available), total));
+    }
+    let total = sysctl("hw.memsize")
+        .or_else(|| sysctl("hw.physmem"))?
+        .parse()
+        .ok()?;
+    let page_size = sysctl("hw.pagesize")
+        .and_then(|size| size.parse::<u64>().ok())
+        .unwrap_or(4096);
+    // vm_stat counts pages; what is neither free nor reclaimable is used.
+    let vm_stat = run_for_output(&mut Command::new("vm_stat")).unwrap_or_default();
+    let pages = |key: &str| {
+        vm_stat
+            .lines()
+            .find_map(|line| line.strip_prefix(key))
+            .and_then(|value| value.trim().trim_end_matches('.').parse::<u64>().ok())
+    };
+    let used = match (pages("Pages active:"), pages("Pages wired down:")) {
+        (Some(active), Some(wired)) => (active + wired) * page_size,
+        _ => return Some((0, total)),
+    };
+    Some((used, total))
+}
+
+fn disk(mount: &Path) -> Option<(u64, u64)> {
+    let path = CString::new(mount.to_str()?).ok()?;
+    // SAFETY: `stat` is plain data, and both pointers outlive the call.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let block = stat.f_frsize as u64;
+    let total = stat.f_blocks as u64 * block;
+    let free = stat.f_bfree as u64 * block;
+    Some((total.saturating_sub(free), total))
+}
+
+/// Installed packages by package manager, for those found.
+fn packages() -> Vec<(&'static str, usize)> {
+    let count_dir = |dir: &str| {
+        fs::read_dir(dir)
+            .ok()
+            .map(|entries| entries.flatten().filter(|e| e.path().is_dir()).count())
+    };
+    let count_lines = |file: &str, prefix: &str| {
+        fs::read_to_string(file)
+            .ok()
+            .map(|content| content.lines().filter(|l| l.starts_with(prefix)).count())
+    };
+    let count_output = |program: &str, args: &[&str]| {
+        run_for_output(Command::new(program).args(args))
+            .map(|out| out.lines().filter(|line| !line.trim().is_empty()).count())
+    };
+    let mut packages = vec![
+        (
+            "dpkg",
+            count_lines("/var/lib/dpkg/status", "Status: install ok installed"),
+        ),
+        ("pacman", count_dir("/var/lib/pacman/local")),
+        ("apk", count_lines("/lib/apk/db/installed", "P:")),
+        ("xbps", count_output("xbps-query", &["-l"])),
+    ];
+    if Path::new("/var/lib/rpm").is_dir() {
+        packages.push(("rpm", count_output("rpm", &["-qa"])));
+    }
+    for cellar in ["/opt/homebrew/Cellar", "/usr/local/Cellar"] {
+        packages.push(("brew", count_dir(cellar)));
+    }
+    if Path::new("/var/lib/flatpak").is_dir() {
+        packages.push(("flatpak", count_output("flatpak", &["list", "--app"])));
+    }
+    if Path::new("/snap").is_dir() {
+        // The first line is a header.
+        packages.push((
+            "snap",
+            count_output("snap", &["list"]).map(|n| n.saturating_sub(1)),
+        ));
+    }
+    packages
+        .into_iter()
+        .filter_map(|(manager, count)| Some((manager, count?)))
+        .filter(|(_, count)| *count > 0)
+        .collect()
+}
+
+struct Display {
+    resolution: Option<String>,
+    gpus: Vec<String>,
+}
+
+fn display() -> Display {
+    if cfg!(target_os = "macos") {
+        let profile = run_for_output(Command::new("system_profiler").arg("SPDisplaysDataType"))
+            .unwrap_or_default();
+        let values = |key: &str| {
+            profile
+                .lines()
+                .filter_map(|line| line.trim().strip_prefix(key))
+                .map(|value| value.trim().to_string())
+                .collect::<Vec<_>>()
+        };
+        return Display {
+            resolution: Some(values("Resolution:").join(", ")).filter(|r| !r.is_empty()),
+            gpus: values("Chipset Model:"),
+        };
+    }
+    Display {
+        resolution: drm_resolution(),
+        gpus: run_for_output(Command::new("lspci").arg("-mm"))
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| line.contains("VGA") || line.contains("3D controller"))
+            .filter_map(|line| {
+                // Fields are quoted: slot, class, vendor, device, ...
+                let fields = line.split('"').skip(1).step_by(2).collect::<Vec<_>>();
+                Some(format!("{} {}", fields.get(2)?, fields.get(3)?))
+            })
+            .collect(),
+    }
+}
+
+/// The preferred mode of each connected output, from the kernel's DRM
+/// devices, e.g. `2560x1440, 1920x1080`.
+fn drm_resolution() -> Option<String> {
+    let mut modes = fs::read_dir("/sys/class/drm")
+        .ok()?
+        .flatten()
+        .filter(|output| {
+            fs::read_to_string(output.path().join("status")).is_ok_and(|s| s.trim() == "connected")
+        })
+        .filter_map(|output| {
+            let modes = fs::read_to_string(output.path().join("modes")).ok()?;
+            Some(modes.lines().next()?.trim().to_string())
+        })
+        .collect::<Vec<_>>();
+    modes.sort();
+    modes.dedup();
+    Some(modes.join(", ")).filter(|modes| !modes.is_empty())
+}
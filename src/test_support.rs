@@ -0,0 +1,523 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::{editor::display_width, record::spawn_on_pty};
+
+/// The prompt test shells show, set through `$PROMPT`.
+pub const PROMPT: &str = "test$ ";
+
+/// How long `wait_for` and friends wait before giving up.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Key sequences a terminal sends, to pass to `PtyShell::send`.
+pub mod keys {
+    pub const ENTER: &str = "\r";
+    pub const TAB: &str = "\t";
+    pub const BACKSPACE: &str = "\x7f";
+    pub const UP: &str = "\x1b[A";
+    pub const DOWN: &str = "\x1b[B";
+    pub const RIGHT: &str = "\x1b[C";
+    pub const LEFT: &str = "\x1b[D";
+    pub const CTRL_A: &str = "\x01";
+    pub const CTRL_C: &str = "\x03";
+    pub const CTRL_D: &str = "\x04";
+    pub const CTRL_E: &str = "\x05";
+    pub const CTRL_K: &str = "\x0b";
+    pub const CTRL_R: &str = "\x12";
+    pub const CTRL_U: &str = "\x15";
+    pub const CTRL_W: &str = "\x17";
+    pub const CTRL_Z: &str = "\x1a";
+}
+
+static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets up a shell to run on a pseudo-terminal, in a home directory of its
+/// own that is removed afterwards:
+///
+/// ```no_run
+/// use a_shell::test_support::{keys, PtyShellBuilder};
+///
+/// // In an integration test, `env!("CARGO_BIN_EXE_a-shell")`.
+/// let mut shell = PtyShellBuilder::new("target/debug/a-shell")
+///     .file("notes.txt", "hello\n")
+///     .spawn()?;
+/// shell.send("cat notes.txt")?;
+/// shell.send(keys::ENTER)?;
+/// shell.wait_for("hello")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct PtyShellBuilder {
+    binary: PathBuf,
+    args: Vec<String>,
+    config: Option<String>,
+    rc: Option<String>,
+    history: Vec<String>,
+    files: Vec<(PathBuf, String)>,
+    size: (u16, u16),
+}
+
+impl PtyShellBuilder {
+    /// For the shell binary at `binary`, which integration tests find in
+    /// `CARGO_BIN_EXE_a-shell`.
+    pub fn new(binary: impl Into<PathBuf>) -> Self {
+        PtyShellBuilder {
+            binary: binary.into(),
+            args: vec![],
+            config: None,
+            rc: None,
+            history: vec![],
+            files: vec![],
+            size: (80, 24),
+        }
+    }
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// The content of the config file.
+    pub fn config(mut self, toml: &str) -> Self {
+        self.config = Some(toml.to_string());
+        self
+    }
+
+    /// The content of the rc file.
+    pub fn rc(mut self, rc: &str) -> Self {
+        self.rc = Some(rc.to_string());
+        self
+    }
+
+    /// Commands already in the history file, oldest first.
+    pub fn history(mut self, commands: &[&str]) -> Self {
+        self.history = commands.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
+    /// A file at `path` in the directory the shell starts in.
+    pub fn file(mut self, path: impl Into<PathBuf>, content: &str) -> Self {
+        self.files.push((path.into(), content.to_string()));
+        self
+    }
+
+    /// The terminal's size in columns and rows.
+    pub fn size(mut self, cols: u16, rows: u16) -> Self {
+        self.size = (cols, rows);
+        self
+    }
+
+    pub fn spawn(self) -> io::Result<PtyShell> {
+        let home = env::temp_dir().join(format!(
+            "a-shell-test-{}-{}",
+            process::id(),
+            SESSIONS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let work = home.join("work");
+        fs::create_dir_all(&work)?;
+        let config_dir = home.join(".config/a-shell");
+        let state_dir = home.join(".local/state/a-shell");
+        fs::create_dir_all(&config_dir)?;
+        fs::create_dir_all(&state_dir)?;
+        if let Some(config) = &self.config {
+            fs::write(config_dir.join("config.toml"), config)?;
+        }
+        if let Some(rc) = &self.rc {
+            fs::write(config_dir.join("rc"), rc)?;
+        }
+        if !self.history.is_empty() {
+            fs::write(state_dir.join("history"), self.history.join("\n") + "\n")?;
+        }
+        for (path, content) in &self.files {
+            let path = work.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, content)?;
+        }
+
+        let mut command = Command::new(&self.binary);
+        command
+            .args(&self.args)
+            .current_dir(&work)
+            .env_clear()
+            .env("PATH", env::var_os("PATH").unwrap_or_default())
+            .env("HOME", &home)
+            .env("USER", "tester")
+            .env("TERM", "xterm-256color")
+            .env("PROMPT", PROMPT);
+        let (cols, rows) = self.size;
+        let (master, child) = spawn_on_pty(command, cols, rows)?;
+        Ok(PtyShell {
+            master,
+            child,
+            screen: Screen::new(cols, rows),
+            home,
+            work,
+        })
+    }
+}
+
+/// A shell running on a pseudo-terminal, with a screen its output is
+/// rendered on as a terminal would.
+pub struct PtyShell {
+    master: File,
+    child: Child,
+    screen: Screen,
+    home: PathBuf,
+    work: PathBuf,
+}
+
+impl PtyShell {
+    /// Types `keys`, such as text or the sequences in `keys`.
+    pub fn send(&mut self, keys: &str) -> io::Result<()> {
+        self.master.write_all(keys.as_bytes())?;
+        self.master.flush()
+    }
+
+    /// Types `line` and Enter.
+    pub fn run(&mut self, line: &str) -> io::Result<()> {
+        self.send(line)?;
+        self.send(keys::ENTER)
+    }
+
+    /// Waits until `text` is on the screen.
+    pub fn wait_for(&mut self, text: &str) -> io::Result<()> {
+        self.wait_until(&format!("{:?} on the screen", text), |screen| {
+            screen.text().contains(text)
+        })
+    }
+
+    /// Waits until the line the cursor is on reads `line`, ignoring
+    /// trailing blanks.
+    pub fn wait_for_line(&mut self, line: &str) -> io::Result<()> {
+        self.wait_until(&format!("the cursor line to be {:?}", line), |screen| {
+            screen.line(screen.cursor().0) == line
+        })
+    }
+
+    /// Waits until `done` holds for the screen, failing with the screen's
+    /// content after a while.
+    pub fn wait_until(&mut self, what: &str, done: impl Fn(&Screen) -> bool) -> io::Result<()> {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if done(&self.screen) {
+                return Ok(());
+            }
+            if Instant::now() >= deadline || !self.pump(Duration::from_millis(20))? {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "waited for {}; the screen shows:\n{}",
+                        what,
+                        self.screen.text()
+                    ),
+                ));
+            }
+        }
+    }
+
+    /// Waits for the shell to exit and returns its exit code, or 128 plus
+    /// the signal that ended it.
+    pub fn wait(mut self) -> io::Result<i32> {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                return Ok(status
+                    .code()
+                    .unwrap_or_else(|| 128 + status.signal().unwrap_or_default()));
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "the shell did not exit; the screen shows:\n{}",
+                        self.screen.text()
+                    ),
+                ));
+            }
+            self.pump(Duration::from_millis(20))?;
+        }
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    /// The home directory the shell runs with.
+    pub fn home(&self) -> &Path {
+        &self.home
+    }
+
+    /// The directory the shell starts in, where `file`s are put.
+    pub fn work_dir(&self) -> &Path {
+        &self.work
+    }
+
+    /// Renders the output that arrives within `timeout`. Returns false
+    /// once the terminal is closed.
+    fn pump(&mut self, timeout: Duration) -> io::Result<bool> {
+        let mut fd = libc::pollfd {
+            fd: self.master.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `fd` is valid for the duration of the call.
+        let ready = unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as i32) };
+        if ready <= 0 {
+            return Ok(true);
+        }
+        let mut buffer = [0u8; 8192];
+        match self.master.read(&mut buffer) {
+            // Linux reports a closed pseudo-terminal as EIO.
+            Ok(0) | Err(_) => Ok(false),
+            Ok(n) => {
+                let replies = self.screen.feed(&buffer[..n]);
+                if !replies.is_empty() {
+                    self.master.write_all(&replies)?;
+                }
+                Ok(true)
+            }
+        }
+    }
+}
+
+impl Drop for PtyShell {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.home);
+    }
+}
+
+/// The visible screen of a terminal, understanding as much of its escape
+/// sequences as the shell uses. Colors and other styles are dropped.
+pub struct Screen {
+    cols: usize,
+    rows: usize,
+    /// Cells by row; `None` is the right half of a wide character.
+    cells: Vec<Vec<Option<char>>>,
+    row: usize,
+    col: usize,
+    /// Set after writing to the last column: the next character wraps.
+    wrap_pending: bool,
+    saved: (usize, usize),
+    /// Output not yet rendered, ending in an incomplete sequence.
+    pending: Vec<u8>,
+}
+
+impl Screen {
+    fn new(cols: u16, rows: u16) -> Self {
+        let (cols, rows) = (cols.max(1) as usize, rows.max(1) as usize);
+        Screen {
+            cols,
+            rows,
+            cells: vec![vec![Some(' '); cols]; rows],
+            row: 0,
+            col: 0,
+            wrap_pending: false,
+            saved: (0, 0),
+            pending: vec![],
+        }
+    }
+
+    /// Row `row`, without trailing blanks.
+    pub fn line(&self, row: usize) -> String {
+        self.cells
+            .get(row)
+            .map(|cells| cells.iter().flatten().collect::<String>())
+            .unwrap_or_default()
+            .trim_end()
+            .to_string()
+    }
+
+    /// All rows, without trailing blanks or blank rows at the end.
+    pub fn text(&self) -> String {
+        let lines = (0..self.rows).map(|row| self.line(row)).collect::<Vec<_>>();
+        lines.join("\n").trim_end().to_string()
+    }
+
+    /// The cursor's row and column, from 0.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.col)
+    }
+
+    /// Renders `output` and returns what the terminal answers, such as
+    /// cursor position reports.
+    fn feed(&mut self, output: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(output);
+        let bytes = std::mem::take(&mut self.pending);
+        let valid = match std::str::from_utf8(&bytes) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            // Not UTF-8 at all: render what is, replaced.
+            Err(_) => bytes.len(),
+        };
+        let text = String::from_utf8_lossy(&bytes[..valid]).into_owned();
+        let mut replies = vec![];
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            match c {
+                '\x1b' => {
+                    let rest = &text[start + 1..];
+                    let Some(length) = self.escape(rest, &mut replies) else {
+                        self.pending.extend_from_slice(&text.as_bytes()[start..]);
+                        break;
+                    };
+                    while chars.next_if(|(i, _)| *i <= start + length).is_some() {}
+                }
+                '\r' => {
+                    self.col = 0;
+                    self.wrap_pending = false;
+                }
+                '\n' => self.line_feed(),
+                '\x08' => {
+                    self.col = self.col.saturating_sub(1);
+                    self.wrap_pending = false;
+                }
+                '\t' => self.col = ((self.col / 8 + 1) * 8).min(self.cols - 1),
+                c if c.is_control() => {}
+                c => self.put(c),
+            }
+        }
+        self.pending.extend_from_slice(&bytes[valid..]);
+        replies
+    }
+
+    /// Handles the escape sequence `rest` starts with, after the ESC, and
+    /// returns its length, or `None` when it is incomplete.
+    fn escape(&mut self, rest: &str, replies: &mut Vec<u8>) -> Option<usize> {
+        let mut chars = rest.chars();
+        match chars.next()? {
+            '[' => {
+                let body = &rest[1..];
+                let end = body.find(|c| ('@'..='~').contains(&c))?;
+                let final_byte = body[end..].chars().next()?;
+                self.control(&body[..end], final_byte, replies);
+                Some(end + 2)
+            }
+            ']' => {
+                // Operating system commands end in BEL or ST.
+                let body = &rest[1..];
+                let end = body.find(['\x07', '\x1b'])?;
+                if body[end..].starts_with('\x07') {
+                    Some(end + 2)
+                } else {
+                    body[end..].get(1..2).map(|_| end + 3)
+                }
+            }
+            '7' => {
+                self.saved = (self.row, self.col);
+                Some(1)
+            }
+            '8' => {
+                (self.row, self.col) = self.saved;
+                self.wrap_pending = false;
+                Some(1)
+            }
+            _ => Some(1),
+        }
+    }
+
+    /// Handles the control sequence with `params` and `final_byte`.
+    fn control(&mut self, params: &str, final_byte: char, replies: &mut Vec<u8>) {
+        if params.starts_with('?') || params.starts_with('>') {
+            // Modes the screen has no use for, like hiding the cursor.
+            return;
+        }
+        let numbers = params
+            .split(';')
+            .map(|n| n.parse::<usize>().ok())
+            .collect::<Vec<_>>();
+        let number = |i: usize, default: usize| {
+            numbers
+                .get(i)
+                .copied()
+                .flatten()
+                .filter(|n| *n > 0)
+                .unwrap_or(default)
+        };
+        self.wrap_pending = false;
+        match final_byte {
+            'A' => self.row = self.row.saturating_sub(number(0, 1)),
+            'B' => self.row = (self.row + number(0, 1)).min(self.rows - 1),
+            'C' => self.col = (self.col + number(0, 1)).min(self.cols - 1),
+            'D' => self.col = self.col.saturating_sub(number(0, 1)),
+            'G' => self.col = (number(0, 1) - 1).min(self.cols - 1),
+            'H' | 'f' => {
+                self.row = (number(0, 1) - 1).min(self.rows - 1);
+                self.col = (number(1, 1) - 1).min(self.cols - 1);
+            }
+            'J' => {
+                let (row, col) = (self.row, self.col);
+                let (from, to) = match numbers.first().copied().flatten().unwrap_or(0) {
+                    0 => ((row, col), (self.rows, 0)),
+                    1 => ((0, 0), (row, col + 1)),
+                    _ => ((0, 0), (self.rows, 0)),
+                };
+                self.erase(from, to);
+            }
+            'K' => {
+                let (row, col) = (self.row, self.col);
+                let (from, to) = match numbers.first().copied().flatten().unwrap_or(0) {
+                    0 => (col, self.cols),
+                    1 => (0, col + 1),
+                    _ => (0, self.cols),
+                };
+                self.erase((row, from), (row, to));
+            }
+            'n' if number(0, 0) == 6 => replies
+                .extend_from_slice(format!("\x1b[{};{}R", self.row + 1, self.col + 1).as_bytes()),
+            _ => {}
+        }
+    }
+
+    /// Blanks the cells from `from` up to but not including `to`, both as
+    /// row and column.
+    fn erase(&mut self, from: (usize, usize), to: (usize, usize)) {
+        for row in from.0..=to.0.min(self.rows - 1) {
+            let start = if row == from.0 { from.1 } else { 0 };
+            let end = if row == to.0 { to.1 } else { self.cols };
+            for cell in &mut self.cells[row][start.min(self.cols)..end.min(self.cols)] {
+                *cell = Some(' ');
+            }
+        }
+    }
+
+    fn put(&mut self, c: char) {
+        let width = display_width(c.encode_utf8(&mut [0; 4])) as usize;
+        if width == 0 {
+            return;
+        }
+        if self.wrap_pending || self.col + width > self.cols {
+            self.col = 0;
+            self.line_feed();
+        }
+        self.cells[self.row][self.col] = Some(c);
+        if width == 2 && self.col + 1 < self.cols {
+            self.cells[self.row][self.col + 1] = None;
+        }
+        if self.col + width >= self.cols {
+            self.col = self.cols - 1;
+            self.wrap_pending = true;
+        } else {
+            self.col += width;
+        }
+    }
+
+    fn line_feed(&mut self) {
+        self.wrap_pending = false;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.cells.remove(0);
+            self.cells.push(vec![Some(' '); self.cols]);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::env;
+use std::io::{stdout, IsTerminal};
+
+use crate::config::Config;
+
+/// The parts of the screen a theme styles.
+#[derive(Clone, Copy)]
+pub enum Role {
+    /// The default prompt.
+    Prompt,
+    /// The `[private]` marker before the prompt.
+    Private,
+    /// The suggested rest of the line after the input.
+    Hint,
+    /// Descriptions and previews in the completion menu.
+    Description,
+    /// The selected completion.
+    Selection,
+    /// Matched characters in the history picker, and the bracket or quote
+    /// matching the one at the cursor.
+    Match,
+    /// The `>` marking the picker's query and selected entry.
+    Pointer,
+    Error,
+    /// Signs of a dangerous context, such as the prompt of root.
+    Warning,
+    /// Notices such as how long a command took.
+    Notice,
+    /// A command name in the input that exists. Ones that do not get the
+    /// error style.
+    Command,
+    /// An argument in the input that names an existing file.
+    Path,
+}
+
+/// Config keys of the roles, in the order of `Role`.
+pub const ROLES: [&str; 12] = [
+    "prompt",
+    "private",
+    "hint",
+    "description",
+    "selection",
+    "match",
+    "pointer",
+    "error",
+    "warning",
+    "notice",
+    "command",
+    "path",
+];
+
+/// Built-in themes, each with a style for every role in the order of
+/// `Role`. `mono` uses no colors and is what `NO_COLOR` gets.
+const THEMES: [(&str, [&str; 12]); 4] = [
+    (
+        "default",
+        [
+            "blue",
+            "magenta",
+            "dim",
+            "dim",
+            "reverse",
+            "bold yellow",
+            "bold cyan",
+            "red",
+            "bold red",
+            "dim",
+            "green",
+            "underline",
+        ],
+    ),
+    (
+        "solarized",
+        [
+            "#268bd2",
+            "#d33682",
+            "#586e75",
+            "#657b83",
+            "reverse",
+            "bold #b58900",
+            "bold #2aa198",
+            "#dc322f",
+            "bold #dc322f",
+            "#586e75",
+            "#859900",
+            "underline",
+        ],
+    ),
+    (
+        "gruvbox",
+        [
+            "109",
+            "175",
+            "245",
+            "245",
+            "reverse",
+            "bold 214",
+            "bold 108",
+            "167",
+            "bold 167",
+            "245",
+            "142",
+            "underline",
+        ],
+    ),
+    (
+        "mono",
+        [
+            "bold",
+            "underline",
+            "dim",
+            "dim",
+            "reverse",
+            "bold",
+            "bold",
+            "bold",
+            "reverse",
+            "dim",
+            "",
+            "underline",
+        ],
+    ),
+];
+
+/// Colors and attributes for the prompt, completion menu, suggestions and
+/// errors, from `[theme]` in the config file:
+///
+/// ```toml
+/// [theme]
+/// name = "gruvbox"        # default, solarized, gruvbox or mono
+/// error = "bold #ff5f5f"  # overrides one role of the theme
+/// hint = "italic 244"
+/// selection = "black on cyan"
+/// ```
+///
+/// A style is a list of attributes (`bold`, `dim`, `italic`, `underline`,
+/// `reverse`), a color name, 0-255 or `#rrggbb`, and `on` before a
+/// background color. When `NO_COLOR` is set only attributes are used, and
+/// when standard output is not a terminal nothing is styled at all.
+#[derive(Clone)]
+pub struct Theme {
+    /// SGR parameters per role, empty for none.
+    styles: [String; 12],
+    colors: bool,
+    enabled: bool,
+}
+
+/// Whether `name` is one of the built-in themes.
+pub fn is_theme(name: &str) -> bool {
+    THEMES.iter().any(|(theme, _)| *theme == name)
+}
+
+impl Theme {
+    pub fn from_config(config: &Config) -> Self {
+        let enabled = stdout().is_terminal();
+        let colors = enabled && use_color();
+        let name = match config.get_str("theme", "name") {
+            Some(name) if colors => name,
+            _ if colors => "default",
+            _ => "mono",
+        };
+        let builtin = THEMES
+            .iter()
+            .find(|(theme, _)| *theme == name)
+            .unwrap_or_else(|| {
+                eprintln!("Unknown theme {}", name);
+                &THEMES[0]
+            })
+            .1;
+        let mut theme = Theme {
+            styles: Default::default(),
+            colors,
+            enabled,
+        };
+        for (role, key) in ROLES.iter().enumerate() {
+            let spec = config
+                .get_str("theme", key)
+                .filter(|_| colors)
+                .unwrap_or(builtin[role]);
+            theme.styles[role] = theme.sgr(spec);
+        }
+        theme
+    }
+
+    /// The SGR parameters for `role`, empty for none.
+    pub fn style(&self, role: Role) -> &str {
+        &self.styles[role as usize]
+    }
+
+    /// `text` in the style of `role`.
+    pub fn paint(&self, role: Role, text: &str) -> String {
+        match self.style(role) {
+            "" => text.to_string(),
+            style => format!("\x1b[{}m{}\x1b[0m", style, text),
+        }
+    }
+
+    /// SGR parameters for a style such as `bold red on 236`, without the
+    /// parts this terminal should not get.
+    pub fn sgr(&self, spec: &str) -> String {
+        if !self.enabled {
+            return String::new();
+        }
+        let mut params = vec![];
+        let mut background = false;
+        for word in spec.split_whitespace() {
+            let param = match word {
+                "bold" => Some("1".to_string()),
+                "dim" => Some("2".to_string()),
+                "italic" => Some("3".to_string()),
+                "underline" => Some("4".to_string()),
+                "reverse" => Some("7".to_string()),
+                "on" => {
+                    background = true;
+                    continue;
+                }
+                color_name if self.colors => color(color_name, background),
+                _ => None,
+            };
+            params.extend(param);
+            background = false;
+        }
+        params.join(";")
+    }
+}
+
+/// Whether colors are wanted: on a terminal, unless `NO_COLOR` is set to
+/// anything.
+pub fn use_color() -> bool {
+    stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+/// SGR parameters for a color given by name, as a 256-color number or as
+/// `#rrggbb`.
+fn color(name: &str, background: bool) -> Option<String> {
+    const NAMES: [&str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    let base = if background { 40 } else { 30 };
+    if let Some(index) = NAMES.iter().position(|n| *n == name) {
+        return Some((base + index).to_string());
+    }
+    if let Some(index) = name
+        .strip_prefix("bright-")
+        .and_then(|name| NAMES.iter().position(|n| *n == name))
+    {
+        return Some((base + 60 + index).to_string());
+    }
+    if let Some(hex) = name.strip_prefix('#').filter(|hex| hex.len() == 6) {
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(format!(
+            "{};2;{};{};{}",
+            base + 8,
+            channel(0)?,
+            channel(2)?,
+            channel(4)?
+        ));
+    }
+    let number = name.parse::<u8>().ok()?;
+    Some(format!("{};5;{}", base + 8, number))
+}
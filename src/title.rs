@@ -0,0 +1,83 @@
+use std::io::{self, stdout, IsTerminal, Write};
+
+use crate::{
+    config::Config,
+    prompt::{home_relative_dir, host, user},
+};
+
+/// The terminal window title, set from `[title]` in the config file:
+///
+/// ```toml
+/// [title]
+/// idle = "{user}@{host}: {cwd}"   # while waiting for input, `{cwd}` by default
+/// running = "{command}"           # while a command line runs, the default
+/// enabled = false                 # leave the title alone
+/// ```
+///
+/// An empty template leaves the title as it is at that point. The title
+/// from before the shell started is restored when it exits.
+pub struct Title {
+    idle: String,
+    running: String,
+    enabled: bool,
+}
+
+impl Title {
+    pub fn from_config(config: &Config) -> Self {
+        Title {
+            idle: config
+                .get_str("title", "idle")
+                .unwrap_or("{cwd}")
+                .to_string(),
+            running: config
+                .get_str("title", "running")
+                .unwrap_or("{command}")
+                .to_string(),
+            enabled: config.get_bool("title", "enabled").unwrap_or(true) && stdout().is_terminal(),
+        }
+    }
+
+    /// Saves the current title on the terminal's title stack.
+    pub fn save(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.enabled {
+            write!(out, "\x1b[22;0t")?;
+        }
+        Ok(())
+    }
+
+    /// Brings back the title saved by `save`.
+    pub fn restore(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.enabled {
+            write!(out, "\x1b[23;0t")?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Shows that the shell waits for input.
+    pub fn idle(&self, out: &mut impl Write) -> io::Result<()> {
+        self.set(out, &self.idle, "")
+    }
+
+    /// Shows that `command` runs.
+    pub fn running(&self, out: &mut impl Write, command: &str) -> io::Result<()> {
+        self.set(out, &self.running, command)
+    }
+
+    fn set(&self, out: &mut impl Write, template: &str, command: &str) -> io::Result<()> {
+        if !self.enabled || template.is_empty() {
+            return Ok(());
+        }
+        let title = template
+            .replace("{cwd}", &home_relative_dir())
+            .replace("{user}", &user())
+            .replace("{host}", &host())
+            .replace("{command}", command.lines().next().unwrap_or_default());
+        let title = title
+            .chars()
+            .filter(|c| !c.is_control())
+            .collect::<String>();
+        write!(out, "\x1b]2;{}\x07", title)?;
+        out.flush()
+    }
+}
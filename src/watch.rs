@@ -0,0 +1,68 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::thread;
+
+/// Calls `on_change` from a background thread whenever `path` is written
+/// or replaced. The parent directory is watched, since saving through a
+/// rename swaps in a new file. Only available on Linux, through inotify.
+#[cfg(target_os = "linux")]
+pub fn watch_file(path: &Path, on_change: impl Fn() -> bool + Send + 'static) -> io::Result<()> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file"));
+    };
+    let dir = CString::new(dir.as_os_str().as_bytes())?;
+    let name = name.as_bytes().to_vec();
+
+    // SAFETY: plain syscalls on a descriptor this function owns.
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_MODIFY;
+    if unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), mask) } < 0 {
+        let error = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(error);
+    }
+
+    thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+        loop {
+            let read = unsafe { libc::read(fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+            if read <= 0 {
+                break;
+            }
+            let mut changed = false;
+            let mut offset = 0;
+            // Each record is a fixed header followed by a NUL-padded name.
+            let header = std::mem::size_of::<libc::inotify_event>();
+            while offset + header <= read as usize {
+                let event = unsafe {
+                    std::ptr::read_unaligned(
+                        buffer[offset..].as_ptr().cast::<libc::inotify_event>(),
+                    )
+                };
+                let start = offset + header;
+                let end = start + event.len as usize;
+                let event_name = buffer[start..end].split(|&b| b == 0).next().unwrap_or(&[]);
+                changed |= event_name == name.as_slice();
+                offset = end;
+            }
+            if changed && !on_change() {
+                break;
+            }
+        }
+        unsafe { libc::close(fd) };
+    });
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn watch_file(_path: &Path, _on_change: impl Fn() -> bool + Send + 'static) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "file watching needs inotify",
+    ))
+}
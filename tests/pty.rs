@@ -0,0 +1,257 @@
+use std::fs;
+use std::io;
+
+use a_shell::test_support::{keys, PtyShell, PtyShellBuilder, PROMPT};
+
+fn shell() -> PtyShellBuilder {
+    PtyShellBuilder::new(env!("CARGO_BIN_EXE_a-shell"))
+}
+
+/// Starts `builder` and waits for the first prompt.
+fn start(builder: PtyShellBuilder) -> io::Result<PtyShell> {
+    let mut shell = builder.spawn()?;
+    shell.wait_for_line(PROMPT.trim_end())?;
+    Ok(shell)
+}
+
+/// Waits until `line` ran and the next prompt is up.
+fn wait_for_prompt_after(shell: &mut PtyShell, line: &str) -> io::Result<()> {
+    let typed = format!("{}{}", PROMPT, line);
+    shell.wait_until("the next prompt", |screen| {
+        let (row, _) = screen.cursor();
+        screen.line(row) == PROMPT.trim_end()
+            && (0..row).any(|earlier| screen.line(earlier) == typed)
+    })
+}
+
+#[test]
+fn typed_text_is_inserted_at_the_cursor() -> io::Result<()> {
+    let mut shell = start(shell())?;
+    shell.send("world")?;
+    shell.send(keys::CTRL_A)?;
+    shell.send("hello ")?;
+    shell.wait_for_line("test$ hello world")?;
+    shell.send(keys::CTRL_E)?;
+    shell.send("!")?;
+    shell.wait_for_line("test$ hello world!")?;
+    shell.send(keys::LEFT)?;
+    shell.send(keys::BACKSPACE)?;
+    shell.wait_for_line("test$ hello worl!")
+}
+
+#[test]
+fn ctrl_w_and_ctrl_u_delete_backwards() -> io::Result<()> {
+    let mut shell = start(shell())?;
+    shell.send("echo one two")?;
+    shell.send(keys::CTRL_W)?;
+    shell.wait_for_line("test$ echo one")?;
+    shell.send(keys::CTRL_U)?;
+    shell.wait_for_line("test$")
+}
+
+#[test]
+fn tab_completes_a_unique_file() -> io::Result<()> {
+    let mut shell = start(shell().file("unique-name.txt", "contents\n"))?;
+    shell.send("cat uni")?;
+    shell.send(keys::TAB)?;
+    shell.wait_for_line("test$ cat unique-name.txt")?;
+    shell.send(keys::ENTER)?;
+    shell.wait_for("contents")
+}
+
+#[test]
+fn tab_completes_a_directory() -> io::Result<()> {
+    let mut shell = start(shell().file("subdir/inner.txt", ""))?;
+    shell.send("cd sub")?;
+    shell.send(keys::TAB)?;
+    shell.wait_for_line("test$ cd subdir/")
+}
+
+#[test]
+fn scripts_complete_arguments() -> io::Result<()> {
+    let script = "complete(\"deploy\", |words, word| [\"staging\", \"production\"]);\n";
+    let mut shell = start(
+        shell()
+            .config("[scripts]\nfiles = [\"deploy.rhai\"]\n")
+            .file("deploy.rhai", script),
+    )?;
+    shell.send("deploy pro")?;
+    shell.send(keys::TAB)?;
+    shell.wait_for_line("test$ deploy production")
+}
+
+#[test]
+fn pipelines_pass_output_along() -> io::Result<()> {
+    let mut shell = start(shell())?;
+    shell.run("echo hello | tr a-z A-Z")?;
+    shell.wait_for("HELLO")
+}
+
+#[test]
+fn and_stops_after_a_failure() -> io::Result<()> {
+    let mut shell = start(shell())?;
+    shell.run("false && echo skipped")?;
+    wait_for_prompt_after(&mut shell, "false && echo skipped")?;
+    shell.run("true && echo ran")?;
+    shell.wait_for("\nran")?;
+    let text = shell.screen().text();
+    assert!(!text.lines().any(|line| line == "skipped"), "{}", text);
+    Ok(())
+}
+
+#[test]
+fn redirections_write_and_read_files() -> io::Result<()> {
+    let mut shell = start(shell().file("words", "b\na\n"))?;
+    shell.run("cat words | sort > \"sorted words\" && echo c >> \"sorted words\"")?;
+    wait_for_prompt_after(
+        &mut shell,
+        "cat words | sort > \"sorted words\" && echo c >> \"sorted words\"",
+    )?;
+    let sorted = fs::read_to_string(shell.work_dir().join("sorted words"))?;
+    assert_eq!(sorted, "a\nb\nc\n");
+    shell.run("tr a-z A-Z < \"sorted words\"")?;
+    shell.wait_for("A\nB\nC")
+}
+
+#[test]
+fn standard_error_can_follow_standard_output() -> io::Result<()> {
+    let mut shell = start(shell())?;
+    let line = "sh -c 'echo out; echo err >&2' > both 2>&1";
+    shell.run(line)?;
+    wait_for_prompt_after(&mut shell, line)?;
+    let both = fs::read_to_string(shell.work_dir().join("both"))?;
+    assert_eq!(both, "out\nerr\n");
+    shell.run("sh -c 'echo piped >&2' 2>&1 | tr a-z A-Z")?;
+    shell.wait_for("\nPIPED")
+}
+
+#[test]
+fn background_jobs_are_reported_when_done() -> io::Result<()> {
+    let mut shell = start(shell())?;
+    shell.run("sleep 1 &")?;
+    wait_for_prompt_after(&mut shell, "sleep 1 &")?;
+    shell.run("jobs")?;
+    shell.wait_for("[1]+  Running    sleep 1 &")?;
+    // Jobs that ended are reported at the next prompt, so keep asking for
+    // one until it says so.
+    let done = "[1]   Done       sleep 1";
+    for poll in 0..20 {
+        if shell.screen().text().contains(done) {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        let line = format!("echo {}", poll);
+        shell.run(&line)?;
+        wait_for_prompt_after(&mut shell, &line)?;
+    }
+    shell.wait_for(done)
+}
+
+#[test]
+fn ctrl_z_stops_a_job_and_fg_resumes_it() -> io::Result<()> {
+    let mut shell = start(shell())?;
+    // Typing only once the shell has stopped reading keys.
+    shell.run("echo reading && cat")?;
+    shell.wait_for("\nreading")?;
+    shell.send("one\r")?;
+    shell.wait_for("one\none")?;
+    shell.send(keys::CTRL_Z)?;
+    shell.wait_for("[1]+  Stopped    echo reading && cat")?;
+    shell.run("fg")?;
+    shell.wait_for("fg\necho reading && cat")?;
+    shell.send("two\r")?;
+    shell.wait_for("two\ntwo")?;
+    shell.send(keys::CTRL_C)?;
+    shell.run("jobs && echo listed")?;
+    shell.wait_for("\nlisted")?;
+    let text = shell.screen().text();
+    assert!(!text.contains("Running"), "{}", text);
+    Ok(())
+}
+
+#[test]
+fn up_and_down_walk_the_history() -> io::Result<()> {
+    let mut shell = start(shell().history(&["echo first", "echo second"]))?;
+    shell.send(keys::UP)?;
+    shell.wait_for_line("test$ echo second")?;
+    shell.send(keys::UP)?;
+    shell.wait_for_line("test$ echo first")?;
+    shell.send(keys::DOWN)?;
+    shell.wait_for_line("test$ echo second")
+}
+
+#[test]
+fn ctrl_r_finds_an_older_command() -> io::Result<()> {
+    let mut shell = start(shell().history(&["echo alpha", "echo beta"]))?;
+    shell.send(keys::CTRL_R)?;
+    shell.send("alp")?;
+    shell.wait_for("echo alpha")?;
+    shell.send(keys::ENTER)?;
+    shell.wait_for("\nalpha")
+}
+
+#[test]
+fn commands_are_saved_to_the_history_file() -> io::Result<()> {
+    let mut shell = start(shell())?;
+    shell.run("echo saved")?;
+    wait_for_prompt_after(&mut shell, "echo saved")?;
+    let history = fs::read_to_string(shell.home().join(".local/state/a-shell/history"))?;
+    assert!(
+        history.lines().any(|line| line == "echo saved"),
+        "{}",
+        history
+    );
+    Ok(())
+}
+
+#[test]
+fn the_history_file_drops_duplicates_and_old_commands() -> io::Result<()> {
+    let config = "[history]\ncontrol = [\"erasedups\"]\nfile_size = 3\n";
+    let mut shell = start(
+        shell()
+            .config(config)
+            .history(&["echo one", "echo two", "echo three"]),
+    )?;
+    shell.run("echo one")?;
+    wait_for_prompt_after(&mut shell, "echo one")?;
+    shell.run("echo four")?;
+    wait_for_prompt_after(&mut shell, "echo four")?;
+    let history = fs::read_to_string(shell.home().join(".local/state/a-shell/history"))?;
+    let commands = history
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        commands,
+        ["echo three", "echo one", "echo four"],
+        "{}",
+        history
+    );
+    Ok(())
+}
+
+#[test]
+fn ctrl_d_exits_with_the_last_status() -> io::Result<()> {
+    let mut shell = start(shell())?;
+    shell.run("false")?;
+    wait_for_prompt_after(&mut shell, "false")?;
+    shell.send(keys::CTRL_D)?;
+    assert_eq!(shell.wait()?, 1);
+    Ok(())
+}
+
+#[test]
+fn dash_c_exits_with_the_command_status() -> io::Result<()> {
+    assert_eq!(shell().arg("-c").arg("true").spawn()?.wait()?, 0);
+    assert_eq!(shell().arg("-c").arg("false").spawn()?.wait()?, 1);
+    Ok(())
+}
+
+#[test]
+fn unknown_commands_exit_127_with_a_hint() -> io::Result<()> {
+    let mut shell = shell().arg("-c").arg("no-such-command-here").spawn()?;
+    shell.wait_for("Command not found: no-such-command-here")?;
+    shell.wait_for("hint: check the spelling")?;
+    assert_eq!(shell.wait()?, 127);
+    Ok(())
+}